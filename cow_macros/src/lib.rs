@@ -31,6 +31,12 @@ pub fn cow_task(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
     let mut tasks_type = vec![];
 
+    // a Trigger<E> parameter makes the function an observer of E rather than a task
+    let mut event_type = None;
+
+    // the QueryState of each query parameter, kept in the TaskState between runs
+    let mut query_states = vec![];
+
 
     for input_arg in input_fn.sig.inputs.iter() {
         if let FnArg::Typed(pat_type) = input_arg {
@@ -44,20 +50,44 @@ pub fn cow_task(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 if let Some(last_segment) = type_path.path.segments.last() {
                     if let syn::PathArguments::AngleBracketed(angle_bracketed_param) = &last_segment.arguments {
                         if let Some(syn::GenericArgument::Type(generic_type)) = angle_bracketed_param.args.first() {
+                            // an optional second argument filters the queried entities
+                            let filter_type = match angle_bracketed_param.args.iter().nth(1) {
+                                Some(syn::GenericArgument::Type(filter_type)) => quote!(#filter_type),
+                                _ => quote!(()),
+                            };
+
                             // Convert the generic type to a string and push it to template_types
                             if actual_path == "Comps" {
                                 templates.push(generic_type);
-                                tasks_type.push(quote!(cow_ecs::schedule::task_type::TaskType::Comp(std::any::TypeId::of::<#generic_type>())));
-                                args_call.push(quote!(Comps::new(archs.fetch_info::<#generic_type>())));
+                                tasks_type.push(quote!([cow_ecs::schedule::task_type::TaskType::Comp(std::any::TypeId::of::<#generic_type>())]));
+                                // each parameter keeps the archetypes it matched between runs
+                                let state = syn::Index::from(query_states.len());
+                                query_states.push(quote!(cow_ecs::archetype::query_state::QueryState<&#generic_type, #filter_type>));
+                                args_call.push(quote!(Comps::new(archs.fetch_info_cached::<#generic_type, #filter_type>(&mut query_states.#state))));
                             } else if actual_path == "CompsMut" {
                                 templates.push(generic_type);
-                                tasks_type.push(quote!(cow_ecs::schedule::task_type::TaskType::CompMut(std::any::TypeId::of::<#generic_type>())));
-                                args_call.push(quote!(CompsMut::new(archs.fetch_info_mut::<#generic_type>())));
+                                tasks_type.push(quote!([cow_ecs::schedule::task_type::TaskType::CompMut(std::any::TypeId::of::<#generic_type>())]));
+                                let state = syn::Index::from(query_states.len());
+                                query_states.push(quote!(cow_ecs::archetype::query_state::QueryState<&mut #generic_type, #filter_type>));
+                                args_call.push(quote!(CompsMut::new(archs.fetch_info_cached_mut::<#generic_type, #filter_type>(&mut query_states.#state))));
+                            } else if actual_path == "Query" {
+                                tasks_type.push(quote!(<#generic_type as cow_ecs::archetype::query_data::QueryData>::task_types()));
+                                let state = syn::Index::from(query_states.len());
+                                query_states.push(quote!(cow_ecs::archetype::query_state::QueryState<#generic_type, #filter_type>));
+                                args_call.push(quote!(Query::new(archs.fetch_query_cached::<#generic_type, #filter_type>(&mut query_states.#state))));
+                            } else if actual_path == "Trigger" {
+                                if event_type.is_some() {
+                                    return syn::Error::new_spanned(&pat_type.ty, "an observer takes a single Trigger")
+                                        .to_compile_error()
+                                        .into();
+                                }
+                                event_type = Some(generic_type);
+                                args_call.push(quote!(trigger));
                             } else if actual_path == "Res" {
-                                tasks_type.push(quote!(cow_ecs::schedule::task_type::TaskType::Res(std::any::TypeId::of::<#generic_type>())));
+                                tasks_type.push(quote!([cow_ecs::schedule::task_type::TaskType::Res(std::any::TypeId::of::<#generic_type>())]));
                                 args_call.push(quote!(Res::new(&res.query::<#generic_type>().unwrap().resource().read().unwrap())));
                             } else if actual_path == "ResMut" {
-                                tasks_type.push(quote!(cow_ecs::schedule::task_type::TaskType::ResMut(std::any::TypeId::of::<#generic_type>())));
+                                tasks_type.push(quote!([cow_ecs::schedule::task_type::TaskType::ResMut(std::any::TypeId::of::<#generic_type>())]));
                                 args_call.push(quote!(ResMut::new(&mut res.query::<#generic_type>().unwrap().resource().write().unwrap())));
                             } else {
                                 return syn::Error::new_spanned(&input_fn.sig.output, "cow_task expect arguments to be &Comps<T>,&Res<T> or &Entities, not ".to_owned() + &actual_path)
@@ -70,7 +100,7 @@ pub fn cow_task(_attr: TokenStream, item: TokenStream) -> TokenStream {
                                 .into();
                         }
                     } else if actual_path == "Commands" {
-                        tasks_type.push(quote!(cow_ecs::schedule::task_type::TaskType::Commands()));
+                        tasks_type.push(quote!([cow_ecs::schedule::task_type::TaskType::Commands()]));
                         args_call.push(quote!(cow_ecs::comps::Commands::new(commands)));
                     } else {
                         return syn::Error::new_spanned(&input_fn.sig.output, "cow_task expect arguments to be &Comps<T>, &Res<T> or &Entities, not ")
@@ -94,6 +124,43 @@ pub fn cow_task(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let fn_name = &input_fn.sig.ident;
     let fn_name_str = fn_name.to_string();
 
+    if let Some(event_type) = event_type {
+        let expanded = quote::quote! {
+
+            #[allow(non_camel_case_types)]
+            struct #fn_name;
+
+            impl cow_ecs::observer::observer::Observer<#event_type> for #fn_name {
+                fn name(&self) -> String {
+                    #fn_name_str.to_string()
+                }
+
+                fn run(&self, trigger: cow_ecs::observer::observer::Trigger<'_, #event_type>,
+                    archs: &mut cow_ecs::archetype::archetype_manager::ArchetypeManager,
+                    commands : &mut cow_ecs::commands::EntityCommands<'_>,
+                    res : &cow_ecs::resource::res_manager::ResManager,
+                    state : &mut cow_ecs::schedule::task_state::TaskState) {
+                    #input_fn
+
+                    #[allow(unused_variables)]
+                    let query_states = state.get_or_default::<(#(#query_states,)*)>();
+
+                    use cow_ecs::comps::Comps;
+                    use cow_ecs::comps::CompsMut;
+                    use cow_ecs::comps::Query;
+                    use cow_ecs::comps::Res;
+                    use cow_ecs::comps::ResMut;
+                    use cow_ecs::comps::Commands;
+                    use cow_ecs::observer::observer::Trigger;
+
+                    #fn_name(#(#args_call),*);
+                }
+            }
+        };
+
+        return TokenStream::from(expanded);
+    }
+
     // Generate the implementation
     let expanded = quote::quote! {
 
@@ -106,17 +173,23 @@ pub fn cow_task(_attr: TokenStream, item: TokenStream) -> TokenStream {
             }
 
             fn arguments(&self) -> Vec<cow_ecs::schedule::task_type::TaskType> {
-                vec![#(#tasks_type),*]
+                let mut arguments = vec![];
+                #(arguments.extend(#tasks_type);)*
+                arguments
             }
 
             fn run(&self, archs: &mut cow_ecs::archetype::archetype_manager::ArchetypeManager,
-                commands : &mut cow_ecs::commands::EntityCommands,
-                res : &cow_ecs::resource::res_manager::ResManager) {
+                commands : &mut cow_ecs::commands::EntityCommands<'_>,
+                res : &cow_ecs::resource::res_manager::ResManager,
+                state : &mut cow_ecs::schedule::task_state::TaskState) {
                 #input_fn
 
-                use cow_ecs::component::comp_storage::CompStorage;
+                #[allow(unused_variables)]
+                let query_states = state.get_or_default::<(#(#query_states,)*)>();
+
                 use cow_ecs::comps::Comps;
                 use cow_ecs::comps::CompsMut;
+                use cow_ecs::comps::Query;
                 use cow_ecs::comps::Res;
                 use cow_ecs::comps::ResMut;
                 use cow_ecs::comps::Commands;
@@ -158,7 +231,7 @@ fn check_fn_args_for_generics(func: &ItemFn) -> bool {
     return true;
 }
 
-#[proc_macro_derive(Component)]
+#[proc_macro_derive(Component, attributes(component))]
 pub fn cow_component_derive(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as DeriveInput);
@@ -166,6 +239,19 @@ pub fn cow_component_derive(input: TokenStream) -> TokenStream {
     // Used for the implementation
     let name = &input.ident;
 
+    let attrs = match parse_component_attrs(&input) {
+        Ok(attrs) => attrs,
+        Err(error) => return error.to_compile_error().into(),
+    };
+    let storage = attrs.storage;
+    let hook = |hook: &Option<syn::Path>| match hook {
+        Some(path) => quote!(Some(#path)),
+        None => quote!(None),
+    };
+    let (on_add, on_insert) = (hook(&attrs.on_add), hook(&attrs.on_insert));
+    let (on_replace, on_remove) = (hook(&attrs.on_replace), hook(&attrs.on_remove));
+    let required = &attrs.required;
+
     // Generate the implementation
     let expanded = quote! {
         impl cow_ecs::component::component::ComponentAny for #name {
@@ -175,13 +261,120 @@ pub fn cow_component_derive(input: TokenStream) -> TokenStream {
             }
 
         }
-        impl cow_ecs::component::component::Component for #name {}
+        impl cow_ecs::component::component::Component for #name {
+            const STORAGE: cow_ecs::component::component::StorageType = #storage;
+
+            fn clone_fn() -> Option<cow_ecs::component::clone::CloneFn> {
+                use cow_ecs::component::clone::{CloneFnOf, NoCloneFn};
+                (&&cow_ecs::component::clone::CloneProbe::<Self>::new()).clone_fn()
+            }
+
+            fn hooks() -> cow_ecs::component::hooks::ComponentHooks {
+                cow_ecs::component::hooks::ComponentHooks {
+                    on_add: #on_add,
+                    on_insert: #on_insert,
+                    on_replace: #on_replace,
+                    on_remove: #on_remove,
+                }
+            }
+
+            fn required() -> Vec<cow_ecs::component::required::RequiredComponent> {
+                vec![#(cow_ecs::component::required::RequiredComponent::of::<#required>()),*]
+            }
+        }
     };
 
     // Hand the output tokens back to the compiler
     TokenStream::from(expanded)
 }
 
+struct ComponentAttrs {
+    storage: proc_macro2::TokenStream,
+    on_add: Option<syn::Path>,
+    on_insert: Option<syn::Path>,
+    on_replace: Option<syn::Path>,
+    on_remove: Option<syn::Path>,
+    required: Vec<syn::Path>,
+}
+
+// reads #[component(storage = "table" | "sparse")], table being the default,
+// the hooks, e.g. #[component(on_add = track, on_remove = untrack)]
+// and the required components, e.g. #[component(requires(Transform, Velocity))]
+fn parse_component_attrs(input: &DeriveInput) -> syn::Result<ComponentAttrs> {
+    let mut attrs = ComponentAttrs {
+        storage: quote!(cow_ecs::component::component::StorageType::Table),
+        on_add: None,
+        on_insert: None,
+        on_replace: None,
+        on_remove: None,
+        required: vec![],
+    };
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("component")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("storage") {
+                let value: syn::LitStr = meta.value()?.parse()?;
+                attrs.storage = match value.value().as_str() {
+                    "table" => quote!(cow_ecs::component::component::StorageType::Table),
+                    "sparse" => quote!(cow_ecs::component::component::StorageType::Sparse),
+                    _ => return Err(syn::Error::new_spanned(&value, "component storage must be \"table\" or \"sparse\"")),
+                };
+                Ok(())
+            } else if meta.path.is_ident("requires") {
+                meta.parse_nested_meta(|required| {
+                    attrs.required.push(required.path);
+                    Ok(())
+                })
+            } else if meta.path.is_ident("on_add") {
+                attrs.on_add = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("on_insert") {
+                attrs.on_insert = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("on_replace") {
+                attrs.on_replace = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("on_remove") {
+                attrs.on_remove = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported component attribute"))
+            }
+        })?;
+    }
+
+    Ok(attrs)
+}
+
+#[proc_macro_derive(Event, attributes(event))]
+pub fn cow_event_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    // #[event(propagate)] sends the event up the hierarchy of the target
+    let mut propagate = false;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("event")) {
+        let parsed = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("propagate") {
+                propagate = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported event attribute"))
+            }
+        });
+        if let Err(error) = parsed {
+            return error.to_compile_error().into();
+        }
+    }
+
+    let expanded = quote! {
+        impl cow_ecs::observer::observer::Event for #name {
+            const PROPAGATE: bool = #propagate;
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
 #[proc_macro_derive(Resource)]
 pub fn cow_resource_derive(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
@@ -192,9 +385,226 @@ pub fn cow_resource_derive(input: TokenStream) -> TokenStream {
 
     // Generate the implementation
     let expanded = quote! {
-        impl cow_ecs::resource::resource::Resource for #name { }
+        impl cow_ecs::resource::resource::Resource for #name {
+            fn clone_fn() -> Option<cow_ecs::component::clone::CloneFn> {
+                use cow_ecs::component::clone::{CloneFnOf, NoCloneFn};
+                (&&cow_ecs::component::clone::CloneProbe::<Self>::new()).clone_fn()
+            }
+        }
     };
 
     // Hand the output tokens back to the compiler
     TokenStream::from(expanded)
-}
\ No newline at end of file
+}
+// Builds the struct from its scene value, field by field.
+// A field marked #[scene(default)] can be left out of the scene,
+// one marked #[scene(entity)] takes an entity of the scene, e.g. Target(@enemy).
+#[proc_macro_derive(FromScene, attributes(scene))]
+pub fn cow_from_scene_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match from_scene_body(&input) {
+        Ok(body) => {
+            let name = &input.ident;
+            let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+            TokenStream::from(quote! {
+                impl #impl_generics cow_ecs::scene::scene_value::FromScene for #name #ty_generics #where_clause {
+                    fn from_scene(value: &cow_ecs::scene::scene_value::SceneValue,
+                                  context: &cow_ecs::scene::scene_value::SceneContext) -> Result<Self, cow_ecs::scene::scene_value::SceneError> {
+                        #body
+                    }
+                }
+            })
+        }
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn from_scene_body(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let syn::Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(&input.ident, "FromScene can only be derived for structs"));
+    };
+
+    match &data.fields {
+        syn::Fields::Named(fields) => {
+            let mut names = vec![];
+            let mut values = vec![];
+            for field in fields.named.iter() {
+                let ident = field.ident.as_ref().unwrap();
+                let name = ident.to_string();
+                let attrs = scene_attrs(field)?;
+                let from_scene = from_scene_fn(&attrs);
+                let missing = if attrs.default {
+                    quote!(Default::default())
+                } else {
+                    quote!(return Err(cow_ecs::scene::scene_value::SceneError::Value(format!("missing field {}", #name))))
+                };
+                values.push(quote! {
+                    #ident: match cow_ecs::scene::scene_value::scene_field(fields, #name) {
+                        Some(value) => #from_scene(value, context)
+                            .map_err(|error| error.in_field(#name))?,
+                        None => #missing,
+                    }
+                });
+                names.push(name);
+            }
+            Ok(quote! {
+                let fields = value.fields()?.named(&[#(#names),*])?;
+                Ok(Self { #(#values),* })
+            })
+        }
+        syn::Fields::Unnamed(fields) => {
+            let len = fields.unnamed.len();
+            let mut values = vec![];
+            for (index, field) in fields.unnamed.iter().enumerate() {
+                let attrs = scene_attrs(field)?;
+                if attrs.default {
+                    return Err(syn::Error::new_spanned(field, "only named fields can have a default"));
+                }
+                let from_scene = from_scene_fn(&attrs);
+                let name = index.to_string();
+                values.push(quote! {
+                    #from_scene(&values[#index], context)
+                        .map_err(|error| error.in_field(#name))?
+                });
+            }
+            Ok(quote! {
+                let values = value.fields()?.tuple(#len)?;
+                Ok(Self(#(#values),*))
+            })
+        }
+        syn::Fields::Unit => Ok(quote! {
+            let _ = context;
+            value.fields()?.unit()?;
+            Ok(Self)
+        }),
+    }
+}
+
+#[derive(Default)]
+struct SceneAttrs {
+    default: bool,
+    entity: bool,
+}
+
+// reads #[scene(default)] and #[scene(entity)]
+fn scene_attrs(field: &syn::Field) -> syn::Result<SceneAttrs> {
+    let mut attrs = SceneAttrs::default();
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("scene")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                attrs.default = true;
+                Ok(())
+            } else if meta.path.is_ident("entity") {
+                attrs.entity = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported scene attribute"))
+            }
+        })?;
+    }
+    Ok(attrs)
+}
+
+fn from_scene_fn(attrs: &SceneAttrs) -> proc_macro2::TokenStream {
+    if attrs.entity {
+        quote!(cow_ecs::scene::scene_value::FromSceneEntity::from_scene_entity)
+    } else {
+        quote!(cow_ecs::scene::scene_value::FromScene::from_scene)
+    }
+}
+
+// Gives access to the fields of the struct by name, see cow_ecs::reflect::reflect::Reflect.
+#[proc_macro_derive(Reflect)]
+pub fn cow_reflect_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let syn::Data::Struct(data) = &input.data else {
+        return syn::Error::new_spanned(&input.ident, "Reflect can only be derived for structs")
+            .to_compile_error()
+            .into();
+    };
+
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    // tuple struct fields are named after their index
+    let members: Vec<(syn::Member, String, String)> = data.fields.iter().enumerate().map(|(index, field)| {
+        let (member, field_name) = match &field.ident {
+            Some(ident) => (syn::Member::Named(ident.clone()), ident.to_string()),
+            None => (syn::Member::Unnamed(syn::Index::from(index)), index.to_string()),
+        };
+        (member, field_name, type_string(&field.ty))
+    }).collect();
+
+    let infos = members.iter().map(|(_, field_name, type_name)| quote!(cow_ecs::reflect::reflect::FieldInfo::new(#field_name, #type_name)));
+    let fields = members.iter().map(|(member, field_name, _)| quote!(#field_name => Some(&self.#member)));
+    let fields_mut = members.iter().map(|(member, field_name, _)| quote!(#field_name => Some(&mut self.#member)));
+    let fmt = match &data.fields {
+        syn::Fields::Named(_) => {
+            let entries = members.iter().map(|(member, field_name, _)| quote!(.field(#field_name, &(&self.#member as &dyn cow_ecs::reflect::reflect::Reflect))));
+            quote!(f.debug_struct(#name_str) #(#entries)* .finish())
+        }
+        syn::Fields::Unnamed(_) => {
+            let entries = members.iter().map(|(member, _, _)| quote!(.field(&(&self.#member as &dyn cow_ecs::reflect::reflect::Reflect))));
+            quote!(f.debug_tuple(#name_str) #(#entries)* .finish())
+        }
+        syn::Fields::Unit => quote!(f.write_str(#name_str)),
+    };
+
+    let expanded = quote! {
+        impl #impl_generics cow_ecs::reflect::reflect::Reflect for #name #ty_generics #where_clause {
+            fn field_infos() -> &'static [cow_ecs::reflect::reflect::FieldInfo] where Self: Sized {
+                const FIELDS: &[cow_ecs::reflect::reflect::FieldInfo] = &[#(#infos),*];
+                FIELDS
+            }
+
+            fn fields(&self) -> &'static [cow_ecs::reflect::reflect::FieldInfo] {
+                <Self as cow_ecs::reflect::reflect::Reflect>::field_infos()
+            }
+
+            fn field(&self, name: &str) -> Option<&dyn cow_ecs::reflect::reflect::Reflect> {
+                match name {
+                    #(#fields,)*
+                    _ => None,
+                }
+            }
+
+            fn field_mut(&mut self, name: &str) -> Option<&mut dyn cow_ecs::reflect::reflect::Reflect> {
+                match name {
+                    #(#fields_mut,)*
+                    _ => None,
+                }
+            }
+
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+                self
+            }
+
+            fn reflect_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                #fmt
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+// The type as written, without the spaces quote puts around punctuation, e.g. Vec<String> or [f32; 3]
+fn type_string(ty: &Type) -> String {
+    let tokens = quote!(#ty).to_string();
+    let chars: Vec<char> = tokens.chars().collect();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    chars.iter().enumerate()
+        .filter(|(i, c)| {
+            **c != ' ' || (*i > 0 && *i + 1 < chars.len()
+                && (matches!(chars[i - 1], ';' | ',') || (is_word(chars[i - 1]) && is_word(chars[i + 1]))))
+        })
+        .map(|(_, c)| *c)
+        .collect()
+}
@@ -1,112 +1,207 @@
-use std::any::{TypeId};
+use std::cell::Cell;
 use std::collections::HashMap;
-use crate::component::comp_storage::{CompStorage, CompStorageAny};
-use crate::component::component::Component;
+use std::sync::Arc;
+use crate::component::column::Column;
+use crate::component::component::{is_tag, tag_slice, tag_slice_mut, Component};
+use crate::component::registry::{ComponentId, ComponentRegistry};
+use crate::component::tick::{ComponentTicks, Tick};
 use crate::entity::entity::EntityId;
 
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct ArchetypeIndex {
-    types: Vec<TypeId>,
+    // sorted ids of the components
+    components: Vec<ComponentId>,
+}
+
+impl Default for ArchetypeIndex {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ArchetypeIndex {
     pub fn new() -> Self {
-        Self { types: vec![] }
+        Self { components: vec![] }
     }
 
-    pub fn from_slice(comp_types: &[TypeId]) -> Self {
-        Self { types: comp_types.to_vec() }
+    pub fn from_slice(components: &[ComponentId]) -> Self {
+        let mut components = components.to_vec();
+        components.sort_unstable();
+        components.dedup();
+        Self { components }
     }
 
-    pub fn add<T: Component + 'static>(&mut self) -> bool {
-        let type_id = TypeId::of::<T>();
+    pub fn add(&mut self, component_id: ComponentId) -> bool {
         // returns true if it added something
-        return match self.types.binary_search(&type_id) {
+        match self.components.binary_search(&component_id) {
             Ok(_) => { false } // don't add it if it's already in there
             Err(index) => {
-                self.types.insert(index, type_id);
+                self.components.insert(index, component_id);
                 true
             }
-        };
+        }
     }
 
-    pub fn contains<T: Component + 'static>(&self) -> bool {
-        self.types.contains(&TypeId::of::<T>())
+    pub fn contains(&self, component_id: ComponentId) -> bool {
+        self.components.binary_search(&component_id).is_ok()
     }
 
-    pub fn remove<T: Component + 'static>(&mut self) {
-        let type_id = TypeId::of::<T>();
-        self.types.retain(|&x| x != type_id);
+    pub fn remove(&mut self, component_id: ComponentId) {
+        self.components.retain(|&x| x != component_id);
     }
 
-    pub fn types(&self) -> &Vec<TypeId> {
-        &self.types
+    pub fn components(&self) -> &Vec<ComponentId> {
+        &self.components
     }
 
     pub fn len(&self) -> usize {
-        self.types.len()
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
     }
 }
 
 
+// The rows and columns are shared with the snapshots of the world,
+// they are copied the first time the archetype writes to them after a snapshot.
 pub struct Archetype {
     index: ArchetypeIndex,
     // entity to archetype id
-    entities: HashMap<EntityId, usize>,
-    indices: Vec<EntityId>,
-    components: Vec<Box<dyn CompStorageAny>>,
+    entities: Arc<HashMap<EntityId, usize>>,
+    indices: Arc<Vec<EntityId>>,
+    // the tick each entity moved in the archetype at, aligned with indices
+    moved: Arc<Vec<Tick>>,
+    columns: Vec<Arc<Column>>,
+    // component id to its column, tags don't have one
+    column_lookup: Vec<Option<usize>>,
 }
 
 impl Archetype {
-    pub fn new(index: ArchetypeIndex) -> Self {
-        Self { index, entities: HashMap::new(), indices: vec![], components: vec![] }
+    pub fn new(index: ArchetypeIndex, components: &ComponentRegistry) -> Self {
+        let mut columns = vec![];
+        let mut column_lookup = vec![];
+        for component_id in index.components() {
+            let info = components.info(*component_id);
+            if info.is_tag() {
+                continue;
+            }
+
+            if column_lookup.len() <= *component_id {
+                column_lookup.resize(*component_id + 1, None);
+            }
+            column_lookup[*component_id] = Some(columns.len());
+            columns.push(Arc::new(Column::new(info.descriptor())));
+        }
+
+        Self { index, entities: Arc::default(), indices: Arc::default(), moved: Arc::default(), columns, column_lookup }
+    }
+
+    // Shares the rows and the columns that can be cloned, the others are left out of the copy
+    // and of its index, so no query matches them.
+    pub fn snapshot(&self) -> Self {
+        let mut index = self.index.clone();
+        let mut columns = vec![];
+        let mut column_lookup = vec![None; self.column_lookup.len()];
+        for (component_id, column_index) in self.column_lookup.iter().enumerate() {
+            if let Some(column_index) = column_index {
+                let column = &self.columns[*column_index];
+                if column.is_cloneable() {
+                    column_lookup[component_id] = Some(columns.len());
+                    columns.push(column.clone());
+                } else {
+                    index.remove(component_id);
+                }
+            }
+        }
+
+        Self {
+            index,
+            entities: self.entities.clone(),
+            indices: self.indices.clone(),
+            moved: self.moved.clone(),
+            columns,
+            column_lookup,
+        }
+    }
+
+    // Shares every row and column, the caller makes sure they can all be cloned.
+    pub fn fork(&self) -> Self {
+        Self {
+            index: self.index.clone(),
+            entities: self.entities.clone(),
+            indices: self.indices.clone(),
+            moved: self.moved.clone(),
+            columns: self.columns.clone(),
+            column_lookup: self.column_lookup.clone(),
+        }
     }
 
     pub fn index(&self) -> &ArchetypeIndex {
         &self.index
     }
 
-    pub fn insert_comp_storage<T: Component + 'static>(&mut self, comp: T) {
-        let mut storage = CompStorage::<T>::new();
-        storage.add(comp);
-        self.components.push(Box::new(storage))
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        self.entities.contains_key(&entity_id)
     }
 
-    pub fn remove_comp_storage<T: Component + 'static>(&mut self) {
-        for (i, comp) in self.components.iter().enumerate() {
-            if comp.contained_type() == TypeId::of::<T>() {
-                self.components.remove(i);
-                return;
-            }
-        }
+    pub fn row(&self, entity_id: EntityId) -> Option<usize> {
+        self.entities.get(&entity_id).copied()
     }
 
-    pub fn add_without_comp(&mut self, entity_id: EntityId) {
-        self.entities.insert(entity_id, self.indices.len());
-        self.indices.push(entity_id);
+    pub(crate) fn rows(&self) -> &HashMap<EntityId, usize> {
+        &self.entities
     }
 
-    pub fn add<T: Component + 'static>(&mut self, comp: T) {
-        let type_id = TypeId::of::<T>();
+    pub fn column(&self, component_id: ComponentId) -> Option<&Column> {
+        let column_index = self.column_lookup.get(component_id).copied().flatten()?;
+        Some(&self.columns[column_index])
+    }
 
-        for storage in self.components.iter_mut() {
-            if storage.as_ref().contained_type() == type_id {
-                if let Some(storage_casted) = storage.as_any_mut().downcast_mut::<CompStorage<T>>() {
-                    storage_casted.add(comp);
-                    return;
-                }
-            }
+    pub fn column_mut(&mut self, component_id: ComponentId) -> Option<&mut Column> {
+        let column_index = self.column_lookup.get(component_id).copied().flatten()?;
+        Some(unshare_column(&mut self.columns[column_index]))
+    }
+
+    // Adds a row for the entity moved in at tick, the caller fills the columns after.
+    pub fn add_without_comp(&mut self, entity_id: EntityId, tick: Tick) {
+        Arc::make_mut(&mut self.entities).insert(entity_id, self.indices.len());
+        Arc::make_mut(&mut self.indices).push(entity_id);
+        Arc::make_mut(&mut self.moved).push(tick);
+    }
+
+    // Moves the component at the end of its column, tags are only part of the index.
+    pub(crate) unsafe fn add(&mut self, component_id: ComponentId, comp: *const u8, tick: Tick) {
+        if let Some(column) = self.column_mut(component_id) {
+            column.push(comp, tick);
         }
     }
 
-    pub fn update<T: Component + 'static>(&mut self, entity_id: EntityId, comp: T) {
-        let type_id = TypeId::of::<T>();
-        for storage in self.components.iter_mut() {
-            if storage.as_ref().contained_type() == type_id {
-                if let Some(storage_casted) = storage.as_any_mut().downcast_mut::<CompStorage<T>>() {
-                    storage_casted.update(self.entities[&entity_id], comp);
-                    return;
-                }
+    pub(crate) unsafe fn update(&mut self, entity_id: EntityId, component_id: ComponentId, comp: *const u8, tick: Tick) {
+        let row = self.entities[&entity_id];
+        if let Some(column) = self.column_mut(component_id) {
+            column.replace(row, comp, tick);
+        }
+    }
+
+    // The ticks of a component of the entity, a tag counts as added when the entity moved in.
+    pub fn ticks(&self, entity_id: EntityId, component_id: ComponentId) -> Option<ComponentTicks> {
+        let row = self.row(entity_id)?;
+        match self.column(component_id) {
+            Some(column) => Some(column.ticks(row)),
+            None => self.index.contains(component_id).then(|| ComponentTicks::new(self.moved[row])),
+        }
+    }
+
+    pub fn moved_tick(&self, row: usize) -> Tick {
+        self.moved[row]
+    }
+
+    pub fn set_changed(&mut self, entity_id: EntityId, component_id: ComponentId, tick: Tick) {
+        if let Some(row) = self.row(entity_id) {
+            if let Some(column) = self.column_mut(component_id) {
+                column.set_changed(row, tick);
             }
         }
     }
@@ -114,45 +209,48 @@ impl Archetype {
     pub fn remove(&mut self, entity_id: EntityId) {
         // To avoid a potentially costly memory copy, we swap the last element and the element to delete
         // and then do a simple efficient pop
-        let index_to_switch = self.entities[&entity_id];
-        let last_entity = self.indices.last().unwrap();
-        self.entities.insert(*last_entity, index_to_switch);
-        self.entities.remove(&entity_id);
-        let last_index = self.indices.len() - 1;
-        self.indices.swap(index_to_switch, last_index);
-        self.indices.pop();
-    }
-
-    pub fn query<T: Component + 'static>(&self, entity_id: EntityId) -> Option<&T> {
-        if let Some(entity_index) = self.entities.get(&entity_id) {
-            for storage in &self.components {
-                if let Some(comp_cast) = storage.as_any().downcast_ref::<CompStorage<T>>() {
-                    return Some(comp_cast.get(*entity_index));
-                }
+        let entities = Arc::make_mut(&mut self.entities);
+        let indices = Arc::make_mut(&mut self.indices);
+        Arc::make_mut(&mut self.moved).swap_remove(entities[&entity_id]);
+        let index_to_switch = entities[&entity_id];
+        let last_entity = indices.last().unwrap();
+        entities.insert(*last_entity, index_to_switch);
+        entities.remove(&entity_id);
+        let last_index = indices.len() - 1;
+        indices.swap(index_to_switch, last_index);
+        indices.pop();
+    }
+
+    pub fn release(&mut self, entity_id: EntityId) {
+        if let Some(entity_index) = self.entities.get(&entity_id).copied() {
+            for column in self.columns.iter_mut() {
+                unshare_column(column).swap_remove(entity_index);
             }
+            self.remove(entity_id);
         }
-        None
     }
 
-    pub fn duplicate(&self, new_index: ArchetypeIndex) -> Archetype {
-        let mut new_arch = Self::new(new_index);
-        for storage in &self.components {
-            new_arch.components.push(storage.duplicate());
-        }
-        new_arch
+    pub fn query<T: Component + 'static>(&self, entity_id: EntityId, component_id: ComponentId) -> Option<&T> {
+        let entity_index = *self.entities.get(&entity_id)?;
+        self.storage::<T>(component_id).map(|storage| &storage[entity_index])
     }
 
-    pub fn transfer(&mut self, other: &mut Self, entity_id: EntityId) {
-        other.entities.insert(entity_id, other.indices.len());
-        other.indices.push(entity_id);
-        if let Some(entity_index) = self.entities.get(&entity_id) {
-            for left_comp in self.components.iter_mut() {
-                for right_comp in other.components.iter_mut() {
-                    if left_comp.contained_type() == right_comp.contained_type() {
-                        left_comp.transfer(right_comp, *entity_index);
+    // Moves the entity and the components both archetypes have, the others are dropped.
+    pub fn transfer(&mut self, other: &mut Self, entity_id: EntityId, tick: Tick) {
+        if let Some(entity_index) = self.entities.get(&entity_id).copied() {
+            other.add_without_comp(entity_id, tick);
+            for (component_id, column_index) in self.column_lookup.iter().enumerate() {
+                if let Some(column_index) = column_index {
+                    let left_column = unshare_column(&mut self.columns[*column_index]);
+                    if let Some(right_column) = other.column_mut(component_id) {
+                        left_column.swap_remove_into(entity_index, right_column);
+                    } else {
+                        // the component was removed
+                        left_column.swap_remove(entity_index);
                     }
                 }
             }
+            self.remove(entity_id);
         }
     }
 
@@ -160,29 +258,132 @@ impl Archetype {
         &self.indices
     }
 
-    pub fn storage<T: Component + 'static>(&self) -> Option<&Vec<T>> {
-        let type_id = TypeId::of::<T>();
-        for component in &self.components {
-            if component.contained_type() == type_id {
-                if let Some(casted_comp) = component.as_any().downcast_ref::<CompStorage<T>>() {
-                    return Some(casted_comp.components());
-                }
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    // Frees the capacity the rows and columns don't use, the storages shared with a snapshot or a fork
+    // are left as they are since shrinking them would mean copying them.
+    pub fn shrink_to_fit(&mut self) {
+        if let Some(entities) = Arc::get_mut(&mut self.entities) {
+            entities.shrink_to_fit();
+        }
+        if let Some(indices) = Arc::get_mut(&mut self.indices) {
+            indices.shrink_to_fit();
+        }
+        if let Some(moved) = Arc::get_mut(&mut self.moved) {
+            moved.shrink_to_fit();
+        }
+        for column in self.columns.iter_mut() {
+            if let Some(column) = Arc::get_mut(column) {
+                column.shrink_to_fit();
             }
         }
+    }
 
-        None
+    // Approximate bytes allocated by the rows and columns, the entity map counts one control byte per slot.
+    // Storages shared with a snapshot are counted by both.
+    pub fn memory(&self) -> usize {
+        let entities = self.entities.capacity() * (std::mem::size_of::<(EntityId, usize)>() + 1);
+        let rows = self.indices.capacity() * std::mem::size_of::<EntityId>() + self.moved.capacity() * std::mem::size_of::<Tick>();
+        let columns: usize = self.columns.iter().map(|column| column.memory()).sum();
+        entities + rows + columns
     }
 
-    pub fn storage_mut<T: Component + 'static>(&mut self) -> Option<&mut Vec<T>> {
-        let type_id = TypeId::of::<T>();
-        for component in self.components.iter_mut() {
-            if component.contained_type() == type_id {
-                if let Some(casted_comp) = component.as_any_mut().downcast_mut::<CompStorage<T>>() {
-                    return Some(casted_comp.components_mut());
-                }
-            }
+    pub fn storage<T: Component + 'static>(&self, component_id: ComponentId) -> Option<&[T]> {
+        if is_tag::<T>() {
+            return self.index.contains(component_id).then(|| tag_slice::<T>(self.indices.len()));
+        }
+
+        self.column(component_id)?.slice::<T>()
+    }
+
+    pub fn storage_mut<T: Component + 'static>(&mut self, component_id: ComponentId) -> Option<&mut [T]> {
+        if is_tag::<T>() {
+            return self.index.contains(component_id).then(|| tag_slice_mut::<T>(self.indices.len()));
         }
 
-        None
+        self.column_mut(component_id)?.slice_mut::<T>()
     }
-}
\ No newline at end of file
+
+    // Same as storage_mut with the ticks of the rows, tags don't have any.
+    pub(crate) fn storage_and_ticks_mut<T: Component + 'static>(&mut self, component_id: ComponentId) -> Option<(&mut [T], &[Cell<ComponentTicks>])> {
+        if is_tag::<T>() {
+            return self.index.contains(component_id).then(|| (tag_slice_mut::<T>(self.indices.len()), &[][..]));
+        }
+
+        self.column_mut(component_id)?.slice_and_ticks_mut::<T>()
+    }
+}
+
+// Copies the column if a snapshot still shares it, only cloneable columns are ever shared.
+fn unshare_column(column: &mut Arc<Column>) -> &mut Column {
+    if Arc::get_mut(column).is_none() {
+        *column = Arc::new(column.try_clone().expect("only cloneable columns are shared"));
+    }
+    Arc::get_mut(column).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::cow_macros::Component;
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Tag;
+
+    #[derive(Component)]
+    struct Value(#[allow(dead_code)] u8);
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Component)]
+    struct DropMarker;
+
+    impl Drop for DropMarker {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn tags_are_queried_without_a_column() {
+        let mut world = World::new();
+        let tagged = world.create();
+        world.add(tagged, Tag);
+        let untagged = world.create();
+        world.add(untagged, Value(1));
+
+        let tag_id = world.archetypes().component_id::<Tag>().unwrap();
+        let archetype = world.archetypes().archetype_of(tagged).unwrap();
+        assert!(archetype.column(tag_id).is_none());
+        assert!(world.query::<Tag>(tagged).is_some());
+        assert!(world.query::<Tag>(untagged).is_none());
+        assert_eq!(world.comps::<Tag>().iter().map(|(entity_id, _)| entity_id).collect::<Vec<_>>(), vec![tagged]);
+    }
+
+    #[test]
+    fn zero_sized_components_with_a_drop_are_dropped() {
+        let mut world = World::new();
+        let removed = world.create();
+        world.add(removed, DropMarker);
+        let released = world.create();
+        world.add(released, DropMarker);
+        let kept = world.create();
+        world.add(kept, DropMarker);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+
+        world.remove::<DropMarker>(removed);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+        world.release(released);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 2);
+        assert!(world.query::<DropMarker>(kept).is_some());
+        drop(world);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+    }
+}
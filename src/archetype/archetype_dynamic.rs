@@ -0,0 +1,238 @@
+use std::cell::Cell;
+use std::marker::PhantomData;
+use crate::component::registry::ComponentId;
+use crate::component::sparse_set::SparseSet;
+use crate::component::tick::{set_changed_cell, ComponentTicks, Tick};
+use crate::entity::entity::EntityId;
+
+// Where the rows of a component are found in a chunk.
+pub enum DynamicColumn<'a> {
+    // first row of the column, the size of a row and the ticks aligned with the rows, null for tags
+    Table(*mut u8, usize, *const Cell<ComponentTicks>),
+    // looked up for each entity, the rows without it are skipped
+    Sparse(&'a SparseSet, usize),
+}
+
+pub struct DynamicChunk<'a> {
+    entities: &'a [EntityId],
+    columns: Vec<DynamicColumn<'a>>,
+    // the tick the rows written are stamped with
+    change_tick: Tick,
+}
+
+impl<'a> DynamicChunk<'a> {
+    pub fn new(entities: &'a [EntityId], columns: Vec<DynamicColumn<'a>>, change_tick: Tick) -> Self {
+        Self { entities, columns, change_tick }
+    }
+}
+
+// Query built from a list of component ids instead of rust types,
+// the components are handed out as pointers or bytes in the order of the ids.
+pub struct DynamicQuery<'a> {
+    components: Vec<ComponentId>,
+    chunks: Vec<DynamicChunk<'a>>,
+}
+
+impl<'a> DynamicQuery<'a> {
+    pub fn new(components: Vec<ComponentId>, chunks: Vec<DynamicChunk<'a>>) -> Self {
+        Self { components, chunks }
+    }
+
+    pub fn components(&self) -> &Vec<ComponentId> {
+        &self.components
+    }
+
+    pub fn iter(&mut self) -> DynamicQueryIter<'_, 'a> {
+        DynamicQueryIter { query: self, outer_index: 0, inner_index: 0 }
+    }
+}
+
+pub struct DynamicQueryIter<'q, 'a> {
+    query: &'q DynamicQuery<'a>,
+    outer_index: usize,
+    inner_index: usize,
+}
+
+impl<'q, 'a> Iterator for DynamicQueryIter<'q, 'a> {
+    type Item = DynamicRow<'q, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.outer_index < self.query.chunks.len() {
+            let chunk = &self.query.chunks[self.outer_index];
+
+            while self.inner_index < chunk.entities.len() {
+                let row = self.inner_index;
+                let entity_id = chunk.entities[row];
+                self.inner_index += 1;
+
+                let has_all = chunk.columns.iter().all(|column| match column {
+                    DynamicColumn::Table(..) => true,
+                    DynamicColumn::Sparse(sparse_set, _) => sparse_set.contains(entity_id),
+                });
+                if has_all {
+                    return Some(DynamicRow { chunk, row, entity_id, _marker: PhantomData });
+                }
+            }
+
+            self.outer_index += 1;
+            self.inner_index = 0;
+        }
+
+        None
+    }
+}
+
+// The components of one entity, in the order of the query ids.
+pub struct DynamicRow<'q, 'a> {
+    chunk: &'q DynamicChunk<'a>,
+    row: usize,
+    entity_id: EntityId,
+    _marker: PhantomData<&'q mut u8>,
+}
+
+impl<'q, 'a> DynamicRow<'q, 'a> {
+    pub fn entity(&self) -> EntityId {
+        self.entity_id
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunk.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunk.columns.is_empty()
+    }
+
+    // To read the component, see ptr_mut to write it.
+    pub fn ptr(&self, index: usize) -> *mut u8 {
+        self.ptr_and_size(index).0
+    }
+
+    // The component counts as changed.
+    pub fn ptr_mut(&mut self, index: usize) -> *mut u8 {
+        self.set_changed(index);
+        self.ptr_and_size(index).0
+    }
+
+    /// The bytes of the component.
+    ///
+    /// # Safety
+    /// They must all be initialized, e.g. the component was inserted from bytes and has no padding.
+    pub unsafe fn bytes(&self, index: usize) -> &[u8] {
+        let (ptr, size) = self.ptr_and_size(index);
+        std::slice::from_raw_parts(ptr, size)
+    }
+
+    /// The bytes of the component, it counts as changed.
+    ///
+    /// # Safety
+    /// Same as bytes, and the bytes written must make a valid value of the component.
+    pub unsafe fn bytes_mut(&mut self, index: usize) -> &mut [u8] {
+        self.set_changed(index);
+        let (ptr, size) = self.ptr_and_size(index);
+        std::slice::from_raw_parts_mut(ptr, size)
+    }
+
+    fn set_changed(&mut self, index: usize) {
+        let change_tick = self.chunk.change_tick;
+        match self.chunk.columns[index] {
+            DynamicColumn::Table(_, _, ticks) if !ticks.is_null() => set_changed_cell(unsafe { &*ticks.add(self.row) }, change_tick),
+            DynamicColumn::Table(..) => {}
+            DynamicColumn::Sparse(sparse_set, _) => set_changed_cell(sparse_set.ticks_cell(self.entity_id).unwrap(), change_tick),
+        }
+    }
+
+    fn ptr_and_size(&self, index: usize) -> (*mut u8, usize) {
+        match self.chunk.columns[index] {
+            DynamicColumn::Table(first, size, _) => (unsafe { first.add(self.row * size) }, size),
+            DynamicColumn::Sparse(sparse_set, size) => (sparse_set.get_ptr(self.entity_id).unwrap(), size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::Layout;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::component::component::StorageType;
+    use crate::component::registry::ComponentDescriptor;
+    use crate::cow_macros::Component;
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Pos(i32);
+
+    fn entities(world: &mut World, components: &[usize]) -> Vec<u32> {
+        world.query_dynamic(components).iter().map(|row| row.entity()).collect()
+    }
+
+    #[test]
+    fn bytes_are_queried_with_rust_components() {
+        let mut world = World::new();
+        let speed = world.register_dynamic(ComponentDescriptor::new("Speed", Layout::new::<[u8; 4]>(), None));
+        let pos = world.register::<Pos>();
+        let (a, b) = (world.create(), world.create());
+        world.add(a, Pos(1));
+        world.add(b, Pos(2));
+        world.add_bytes(a, speed, &[1, 2, 3, 4]);
+
+        let mut query = world.query_dynamic(&[speed, pos]);
+        let mut rows = query.iter();
+        let mut row = rows.next().unwrap();
+        assert!(rows.next().is_none());
+        assert_eq!((row.entity(), row.len()), (a, 2));
+        assert_eq!(unsafe { row.bytes(0) }, &[1, 2, 3, 4]);
+        assert_eq!(unsafe { &*row.ptr(1).cast::<Pos>() }, &Pos(1));
+        unsafe { row.bytes_mut(0)[0] = 9 };
+        unsafe { (*row.ptr_mut(1).cast::<Pos>()).0 = 10 };
+
+        let ptr = world.get_ptr(a, speed).unwrap();
+        assert_eq!(unsafe { std::slice::from_raw_parts(ptr, 4) }, &[9, 2, 3, 4]);
+        assert_eq!(world.query::<Pos>(a), Some(&Pos(10)));
+        assert_eq!(entities(&mut world, &[pos]), [b, a]);
+    }
+
+    #[test]
+    fn drop_runs_on_overwrite_remove_and_despawn() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        unsafe fn count_drop(_: *mut u8) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let mut world = World::new();
+        let handle = world.register_dynamic(ComponentDescriptor::new("Handle", Layout::new::<u32>(), Some(count_drop)));
+        let (a, b) = (world.create(), world.create());
+        world.add_bytes(a, handle, &1u32.to_ne_bytes());
+        world.add_bytes(b, handle, &2u32.to_ne_bytes());
+
+        world.add_bytes(a, handle, &3u32.to_ne_bytes());
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+        world.remove_by_id(a, handle);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+        world.release(b);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 3);
+        assert!(entities(&mut world, &[handle]).is_empty());
+    }
+
+    #[test]
+    fn zero_sized_and_sparse_components() {
+        let mut world = World::new();
+        let flag = world.register_dynamic(ComponentDescriptor::new("Flag", Layout::new::<()>(), None));
+        let sparse = world.register_dynamic(ComponentDescriptor::new("Heat", Layout::new::<u16>(), None).with_storage(StorageType::Sparse));
+        let (a, b, c) = (world.create(), world.create(), world.create());
+        world.add_bytes(a, flag, &[]);
+        world.add_bytes(b, sparse, &7u16.to_ne_bytes());
+        world.add_bytes(c, flag, &[]);
+        world.add_bytes(c, sparse, &8u16.to_ne_bytes());
+
+        assert_eq!(entities(&mut world, &[flag]), [a, c]);
+        assert_eq!(entities(&mut world, &[sparse]), [b, c]);
+        let mut query = world.query_dynamic(&[flag, sparse]);
+        let heats: Vec<_> = query.iter().map(|row| (row.entity(), unsafe { row.bytes(0).len() }, unsafe { row.bytes(1).to_vec() })).collect();
+        assert_eq!(heats, [(c, 0, 8u16.to_ne_bytes().to_vec())]);
+
+        world.remove_by_id(c, sparse);
+        assert_eq!(entities(&mut world, &[sparse]), [b]);
+        assert_eq!(world.archetypes().archetype_of(b).unwrap().index().components().len(), 0);
+    }
+}
@@ -0,0 +1,90 @@
+use std::marker::PhantomData;
+use crate::archetype::archetype::ArchetypeIndex;
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::component::component::{Component, StorageType};
+use crate::entity::entity::EntityId;
+
+pub trait QueryFilter {
+    // the filter accepts everything, the rows don't need to be looked at
+    const IS_EMPTY: bool = false;
+    // the filter looks at sparse components, so it must be checked for each entity
+    const IS_SPARSE: bool;
+
+    fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool;
+
+    fn matches_entity(archs: &ArchetypeManager, entity_id: EntityId) -> bool;
+}
+
+// Only keep the entities that have the component T, without fetching it.
+pub struct With<T: Component + 'static> {
+    _marker: PhantomData<T>,
+}
+
+// Only keep the entities that don't have the component T.
+pub struct Without<T: Component + 'static> {
+    _marker: PhantomData<T>,
+}
+
+impl QueryFilter for () {
+    const IS_EMPTY: bool = true;
+    const IS_SPARSE: bool = false;
+
+    fn matches_archetype(_archs: &ArchetypeManager, _index: &ArchetypeIndex) -> bool {
+        true
+    }
+
+    fn matches_entity(_archs: &ArchetypeManager, _entity_id: EntityId) -> bool {
+        true
+    }
+}
+
+impl<T: Component + 'static> QueryFilter for With<T> {
+    const IS_SPARSE: bool = matches!(T::STORAGE, StorageType::Sparse);
+
+    fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool {
+        Self::IS_SPARSE || archs.component_id::<T>().is_some_and(|component_id| index.contains(component_id))
+    }
+
+    fn matches_entity(archs: &ArchetypeManager, entity_id: EntityId) -> bool {
+        !Self::IS_SPARSE || archs.component_id::<T>()
+            .is_some_and(|component_id| archs.has_sparse(component_id, entity_id))
+    }
+}
+
+impl<T: Component + 'static> QueryFilter for Without<T> {
+    const IS_SPARSE: bool = matches!(T::STORAGE, StorageType::Sparse);
+
+    fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool {
+        Self::IS_SPARSE || !archs.component_id::<T>().is_some_and(|component_id| index.contains(component_id))
+    }
+
+    fn matches_entity(archs: &ArchetypeManager, entity_id: EntityId) -> bool {
+        !Self::IS_SPARSE || !archs.component_id::<T>()
+            .is_some_and(|component_id| archs.has_sparse(component_id, entity_id))
+    }
+}
+
+// a tuple of filters keeps the entities accepted by all of them
+macro_rules! impl_query_filter_tuple {
+    ($($filter:ident),+) => {
+        impl<$($filter: QueryFilter),+> QueryFilter for ($($filter,)+) {
+            const IS_EMPTY: bool = $($filter::IS_EMPTY)&&+;
+            const IS_SPARSE: bool = $($filter::IS_SPARSE)||+;
+
+            fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool {
+                $($filter::matches_archetype(archs, index))&&+
+            }
+
+            fn matches_entity(archs: &ArchetypeManager, entity_id: EntityId) -> bool {
+                $($filter::matches_entity(archs, entity_id))&&+
+            }
+        }
+    };
+}
+
+impl_query_filter_tuple!(A);
+impl_query_filter_tuple!(A, B);
+impl_query_filter_tuple!(A, B, C);
+impl_query_filter_tuple!(A, B, C, D);
+impl_query_filter_tuple!(A, B, C, D, E);
+impl_query_filter_tuple!(A, B, C, D, E, F);
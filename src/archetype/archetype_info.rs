@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use crate::archetype::archetype::{Archetype, ArchetypeIndex};
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::component::registry::{ComponentInfo, ComponentRegistry};
+use crate::component::tick::Tick;
+use crate::entity::entity::EntityId;
+
+// A read-only view of an archetype for debugging and admin tools.
+#[derive(Clone, Copy)]
+pub struct ArchetypeInfo<'a> {
+    id: usize,
+    archetype: &'a Archetype,
+    components: &'a ComponentRegistry,
+}
+
+impl<'a> ArchetypeInfo<'a> {
+    pub(crate) fn new(id: usize, archetype: &'a Archetype, components: &'a ComponentRegistry) -> Self {
+        Self { id, archetype, components }
+    }
+
+    // The position of the archetype in the manager, it stays valid until the manager is compacted.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn index(&self) -> &'a ArchetypeIndex {
+        self.archetype.index()
+    }
+
+    pub fn components(&self) -> impl Iterator<Item=&'a ComponentInfo> + 'a {
+        let components = self.components;
+        self.archetype.index().components().iter().map(move |component_id| components.info(*component_id))
+    }
+
+    pub fn component_names(&self) -> Vec<&'a str> {
+        self.components().map(|info| info.short_name()).collect()
+    }
+
+    pub fn entities(&self) -> &'a [EntityId] {
+        self.archetype.indices()
+    }
+
+    pub fn len(&self) -> usize {
+        self.archetype.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.archetype.is_empty()
+    }
+
+    // Approximate bytes allocated by the archetype, see Archetype::memory
+    pub fn memory(&self) -> usize {
+        self.archetype.memory()
+    }
+}
+
+// e.g. #3 (Position, Velocity): 120 entities, 4096 bytes
+impl Display for ArchetypeInfo<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} ({}): {} entities, {} bytes", self.id, self.component_names().join(", "), self.len(), self.memory())
+    }
+}
+
+// The archetypes of a manager at one tick, recorded by ArchetypeHistory to follow the fragmentation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ArchetypeStats {
+    pub tick: Tick,
+    pub archetypes: usize,
+    pub empty_archetypes: usize,
+    pub entities: usize,
+    pub largest: usize,
+    pub memory: usize,
+}
+
+impl ArchetypeStats {
+    pub fn of(archs: &ArchetypeManager) -> Self {
+        let mut stats = Self { tick: archs.change_tick(), ..Self::default() };
+        for info in archs.archetype_infos() {
+            stats.archetypes += 1;
+            stats.entities += info.len();
+            stats.largest = stats.largest.max(info.len());
+            stats.memory += info.memory();
+            if info.is_empty() {
+                stats.empty_archetypes += 1;
+            }
+        }
+        stats
+    }
+
+    pub fn average_entities(&self) -> f32 {
+        let used = self.archetypes - self.empty_archetypes;
+        if used == 0 { 0.0 } else { self.entities as f32 / used as f32 }
+    }
+
+    // 0 when every entity shares one archetype, gets close to 1 when each entity has its own.
+    pub fn fragmentation(&self) -> f32 {
+        let used = self.archetypes - self.empty_archetypes;
+        if self.entities <= 1 { 0.0 } else { used.saturating_sub(1) as f32 / (self.entities - 1) as f32 }
+    }
+}
+
+// The last stats recorded, the oldest are dropped past the capacity.
+pub struct ArchetypeHistory {
+    capacity: usize,
+    samples: VecDeque<ArchetypeStats>,
+}
+
+impl ArchetypeHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), samples: VecDeque::new() }
+    }
+
+    pub fn record(&mut self, archs: &ArchetypeManager) -> ArchetypeStats {
+        let stats = ArchetypeStats::of(archs);
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(stats);
+        stats
+    }
+
+    // oldest first
+    pub fn samples(&self) -> impl Iterator<Item=&ArchetypeStats> {
+        self.samples.iter()
+    }
+
+    pub fn last(&self) -> Option<&ArchetypeStats> {
+        self.samples.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archetype::archetype_filter::Without;
+    use crate::component::component_box::ComponentBox;
+    use crate::component::tick::{ComponentTicks, Tick};
+    use crate::cow_macros::Component;
+    use crate::entity::entity::EntityId;
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Pos(#[allow(dead_code)] [f32; 3]);
+
+    #[derive(Component)]
+    struct Vel(#[allow(dead_code)] [f32; 3]);
+
+    #[derive(Component)]
+    struct Player;
+
+    #[test]
+    fn infos_describe_each_archetype() {
+        let mut world = World::new();
+        let moving: Vec<EntityId> = (0..3).map(|_| world.create()).collect();
+        for entity_id in moving.iter().copied() {
+            world.add_boxes(entity_id, vec![ComponentBox::new(Pos([0.0; 3])), ComponentBox::new(Vel([1.0; 3]))]);
+        }
+        let players: Vec<EntityId> = (0..2).map(|_| world.create()).collect();
+        for entity_id in players.iter().copied() {
+            world.add_boxes(entity_id, vec![ComponentBox::new(Pos([0.0; 3])), ComponentBox::new(Player)]);
+        }
+
+        let archetypes = world.archetypes();
+        let moving_info = archetypes.archetype_info_of(moving[0]).unwrap();
+        let player_info = archetypes.archetype_info_of(players[0]).unwrap();
+        assert_eq!(moving_info.component_names(), ["Pos", "Vel"]);
+        assert_eq!(player_info.component_names(), ["Pos", "Player"]);
+        assert_eq!((moving_info.len(), player_info.len()), (3, 2));
+        assert_eq!(moving_info.entities(), moving);
+        assert_eq!(archetypes.archetype_info(moving_info.id()).unwrap().entities(), moving);
+
+        // the rows, and a column for each component holding bytes, the tag has none
+        let row = std::mem::size_of::<EntityId>() + std::mem::size_of::<Tick>();
+        let column = std::mem::size_of::<[f32; 3]>() + std::mem::size_of::<ComponentTicks>();
+        assert!(moving_info.memory() >= 3 * (row + 2 * column));
+        assert!(player_info.memory() >= 2 * (row + column));
+        assert!(player_info.memory() < moving_info.memory());
+        assert_eq!(moving_info.to_string(), format!("#{} (Pos, Vel): 3 entities, {} bytes", moving_info.id(), moving_info.memory()));
+
+        let ids = |infos: Vec<usize>| { let mut infos = infos; infos.sort(); infos };
+        let matching = ids(archetypes.matching_archetypes::<(&Pos,), ()>().map(|info| info.id()).collect());
+        assert_eq!(matching, ids(vec![moving_info.id(), player_info.id()]));
+        let matching: Vec<usize> = archetypes.matching_archetypes::<(&Pos,), Without<Vel>>().map(|info| info.id()).collect();
+        assert_eq!(matching, [player_info.id()]);
+        let matching: Vec<usize> = archetypes.matching_archetypes::<(&Vel, &Player), ()>().map(|info| info.id()).collect();
+        assert!(matching.is_empty());
+
+        let stats = archetypes.stats();
+        assert_eq!((stats.entities, stats.largest), (5, 3));
+        // the entities were created in the empty archetype
+        assert_eq!((stats.archetypes, stats.empty_archetypes), (3, 1));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::archetype::archetype_query::{ArchetypeQuery, ArchetypeQueryMut};
+use crate::archetype::archetype_query::{ArchetypeQuery, ArchetypeQueryMut, QueryChunk};
+use crate::archetype::query_data::QueryData;
 use crate::component::component::Component;
 use crate::entity::entity::EntityId;
 
@@ -62,12 +63,11 @@ impl<'a, T: Component> Iterator for ArchetypeQueryIterMut<'a, T> {
 
             while self.outer_index < query.indices().len() {
                 let indices = query.indices()[self.outer_index];
-                let storages = &mut *query.storages()[self.outer_index];
 
                 if self.inner_index < indices.len() {
                     let entity_id = indices[self.inner_index];
 
-                    let component = &mut *(&mut storages[self.inner_index] as *mut T);
+                    let component = &mut *(query.row_mut(self.outer_index, self.inner_index) as *mut T);
 
                     self.inner_index += 1;
                     return Some((entity_id, component));
@@ -80,4 +80,262 @@ impl<'a, T: Component> Iterator for ArchetypeQueryIterMut<'a, T> {
             None
         }
     }
-}
\ No newline at end of file
+}
+pub struct ArchetypeTupleQueryIter<'q, 'a, D: QueryData> {
+    chunks: &'q [QueryChunk<'a, D>],
+    outer_index: usize,
+    inner_index: usize,
+}
+
+impl<'q, 'a, D: QueryData> ArchetypeTupleQueryIter<'q, 'a, D> {
+    pub fn new(chunks: &'q [QueryChunk<'a, D>]) -> Self {
+        Self { chunks, outer_index: 0, inner_index: 0 }
+    }
+}
+
+impl<'q, 'a, D: QueryData> Iterator for ArchetypeTupleQueryIter<'q, 'a, D> {
+    type Item = (EntityId, D::Item<'q>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.outer_index < self.chunks.len() {
+            let chunk = &self.chunks[self.outer_index];
+
+            while self.inner_index < chunk.len() {
+                let index = self.inner_index;
+                self.inner_index += 1;
+                // each row is handed out once, the items never alias
+                if let Some(item) = unsafe { chunk.item(index) } {
+                    return Some(item);
+                }
+            }
+
+            self.outer_index += 1;
+            self.inner_index = 0;
+        }
+
+        None
+    }
+}
+
+// The (storage, row) of each entity of the current combination, always in increasing order.
+struct CombinationCursor<const K: usize> {
+    rows: [(usize, usize); K],
+    started: bool,
+    done: bool,
+}
+
+impl<const K: usize> CombinationCursor<K> {
+    fn new() -> Self {
+        Self { rows: [(0, 0); K], started: false, done: K == 0 }
+    }
+
+    // The row after position, skipping the empty storages.
+    fn step(indices: &[&[EntityId]], (outer, inner): (usize, usize)) -> Option<(usize, usize)> {
+        if inner + 1 < indices[outer].len() {
+            return Some((outer, inner + 1));
+        }
+        (outer + 1..indices.len()).find(|outer| !indices[*outer].is_empty()).map(|outer| (outer, 0))
+    }
+
+    // Places the rows from the index on, each one right after the previous.
+    fn fill(indices: &[&[EntityId]], rows: &mut [(usize, usize); K], from: usize) -> bool {
+        for i in from..K {
+            match Self::step(indices, rows[i - 1]) {
+                Some(row) => rows[i] = row,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn advance(&mut self, indices: &[&[EntityId]]) -> bool {
+        if self.done {
+            return false;
+        }
+
+        if !self.started {
+            self.started = true;
+            let mut rows = self.rows;
+            self.done = match indices.iter().position(|entities| !entities.is_empty()) {
+                Some(outer) => {
+                    rows[0] = (outer, 0);
+                    !Self::fill(indices, &mut rows, 1)
+                }
+                None => true,
+            };
+            self.rows = rows;
+            return !self.done;
+        }
+
+        // moves the last row that still leaves room for the ones after it
+        for i in (0..K).rev() {
+            let mut rows = self.rows;
+            if let Some(row) = Self::step(indices, rows[i]) {
+                rows[i] = row;
+                if Self::fill(indices, &mut rows, i + 1) {
+                    self.rows = rows;
+                    return true;
+                }
+            }
+        }
+
+        self.done = true;
+        false
+    }
+}
+
+// Every unordered combination of K entities of the query, e.g. the pairs for K = 2
+pub struct CombinationIter<'a, T: Component + 'static, const K: usize> {
+    query: &'a ArchetypeQuery<'a, T>,
+    cursor: CombinationCursor<K>,
+}
+
+impl<'a, T: Component + 'static, const K: usize> CombinationIter<'a, T, K> {
+    pub fn new(query: &'a ArchetypeQuery<'a, T>) -> Self {
+        Self { query, cursor: CombinationCursor::new() }
+    }
+}
+
+impl<'a, T: Component + 'static, const K: usize> Iterator for CombinationIter<'a, T, K> {
+    type Item = [(EntityId, &'a T); K];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let indices = self.query.indices();
+        if !self.cursor.advance(indices) {
+            return None;
+        }
+
+        let storages = self.query.storage();
+        Some(self.cursor.rows.map(|(outer, inner)| (indices[outer][inner], &storages[outer][inner])))
+    }
+}
+
+// Same as CombinationIter with mutable components. An entity is part of many combinations,
+// so a combination has to be dropped before fetching the next one.
+pub struct CombinationIterMut<'q, 'a, T: Component + 'static, const K: usize> {
+    query: &'q mut ArchetypeQueryMut<'a, T>,
+    cursor: CombinationCursor<K>,
+}
+
+impl<'q, 'a, T: Component + 'static, const K: usize> CombinationIterMut<'q, 'a, T, K> {
+    pub fn new(query: &'q mut ArchetypeQueryMut<'a, T>) -> Self {
+        Self { query, cursor: CombinationCursor::new() }
+    }
+
+    pub fn fetch_next(&mut self) -> Option<[(EntityId, &mut T); K]> {
+        let indices = self.query.indices();
+        if !self.cursor.advance(indices) {
+            return None;
+        }
+
+        let rows = self.cursor.rows;
+        let entities = rows.map(|(outer, inner)| indices[outer][inner]);
+        // the rows of a combination are all different, the references don't alias
+        let mut components = unsafe { self.query.rows_mut(rows) }.into_iter();
+        Some(entities.map(|entity_id| (entity_id, components.next().unwrap())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::archetype::archetype_iter::CombinationCursor;
+    use crate::cow_macros::Component;
+    use crate::entity::entity::EntityId;
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Count(usize);
+
+    #[derive(Component)]
+    struct Named;
+
+    #[derive(Component)]
+    struct Frozen;
+
+    fn cursor_rows<const K: usize>(indices: &[&[EntityId]]) -> Vec<[(usize, usize); K]> {
+        let mut cursor = CombinationCursor::<K>::new();
+        let mut rows = vec![];
+        while cursor.advance(indices) {
+            rows.push(cursor.rows);
+        }
+        // done stays done
+        assert!(!cursor.advance(indices));
+        rows
+    }
+
+    // count entities spread over up to three archetypes
+    fn world(count: usize) -> World {
+        let mut world = World::new();
+        for i in 0..count {
+            let entity_id = world.create();
+            world.add(entity_id, Count(0));
+            match i % 3 {
+                1 => world.add(entity_id, Named),
+                2 => world.add(entity_id, Frozen),
+                _ => (),
+            }
+        }
+        world
+    }
+
+    fn choose(n: usize, k: usize) -> usize {
+        if k > n {
+            return 0;
+        }
+        (0..k).fold(1, |total, i| total * (n - i) / (i + 1))
+    }
+
+    #[test]
+    fn cursor_skips_the_empty_storages() {
+        let indices: [&[EntityId]; 4] = [&[], &[1, 2], &[], &[3]];
+        assert_eq!(cursor_rows::<2>(&indices), [[(1, 0), (1, 1)], [(1, 0), (3, 0)], [(1, 1), (3, 0)]]);
+        assert_eq!(cursor_rows::<3>(&indices), [[(1, 0), (1, 1), (3, 0)]]);
+        assert!(cursor_rows::<4>(&indices).is_empty());
+        assert!(cursor_rows::<1>(&[&[], &[]]).is_empty());
+        assert!(cursor_rows::<0>(&indices).is_empty());
+    }
+
+    #[test]
+    fn combinations_cover_every_set_of_entities_once() {
+        for count in [0, 1, 2, 3, 7] {
+            let world = world(count);
+            let comps = world.comps::<Count>();
+
+            let pairs: Vec<[EntityId; 2]> = comps.iter_combinations::<2>().map(|pair| pair.map(|(entity_id, _)| entity_id)).collect();
+            let triples: Vec<[EntityId; 3]> = comps.iter_combinations::<3>().map(|triple| triple.map(|(entity_id, _)| entity_id)).collect();
+            assert_eq!(pairs.len(), choose(count, 2));
+            assert_eq!(triples.len(), choose(count, 3));
+
+            let distinct = |entities: &[EntityId]| entities.iter().collect::<HashSet<_>>().len() == entities.len();
+            assert!(pairs.iter().all(|pair| distinct(pair)) && triples.iter().all(|triple| distinct(triple)));
+            let sorted = |entities: &[EntityId]| { let mut entities = entities.to_vec(); entities.sort(); entities };
+            assert_eq!(pairs.iter().map(|pair| sorted(pair)).collect::<HashSet<_>>().len(), pairs.len());
+            assert_eq!(triples.iter().map(|triple| sorted(triple)).collect::<HashSet<_>>().len(), triples.len());
+        }
+    }
+
+    #[test]
+    fn combinations_mut_write_each_entity_once_per_combination() {
+        for count in [0, 1, 3, 7] {
+            let mut world = world(count);
+            let mut comps = world.comps_mut::<Count>();
+            let mut pairs = comps.iter_combinations_mut::<2>();
+            while let Some([(_, a), (_, b)]) = pairs.fetch_next() {
+                a.0 += 1;
+                b.0 += 1;
+            }
+            let mut triples = comps.iter_combinations_mut::<3>();
+            while let Some(triple) = triples.fetch_next() {
+                for (_, count) in triple {
+                    count.0 += 100;
+                }
+            }
+
+            // each entity is in a pair with every other and in a triple with every pair of the others
+            let expected = choose(count.saturating_sub(1), 1) + 100 * choose(count.saturating_sub(1), 2);
+            assert!(world.comps::<Count>().iter().all(|(_, total)| total.0 == expected));
+            assert_eq!(world.comps::<Count>().iter().count(), count);
+        }
+    }
+}
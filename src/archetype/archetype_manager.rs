@@ -1,211 +1,960 @@
-use std::any::TypeId;
 use std::collections::{HashMap, HashSet};
+use std::mem::ManuallyDrop;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use crate::archetype::archetype::{Archetype, ArchetypeIndex};
-use crate::archetype::archetype_query::{ArchetypeQuery, ArchetypeQueryMut};
-use crate::component::component::{Component};
+use crate::archetype::archetype_dynamic::{DynamicChunk, DynamicColumn, DynamicQuery};
+use crate::archetype::archetype_filter::QueryFilter;
+use crate::archetype::archetype_info::{ArchetypeInfo, ArchetypeStats};
+use crate::archetype::archetype_query::{ArchetypeQuery, ArchetypeQueryMut, ArchetypeTupleQuery, EntityLocator, EntityRows, QueryChunk};
+use crate::archetype::query_data::{QueryData, ReadOnlyQueryData};
+use crate::archetype::query_state::QueryState;
+use crate::component::component::{Component, StorageType};
+use crate::component::component_box::ComponentBox;
+use crate::component::registry::{ComponentDescriptor, ComponentId, ComponentRegistry};
+use crate::component::sparse_set::SparseSet;
+use crate::component::tick::{ComponentTicks, Tick};
 use crate::entity::entity::EntityId;
+use crate::schedule::task_pool::TaskPool;
+use crate::schedule::task_type::TaskType;
+use crate::world::ForkError;
+
+// ids given to the managers, 0 is never used
+static NEXT_MANAGER_ID: AtomicU64 = AtomicU64::new(1);
 
 pub struct ArchetypeManager {
+    // tells the query states which manager their archetypes belong to
+    id: u64,
+    // every component type known by the world
+    components: ComponentRegistry,
     // link  current archetype of an entity
-    entities: HashMap<EntityId, usize>,
+    entities: Arc<HashMap<EntityId, usize>>,
     // link an index with an archetype
     archetypes_types: HashMap<ArchetypeIndex, usize>,
     // link all archetypes where are contained type is contained where
-    archetypes_contains: HashMap<TypeId, HashSet<usize>>,
+    archetypes_contains: HashMap<ComponentId, HashSet<usize>>,
     // the archetypes
     archetypes: Vec<Archetype>,
+    // components stored outside of the archetypes
+    sparse_sets: HashMap<ComponentId, Arc<SparseSet>>,
+    // the tick writes are stamped with, see World::advance_tick
+    change_tick: Tick,
+    // the tick each entity was spawned at
+    spawned: Arc<HashMap<EntityId, Tick>>,
+    // removed components and released entities, only logged while replicated, see World::enable_replication
+    track_removals: bool,
+    removed: Arc<Vec<(Tick, EntityId, ComponentId)>>,
+    despawned: Arc<Vec<(Tick, EntityId)>>,
+    // the threads of the parallel queries, shared with the snapshots and forks
+    task_pool: Arc<TaskPool>,
+}
+
+impl Default for ArchetypeManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ArchetypeManager {
     pub fn new() -> Self {
+        let components = ComponentRegistry::new();
+
         // create the basic archetype empty
         let mut archetypes_types = HashMap::new();
         archetypes_types.insert(ArchetypeIndex::new(), 0);
+        let archetypes = vec![Archetype::new(ArchetypeIndex::new(), &components)];
 
         Self {
-            entities: HashMap::new(),
+            id: NEXT_MANAGER_ID.fetch_add(1, Ordering::Relaxed),
+            components,
+            entities: Arc::default(),
             archetypes_types,
             archetypes_contains: HashMap::new(),
-            archetypes: vec![Archetype::new(ArchetypeIndex::new())],
+            archetypes,
+            sparse_sets: HashMap::new(),
+            change_tick: 1,
+            spawned: Arc::default(),
+            track_removals: false,
+            removed: Arc::default(),
+            despawned: Arc::default(),
+            task_pool: Arc::default(),
+        }
+    }
+
+    // A read only copy sharing the storages, see World::snapshot
+    pub fn snapshot(&self) -> Self {
+        // the components left out of the copy are not in its archetypes, the lookups are rebuilt without them
+        // (several archetypes can end up with the same components, the first one is kept in archetypes_types)
+        let archetypes: Vec<Archetype> = self.archetypes.iter().map(Archetype::snapshot).collect();
+        let mut archetypes_types = HashMap::new();
+        let mut archetypes_contains: HashMap<ComponentId, HashSet<usize>> = HashMap::new();
+        for (arch_id, archetype) in archetypes.iter().enumerate() {
+            for component_id in archetype.index().components() {
+                archetypes_contains.entry(*component_id).or_default().insert(arch_id);
+            }
+            archetypes_types.entry(archetype.index().clone()).or_insert(arch_id);
+        }
+
+        Self {
+            id: NEXT_MANAGER_ID.fetch_add(1, Ordering::Relaxed),
+            components: self.components.clone(),
+            entities: self.entities.clone(),
+            archetypes_types,
+            archetypes_contains,
+            archetypes,
+            sparse_sets: self.sparse_sets.iter()
+                .filter(|(_, sparse_set)| sparse_set.column().is_cloneable())
+                .map(|(component_id, sparse_set)| (*component_id, sparse_set.clone()))
+                .collect(),
+            change_tick: self.change_tick,
+            spawned: self.spawned.clone(),
+            track_removals: self.track_removals,
+            removed: self.removed.clone(),
+            despawned: self.despawned.clone(),
+            task_pool: self.task_pool.clone(),
+        }
+    }
+
+    // A copy sharing the storages with this one until either writes to them, see World::fork
+    pub fn fork(&self) -> Result<Self, ForkError> {
+        for info in self.components.infos().iter().filter(|info| !info.is_tag() && !info.is_cloneable()) {
+            let in_archetypes = self.archetypes_contains.get(&info.id())
+                .is_some_and(|archetypes| archetypes.iter().any(|index| !self.archetypes[*index].indices().is_empty()));
+            let in_sparse_set = self.sparse_sets.get(&info.id()).is_some_and(|sparse_set| !sparse_set.is_empty());
+            if in_archetypes || in_sparse_set {
+                return Err(ForkError::Component(info.name().to_string()));
+            }
+        }
+
+        Ok(Self {
+            id: NEXT_MANAGER_ID.fetch_add(1, Ordering::Relaxed),
+            components: self.components.clone(),
+            entities: self.entities.clone(),
+            archetypes_types: self.archetypes_types.clone(),
+            archetypes_contains: self.archetypes_contains.clone(),
+            archetypes: self.archetypes.iter().map(Archetype::fork).collect(),
+            sparse_sets: self.sparse_sets.clone(),
+            change_tick: self.change_tick,
+            spawned: self.spawned.clone(),
+            track_removals: self.track_removals,
+            removed: self.removed.clone(),
+            despawned: self.despawned.clone(),
+            task_pool: self.task_pool.clone(),
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn archetypes(&self) -> &[Archetype] {
+        &self.archetypes
+    }
+
+    pub fn archetype_infos(&self) -> impl Iterator<Item=ArchetypeInfo<'_>> {
+        self.archetypes.iter().enumerate().map(|(id, archetype)| ArchetypeInfo::new(id, archetype, &self.components))
+    }
+
+    pub fn archetype_info(&self, id: usize) -> Option<ArchetypeInfo<'_>> {
+        self.archetypes.get(id).map(|archetype| ArchetypeInfo::new(id, archetype, &self.components))
+    }
+
+    // The archetype made of exactly these components, if an entity ever had them.
+    pub fn archetype_id(&self, index: &ArchetypeIndex) -> Option<usize> {
+        self.archetypes_types.get(index).copied()
+    }
+
+    pub fn archetype_info_of(&self, entity_id: EntityId) -> Option<ArchetypeInfo<'_>> {
+        self.archetype_info(*self.entities.get(&entity_id)?)
+    }
+
+    // The archetypes a query over D with the filter F goes through, sparse filters are not checked per entity.
+    pub fn matching_archetypes<D: QueryData, F: QueryFilter>(&self) -> impl Iterator<Item=ArchetypeInfo<'_>> {
+        self.archetype_infos()
+            .filter(|info| D::matches_archetype(self, info.index()) && F::matches_archetype(self, info.index()))
+    }
+
+    pub fn stats(&self) -> ArchetypeStats {
+        ArchetypeStats::of(self)
+    }
+
+    pub fn components(&self) -> &ComponentRegistry {
+        &self.components
+    }
+
+    pub fn register<T: Component + 'static>(&mut self) -> ComponentId {
+        self.components.register::<T>()
+    }
+
+    pub fn register_descriptor(&mut self, descriptor: ComponentDescriptor) -> ComponentId {
+        self.components.register_descriptor(descriptor)
+    }
+
+    pub fn component_id<T: Component + 'static>(&self) -> Option<ComponentId> {
+        self.components.id::<T>()
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item=EntityId> + '_ {
+        self.entities.keys().copied()
+    }
+
+    pub fn entities_count(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn change_tick(&self) -> Tick {
+        self.change_tick
+    }
+
+    pub fn advance_tick(&mut self) -> Tick {
+        self.change_tick += 1;
+        self.change_tick
+    }
+
+    pub fn spawn_tick(&self, entity_id: EntityId) -> Option<Tick> {
+        self.spawned.get(&entity_id).copied()
+    }
+
+    pub fn spawn_ticks(&self) -> impl Iterator<Item=(EntityId, Tick)> + '_ {
+        self.spawned.iter().map(|(entity_id, tick)| (*entity_id, *tick))
+    }
+
+    // Starts or stops logging the removed components and released entities, the logs are emptied when it stops.
+    pub fn set_track_removals(&mut self, track_removals: bool) {
+        self.track_removals = track_removals;
+        if !track_removals {
+            self.removed = Arc::default();
+            self.despawned = Arc::default();
+        }
+    }
+
+    pub fn tracks_removals(&self) -> bool {
+        self.track_removals
+    }
+
+    // The components removed from living entities, oldest first.
+    pub fn removed(&self) -> &[(Tick, EntityId, ComponentId)] {
+        &self.removed
+    }
+
+    pub fn despawned(&self) -> &[(Tick, EntityId)] {
+        &self.despawned
+    }
+
+    // Forgets the removals and releases made at or before tick.
+    pub fn prune_removed(&mut self, tick: Tick) {
+        if self.removed.first().is_some_and(|(removed_tick, _, _)| *removed_tick <= tick) {
+            Arc::make_mut(&mut self.removed).retain(|(removed_tick, _, _)| *removed_tick > tick);
+        }
+        if self.despawned.first().is_some_and(|(despawned_tick, _)| *despawned_tick <= tick) {
+            Arc::make_mut(&mut self.despawned).retain(|(despawned_tick, _)| *despawned_tick > tick);
+        }
+    }
+
+    // Frees the archetypes left without entities and the capacity the storages don't use,
+    // returns the number of archetypes freed. The archetypes left are renumbered, so the manager
+    // gets a new id and the query states built for it start over.
+    pub fn compact(&mut self) -> usize {
+        for archetype in self.archetypes.iter_mut() {
+            archetype.shrink_to_fit();
+        }
+        for sparse_set in self.sparse_sets.values_mut() {
+            if let Some(sparse_set) = Arc::get_mut(sparse_set) {
+                sparse_set.shrink_to_fit();
+            }
+        }
+        if let Some(entities) = Arc::get_mut(&mut self.entities) {
+            entities.shrink_to_fit();
+        }
+        if let Some(removed) = Arc::get_mut(&mut self.removed) {
+            removed.shrink_to_fit();
+        }
+        if let Some(despawned) = Arc::get_mut(&mut self.despawned) {
+            despawned.shrink_to_fit();
+        }
+
+        // the archetype without components is where entities are created, it's always kept
+        let freed = self.archetypes.iter().skip(1).filter(|archetype| archetype.is_empty()).count();
+        if freed == 0 {
+            return 0;
+        }
+
+        let mut new_ids = Vec::with_capacity(self.archetypes.len());
+        let mut archetypes = Vec::with_capacity(self.archetypes.len() - freed);
+        for (arch_id, archetype) in std::mem::take(&mut self.archetypes).into_iter().enumerate() {
+            if arch_id != 0 && archetype.is_empty() {
+                new_ids.push(None);
+            } else {
+                new_ids.push(Some(archetypes.len()));
+                archetypes.push(archetype);
+            }
         }
+        self.archetypes = archetypes;
+
+        self.archetypes_types.clear();
+        self.archetypes_contains.clear();
+        for arch_id in 0..self.archetypes.len() {
+            let archetype_index = self.archetypes[arch_id].index().clone();
+            self.set_all_contained(&archetype_index, arch_id);
+            self.archetypes_types.insert(archetype_index, arch_id);
+        }
+        self.archetypes_types.shrink_to_fit();
+        self.archetypes_contains.shrink_to_fit();
+
+        for arch_id in Arc::make_mut(&mut self.entities).values_mut() {
+            *arch_id = new_ids[*arch_id].expect("an entity is in an archetype that was freed");
+        }
+        self.id = NEXT_MANAGER_ID.fetch_add(1, Ordering::Relaxed);
+        freed
+    }
+
+    pub fn ticks(&self, entity_id: EntityId, component_id: ComponentId) -> Option<ComponentTicks> {
+        if self.components.get_info(component_id)?.storage() == StorageType::Sparse {
+            return self.sparse_sets.get(&component_id)?.ticks(entity_id);
+        }
+        self.archetype_of(entity_id)?.ticks(entity_id, component_id)
+    }
+
+    // The archetypes storing the component in their table.
+    pub fn archetypes_with(&self, component_id: ComponentId) -> impl Iterator<Item=&Archetype> {
+        self.archetypes_contains.get(&component_id)
+            .into_iter()
+            .flat_map(|archetypes| archetypes.iter().map(|index| &self.archetypes[*index]))
+    }
+
+    pub fn archetype_of(&self, entity_id: EntityId) -> Option<&Archetype> {
+        self.entities.get(&entity_id).map(|arch_id| &self.archetypes[*arch_id])
+    }
+
+    // The components of the entity, the ones in its archetype then the sparse ones.
+    pub fn components_of(&self, entity_id: EntityId) -> impl Iterator<Item=ComponentId> + '_ {
+        let archetype = self.archetype_of(entity_id).map(|archetype| archetype.index().components().as_slice()).unwrap_or_default();
+        let sparse = self.sparse_sets.iter()
+            .filter(move |(_, sparse_set)| sparse_set.contains(entity_id))
+            .map(|(component_id, _)| *component_id);
+        archetype.iter().copied().chain(sparse)
+    }
+
+    pub fn sparse_set(&self, component_id: ComponentId) -> Option<&SparseSet> {
+        self.sparse_sets.get(&component_id).map(Arc::as_ref)
+    }
+
+    pub fn has_sparse(&self, component_id: ComponentId, entity_id: EntityId) -> bool {
+        self.sparse_sets.get(&component_id).is_some_and(|sparse_set| sparse_set.contains(entity_id))
     }
 
     pub fn add_entity(&mut self, entity_id: EntityId) {
-        self.entities.insert(entity_id, 0);
-        self.archetypes[0].add_without_comp(entity_id);
+        Arc::make_mut(&mut self.entities).insert(entity_id, 0);
+        Arc::make_mut(&mut self.spawned).insert(entity_id, self.change_tick);
+        self.archetypes[0].add_without_comp(entity_id, self.change_tick);
+    }
+
+    pub fn remove_entity(&mut self, entity_id: EntityId) {
+        if let Some(arch_id) = Arc::make_mut(&mut self.entities).remove(&entity_id) {
+            self.archetypes[arch_id].release(entity_id);
+            Arc::make_mut(&mut self.spawned).remove(&entity_id);
+            if self.track_removals {
+                Arc::make_mut(&mut self.despawned).push((self.change_tick, entity_id));
+            }
+        }
+
+        for sparse_set in self.sparse_sets.values_mut() {
+            if sparse_set.contains(entity_id) {
+                unshare_sparse_set(sparse_set).remove(entity_id);
+            }
+        }
     }
 
     pub fn add<T: Component + 'static>(&mut self, entity_id: EntityId, comp: T) {
-        // entity exist
-        let arch_before_add = &self.archetypes[self.entities[&entity_id]].index().clone();
+        let component_id = self.components.register::<T>();
+        let comp = ManuallyDrop::new(comp);
+        unsafe { self.add_raw(entity_id, component_id, &*comp as *const T as *const u8) };
+    }
+
+    pub fn add_box(&mut self, entity_id: EntityId, comp: ComponentBox) {
+        let component_id = self.components.register_descriptor(comp.descriptor().clone());
+        comp.insert_with(|comp| unsafe { self.add_raw(entity_id, component_id, comp) });
+    }
+
+    // Moves the component bytes pointed by comp to the entity, the caller must not drop them.
+    // comp must point to a valid value of the registered component.
+    pub(crate) unsafe fn add_raw(&mut self, entity_id: EntityId, component_id: ComponentId, comp: *const u8) {
+        // sparse components are not part of the archetype, the entity doesn't move
+        let info = self.components.info(component_id);
+        if info.storage() == StorageType::Sparse {
+            let sparse_set = self.sparse_sets.entry(component_id)
+                .or_insert_with(|| Arc::new(SparseSet::new(info.descriptor())));
+            unshare_sparse_set(sparse_set).insert(entity_id, comp, self.change_tick);
+            return;
+        }
 
-        let mut new_arch = arch_before_add.clone();
-        let is_update = !new_arch.add::<T>();
+        // entity exist
+        let old_arch_id = self.entities[&entity_id];
+        let mut new_arch = self.archetypes[old_arch_id].index().clone();
 
         // is the archetype of the entity changed (could be a simple update) ?
         // if so we need to transfer it of archetype
-        if !is_update {
-
-            // does the new archetype exist
-            let new_arch_id = if let Some(entity_arch_types) = self.archetypes_types.get(&new_arch) {
-                if let Some(old_archetype_index) = self.archetypes_types.get(arch_before_add) {
-                    if old_archetype_index != entity_arch_types {
-
-                        // Ensure that we have two distinct indices
-                        let (old_archetype, new_archetype) = if old_archetype_index < entity_arch_types {
-                            let (first_part, second_part) = self.archetypes.split_at_mut(*entity_arch_types);
-                            let old_archetype = &mut first_part[*old_archetype_index];
-                            let archetype = &mut second_part[0];
-                            (old_archetype, archetype)
-                        } else {
-                            let (first_part, second_part) = self.archetypes.split_at_mut(*old_archetype_index);
-                            let archetype = &mut first_part[*entity_arch_types];
-                            let old_archetype = &mut second_part[0];
-                            (old_archetype, archetype)
-                        };
-
-                        old_archetype.transfer(new_archetype, entity_id);
-                        new_archetype.add(comp);
+        if !new_arch.add(component_id) {
+            self.archetypes[old_arch_id].update(entity_id, component_id, comp, self.change_tick);
+            return;
+        }
+
+        let tick = self.change_tick;
+        let new_arch_id = self.find_or_create_archetype(new_arch);
+        let (old_archetype, new_archetype) = self.archetype_pair(old_arch_id, new_arch_id);
+        old_archetype.transfer(new_archetype, entity_id, tick);
+        new_archetype.add(component_id, comp, tick);
+
+        // update the archetypes of the entity
+        Arc::make_mut(&mut self.entities).insert(entity_id, new_arch_id);
+    }
+
+    // Same as add_raw for several components, the entity changes of archetype at most once.
+    // A component given twice keeps the last value.
+    pub(crate) unsafe fn add_raw_many(&mut self, entity_id: EntityId, comps: &[(ComponentId, *const u8)]) {
+        match comps {
+            [] => return,
+            [(component_id, comp)] => return self.add_raw(entity_id, *component_id, *comp),
+            _ => (),
+        }
+
+        let old_arch_id = self.entities[&entity_id];
+        let mut new_arch = self.archetypes[old_arch_id].index().clone();
+        let mut added: Vec<(ComponentId, *const u8)> = vec![];
+        for (component_id, comp) in comps.iter().copied() {
+            let info = self.components.info(component_id);
+            if info.storage() == StorageType::Sparse {
+                self.add_raw(entity_id, component_id, comp);
+            } else if self.archetypes[old_arch_id].index().contains(component_id) {
+                self.archetypes[old_arch_id].update(entity_id, component_id, comp, self.change_tick);
+            } else if new_arch.add(component_id) {
+                added.push((component_id, comp));
+            } else if let Some(previous) = added.iter_mut().find(|(added_id, _)| *added_id == component_id) {
+                // the previous value is replaced before it's ever stored
+                if let Some(drop) = info.descriptor().drop_fn() {
+                    drop(previous.1 as *mut u8);
+                }
+                previous.1 = comp;
+            }
+        }
+
+        if added.is_empty() {
+            return;
+        }
+
+        let tick = self.change_tick;
+        let new_arch_id = self.find_or_create_archetype(new_arch);
+        let (old_archetype, new_archetype) = self.archetype_pair(old_arch_id, new_arch_id);
+        old_archetype.transfer(new_archetype, entity_id, tick);
+        for (component_id, comp) in added {
+            new_archetype.add(component_id, comp, tick);
+        }
+        Arc::make_mut(&mut self.entities).insert(entity_id, new_arch_id);
+    }
+
+    pub fn remove<T: Component + 'static>(&mut self, entity_id: EntityId) {
+        if let Some(component_id) = self.components.id::<T>() {
+            self.remove_by_id(entity_id, component_id);
+        }
+    }
+
+    pub fn remove_by_id(&mut self, entity_id: EntityId, component_id: ComponentId) {
+        if self.components.info(component_id).storage() == StorageType::Sparse {
+            if let Some(sparse_set) = self.sparse_sets.get_mut(&component_id) {
+                if sparse_set.contains(entity_id) {
+                    unshare_sparse_set(sparse_set).remove(entity_id);
+                    if self.track_removals {
+                        Arc::make_mut(&mut self.removed).push((self.change_tick, entity_id, component_id));
                     }
                 }
-                *entity_arch_types
-            } else {
+            }
+            return;
+        }
 
-                //archetype doesn't exist, create it
-                // replicate the old archetype
-                let new_arch_id = self.archetypes_types.len();
-                let old_arch = self.archetypes_types[arch_before_add];
-                let mut new_archetype = self.archetypes[old_arch].duplicate(new_arch.clone());
-                new_archetype.insert_comp_storage::<T>(comp);
+        let old_arch_id = self.entities[&entity_id];
+        // does the current arch contain the component
+        if !self.archetypes[old_arch_id].index().contains(component_id) {
+            return;
+        }
+
+        let mut new_arch = self.archetypes[old_arch_id].index().clone();
+        new_arch.remove(component_id);
+        let new_arch_id = self.find_or_create_archetype(new_arch);
+
+        let tick = self.change_tick;
+        let (old_archetype, new_archetype) = self.archetype_pair(old_arch_id, new_arch_id);
+        old_archetype.transfer(new_archetype, entity_id, tick);
+        Arc::make_mut(&mut self.entities).insert(entity_id, new_arch_id);
+        if self.track_removals {
+            Arc::make_mut(&mut self.removed).push((tick, entity_id, component_id));
+        }
+    }
+
+    pub fn query<T: Component + 'static>(&self, entity_id: EntityId) -> Option<&T> {
+        let component_id = self.components.id::<T>()?;
+        if T::STORAGE == StorageType::Sparse {
+            let comp = self.sparse_sets.get(&component_id)?.get_ptr(entity_id)?;
+            return Some(unsafe { &*comp.cast::<T>() });
+        }
+
+        let arch_id = self.entities.get(&entity_id)?;
+        self.archetypes[*arch_id].query::<T>(entity_id, component_id)
+    }
+
+    pub fn query_mut<T: Component + 'static>(&mut self, entity_id: EntityId) -> Option<&mut T> {
+        let component_id = self.components.id::<T>()?;
+        let comp = self.get_ptr_mut(entity_id, component_id)?;
+        Some(unsafe { &mut *comp.cast::<T>() })
+    }
 
-                if let Some(old_archetype_index) = self.archetypes_types.get(arch_before_add) {
-                    self.archetypes[*old_archetype_index].transfer(&mut new_archetype, entity_id);
+    // Same as get_ptr, the storage is no longer shared with a snapshot so the component can be written.
+    // The component counts as changed at the current tick.
+    pub fn get_ptr_mut(&mut self, entity_id: EntityId, component_id: ComponentId) -> Option<*mut u8> {
+        self.touch_entity(entity_id, component_id);
+        self.get_ptr(entity_id, component_id)
+    }
+
+    // Several components of one entity at once, e.g. (&Position, &Velocity)
+    pub fn query_many<D: ReadOnlyQueryData>(&self, entity_id: EntityId) -> Option<D::Item<'_>> {
+        unsafe { self.query_many_unchecked::<D>(entity_id) }
+    }
+
+    // Only the written components of this entity count as changed.
+    pub fn query_many_mut<D: QueryData>(&mut self, entity_id: EntityId) -> Option<D::Item<'_>> {
+        // nothing is stamped when the entity doesn't match
+        if !self.matches_many::<D>(entity_id) {
+            return None;
+        }
+        for task_type in D::task_types() {
+            if let TaskType::CompMut(type_id) = task_type {
+                if let Some(component_id) = self.components.id_from_type(type_id) {
+                    self.touch_entity(entity_id, component_id);
                 }
+            }
+        }
+        unsafe { self.query_many_unchecked::<D>(entity_id) }
+    }
 
-                self.archetypes.push(new_archetype);
-                self.set_all_contained(&new_arch, new_arch_id);
-                self.archetypes_types.insert(new_arch, new_arch_id);
-                new_arch_id
-            };
+    fn matches_many<D: QueryData>(&self, entity_id: EntityId) -> bool {
+        let Some(archetype) = self.archetype_of(entity_id) else {
+            return false;
+        };
+        D::matches_archetype(self, archetype.index()) && D::column(self, archetype).is_some_and(|column| D::contains(&column, entity_id))
+    }
 
-            // remove it from the old archetype
-            self.archetypes[self.entities[&entity_id]].remove(entity_id);
+    unsafe fn query_many_unchecked<D: QueryData>(&self, entity_id: EntityId) -> Option<D::Item<'_>> {
+        check_access(&D::task_types());
 
-            // update the archetypes of the entity
-            self.entities.insert(entity_id, new_arch_id);
-        } else {
-            self.archetypes[self.entities[&entity_id]].update(entity_id, comp);
+        let archetype = self.archetype_of(entity_id)?;
+        if !D::matches_archetype(self, archetype.index()) {
+            return None;
+        }
+
+        let column = D::column(self, archetype)?;
+        if !D::contains(&column, entity_id) {
+            return None;
         }
+        Some(D::item(&column, entity_id, archetype.row(entity_id)?))
     }
 
-    pub fn remove<T: Component + 'static>(&mut self, entity_id: EntityId) {
-        let old_archetype_index = self.entities[&entity_id];
-        if let Some(old_archetype) = self.archetypes.get(old_archetype_index) {
-            let mut new_arch_types = old_archetype.index().clone();
-            new_arch_types.remove::<T>();
-            // does the current arch contain the component
-            let arch_target_index = if let Some(entity_arch_types) = self.archetypes_types.get(&new_arch_types) {
-                *entity_arch_types
-            } else {
-                self.archetypes_types.insert(new_arch_types.clone(), self.archetypes.len());
-                let mut new_arch = old_archetype.duplicate(new_arch_types.clone());
-                new_arch.remove_comp_storage::<T>();
-                self.archetypes.push(new_arch);
-                let new_arch_id = self.archetypes.len() - 1;
-                self.set_all_contained(&new_arch_types, new_arch_id);
-                new_arch_id
+    pub fn get_ptr(&self, entity_id: EntityId, component_id: ComponentId) -> Option<*mut u8> {
+        if self.components.get_info(component_id)?.storage() == StorageType::Sparse {
+            return self.sparse_sets.get(&component_id)?.get_ptr(entity_id);
+        }
+
+        let archetype = self.archetype_of(entity_id)?;
+        if !archetype.index().contains(component_id) {
+            return None;
+        }
+
+        // tags don't have any byte
+        if self.components.info(component_id).is_tag() {
+            return Some(std::ptr::NonNull::<u8>::dangling().as_ptr());
+        }
+
+        let column = archetype.column(component_id)?;
+        Some(column.get_ptr(archetype.row(entity_id)?))
+    }
+
+    // Query on component ids rather than types, used for components defined at runtime.
+    pub fn fetch_dynamic(&mut self, components: &[ComponentId]) -> DynamicQuery<'_> {
+        let mut chunks = vec![];
+        if components.iter().any(|component_id| self.components.get_info(*component_id).is_none()) {
+            return DynamicQuery::new(components.to_vec(), chunks);
+        }
+
+        let (sparse, table): (Vec<ComponentId>, Vec<ComponentId>) = components.iter()
+            .partition(|component_id| self.components.info(**component_id).storage() == StorageType::Sparse);
+        if sparse.iter().any(|component_id| !self.sparse_sets.contains_key(component_id)) {
+            return DynamicQuery::new(components.to_vec(), chunks);
+        }
+
+        for component_id in components {
+            self.unshare(*component_id);
+        }
+
+        let sparse_sets = &self.sparse_sets;
+        let registry = &self.components;
+        let change_tick = self.change_tick;
+        for archetype in self.archetypes.iter_mut() {
+            if !table.iter().all(|component_id| archetype.index().contains(*component_id)) {
+                continue;
+            }
+
+            let columns: Option<Vec<DynamicColumn>> = components.iter().map(|component_id| {
+                let info = registry.info(*component_id);
+                let size = info.layout().size();
+                match sparse_sets.get(component_id) {
+                    Some(sparse_set) => Some(DynamicColumn::Sparse(sparse_set.as_ref(), size)),
+                    None => match archetype.column_mut(*component_id) {
+                        Some(column) => Some(DynamicColumn::Table(column.as_ptr(), size, column.ticks_cells().as_ptr())),
+                        // tags don't have a column
+                        None if info.is_tag() => Some(DynamicColumn::Table(std::ptr::NonNull::<u8>::dangling().as_ptr(), size, std::ptr::null())),
+                        None => None,
+                    }
+                }
+            }).collect();
+            let Some(columns) = columns else {
+                continue;
             };
 
-            if old_archetype_index != arch_target_index {
+            let archetype: &Archetype = archetype;
+            chunks.push(DynamicChunk::new(archetype.indices(), columns, change_tick));
+        }
 
-                // Ensure that we have two distinct indices
-                let (old_archetype, new_archetype) = if old_archetype_index < arch_target_index {
-                    let (first_part, second_part) = self.archetypes.split_at_mut(arch_target_index);
-                    let old_archetype = &mut first_part[old_archetype_index];
-                    let archetype = &mut second_part[0];
-                    (old_archetype, archetype)
-                } else {
-                    let (first_part, second_part) = self.archetypes.split_at_mut(old_archetype_index);
-                    let archetype = &mut first_part[arch_target_index];
-                    let old_archetype = &mut second_part[0];
-                    (old_archetype, archetype)
-                };
+        DynamicQuery::new(components.to_vec(), chunks)
+    }
 
-                // Get mutable references to archetypes at min_index and max_index
-                old_archetype.transfer(new_archetype, entity_id);
-                old_archetype.remove(entity_id);
+    pub fn fetch_info<T: Component>(&self) -> ArchetypeQuery<'_, T> {
+        self.fetch_info_filtered::<T, ()>()
+    }
 
-                self.entities.insert(entity_id, arch_target_index);
+    pub fn fetch_info_filtered<T: Component, F: QueryFilter>(&self) -> ArchetypeQuery<'_, T> {
+        let mut storages = Vec::new();
+        let mut indices = Vec::new();
+        let component_id = match self.components.id::<T>() {
+            Some(component_id) => component_id,
+            None => return ArchetypeQuery::new(indices, storages, EntityLocator::default(), &self.task_pool),
+        };
+
+        // a sparse set is queried like a single archetype holding every entity with the component
+        let mut locator = EntityLocator::new(None);
+        if T::STORAGE == StorageType::Sparse {
+            if let Some(sparse_set) = self.sparse_sets.get(&component_id) {
+                if let Some(storage) = sparse_set.column().slice::<T>() {
+                    let entities = sparse_set.entities();
+                    for run in self.filtered_runs::<F>(entities, false) {
+                        locator.push(0, EntityRows::Sparse(sparse_set.rows()), indices.len(), run.clone());
+                        indices.push(&entities[run.clone()]);
+                        storages.push(&storage[run]);
+                    }
+                }
             }
+            return ArchetypeQuery::new(indices, storages, locator, &self.task_pool);
         }
+
+        let matched = self.archetypes_contains.get(&component_id).into_iter().flatten().copied()
+            .filter(|index| F::matches_archetype(self, self.archetypes[*index].index()));
+        self.table_query::<T, F>(component_id, matched)
     }
 
-    pub fn query<T: Component + 'static>(&self, entity_id: EntityId) -> Option<&T> {
-        if let Some(arch_id) = self.entities.get(&entity_id) {
-            return self.archetypes[*arch_id].query::<T>(entity_id);
+    // Same as fetch_info_filtered, only the archetypes created since the last fetch with the state are matched.
+    pub fn fetch_info_cached<T: Component, F: QueryFilter>(&self, state: &mut QueryState<&T, F>) -> ArchetypeQuery<'_, T> {
+        let component_id = match self.components.id::<T>() {
+            Some(component_id) if T::STORAGE == StorageType::Table => component_id,
+            _ => return self.fetch_info_filtered::<T, F>(),
+        };
+        state.update(self);
+        self.table_query::<T, F>(component_id, state.matched().iter().copied())
+    }
+
+    fn table_query<T: Component, F: QueryFilter>(&self, component_id: ComponentId, matched: impl Iterator<Item=usize>) -> ArchetypeQuery<'_, T> {
+        let mut storages = Vec::new();
+        let mut indices = Vec::new();
+        let mut locator = EntityLocator::new(Some(&self.entities));
+        for index in matched {
+            let archetype = &self.archetypes[index];
+            if let Some(storage) = archetype.storage::<T>(component_id) {
+                let entities = archetype.indices();
+                for run in self.filtered_runs::<F>(entities, true) {
+                    locator.push(index, EntityRows::Table(archetype.rows()), indices.len(), run.clone());
+                    indices.push(&entities[run.clone()]);
+                    storages.push(&storage[run]);
+                }
+            }
         }
-        None
+
+        ArchetypeQuery::new(indices, storages, locator, &self.task_pool)
+    }
+
+    pub fn fetch_info_mut<T: Component>(&mut self) -> ArchetypeQueryMut<'_, T> {
+        self.fetch_info_filtered_mut::<T, ()>()
     }
 
-    pub fn fetch_info<T: Component>(&self) -> ArchetypeQuery<T> {
-        let type_id = TypeId::of::<T>();
+    pub fn fetch_info_filtered_mut<T: Component, F: QueryFilter>(&mut self) -> ArchetypeQueryMut<'_, T> {
         let mut storages = Vec::new();
         let mut indices = Vec::new();
-        if let Some(index_for_storage) = self.archetypes_contains.get(&type_id) {
-            for index in index_for_storage {
-                if let Some(storage) = self.archetypes[*index].storage::<T>() {
-                    indices.push(self.archetypes[*index].indices());
-                    storages.push(storage);
+        let mut ticks = Vec::new();
+        let change_tick = self.change_tick;
+        let component_id = match self.components.id::<T>() {
+            Some(component_id) => component_id,
+            None => return ArchetypeQueryMut::new(indices, storages, ticks, EntityLocator::default(), change_tick, &self.task_pool),
+        };
+        self.unshare(component_id);
+
+        if T::STORAGE == StorageType::Sparse {
+            let runs = match self.sparse_sets.get(&component_id) {
+                Some(sparse_set) => self.filtered_runs::<F>(sparse_set.entities(), false),
+                None => vec![],
+            };
+
+            let mut locator = EntityLocator::new(None);
+            if let Some(sparse_set) = self.sparse_sets.get_mut(&component_id) {
+                let (rows, entities, column) = unshare_sparse_set(sparse_set).rows_entities_and_column_mut();
+                if let Some((storage, column_ticks)) = column.slice_and_ticks_mut::<T>() {
+                    for (run, storage) in runs.iter().zip(split_runs_mut(storage, &runs)) {
+                        locator.push(0, EntityRows::Sparse(rows), indices.len(), run.clone());
+                        indices.push(&entities[run.clone()]);
+                        storages.push(storage);
+                        ticks.push(&column_ticks[run.clone()]);
+                    }
                 }
             }
+            return ArchetypeQueryMut::new(indices, storages, ticks, locator, change_tick, &self.task_pool);
         }
 
-        ArchetypeQuery::new(indices, storages)
+        // the rows to keep in each archetype, found before borrowing the storages mutably
+        let archetype_runs: Vec<(usize, Vec<Range<usize>>)> = self.archetypes_contains.get(&component_id).into_iter().flatten()
+            .filter(|index| F::matches_archetype(self, self.archetypes[**index].index()))
+            .map(|index| (*index, self.filtered_runs::<F>(self.archetypes[*index].indices(), true)))
+            .collect();
+        self.table_query_mut::<T>(component_id, archetype_runs)
+    }
+
+    // Same as fetch_info_filtered_mut, see fetch_info_cached
+    pub fn fetch_info_cached_mut<T: Component, F: QueryFilter>(&mut self, state: &mut QueryState<&mut T, F>) -> ArchetypeQueryMut<'_, T> {
+        let component_id = match self.components.id::<T>() {
+            Some(component_id) if T::STORAGE == StorageType::Table => component_id,
+            _ => return self.fetch_info_filtered_mut::<T, F>(),
+        };
+        self.unshare(component_id);
+        state.update(self);
+
+        let archetype_runs: Vec<(usize, Vec<Range<usize>>)> = state.matched().iter()
+            .map(|index| (*index, self.filtered_runs::<F>(self.archetypes[*index].indices(), true)))
+            .collect();
+        self.table_query_mut::<T>(component_id, archetype_runs)
     }
 
-    pub fn fetch_info_mut<T: Component>(&mut self) -> ArchetypeQueryMut<T> {
-        let type_id = TypeId::of::<T>();
+    fn table_query_mut<T: Component>(&mut self, component_id: ComponentId, archetype_runs: Vec<(usize, Vec<Range<usize>>)>) -> ArchetypeQueryMut<'_, T> {
         let mut storages = Vec::new();
         let mut indices = Vec::new();
+        let mut ticks = Vec::new();
+        let mut locator = EntityLocator::new(Some(&self.entities));
 
         // Get the raw pointer to the archetypes array.
         let archetypes_ptr = self.archetypes.as_mut_ptr();
 
-        if let Some(index_for_storage) = self.archetypes_contains.get(&type_id) {
-            // Reserve space to avoid reallocations that could invalidate pointers.
-            storages.reserve(index_for_storage.len());
-            indices.reserve(index_for_storage.len());
+        // Reserve space to avoid reallocations that could invalidate pointers.
+        storages.reserve(archetype_runs.len());
+        indices.reserve(archetype_runs.len());
+
+        for (index, runs) in archetype_runs {
+            unsafe {
+                // Access each archetype by index using the raw pointer.
+                let archetype = &mut *archetypes_ptr.add(index);
+
+                // Directly access indices function and convert to raw pointer and back to ref.
+                let indices_ptr = archetype.indices() as *const Vec<EntityId>;
+                let entities = &*indices_ptr;
+                let rows = &*(archetype.rows() as *const HashMap<EntityId, usize>);
+
+                if let Some((storage, column_ticks)) = archetype.storage_and_ticks_mut::<T>(component_id) {
+                    for (run, storage) in runs.iter().zip(split_runs_mut(storage, &runs)) {
+                        locator.push(index, EntityRows::Table(rows), indices.len(), run.clone());
+                        indices.push(&entities[run.clone()]);
+                        storages.push(storage);
+                        // tags don't have ticks
+                        ticks.push(column_ticks.get(run.clone()).unwrap_or_default());
+                    }
+                }
+            }
+        }
+
+        ArchetypeQueryMut::new(indices, storages, ticks, locator, self.change_tick, &self.task_pool)
+    }
+
+    pub fn fetch_query<D: QueryData, F: QueryFilter>(&mut self) -> ArchetypeTupleQuery<'_, D> {
+        for task_type in D::task_types() {
+            if let TaskType::CompMut(type_id) = task_type {
+                if let Some(component_id) = self.components.id_from_type(type_id) {
+                    self.unshare(component_id);
+                }
+            }
+        }
+        unsafe { self.fetch_query_unchecked::<D, F>() }
+    }
+
+    pub fn fetch_query_read<D: ReadOnlyQueryData, F: QueryFilter>(&self) -> ArchetypeTupleQuery<'_, D> {
+        unsafe { self.fetch_query_unchecked::<D, F>() }
+    }
+
+    // Same as fetch_query, see fetch_info_cached
+    pub fn fetch_query_cached<D: QueryData, F: QueryFilter>(&mut self, state: &mut QueryState<D, F>) -> ArchetypeTupleQuery<'_, D> {
+        for task_type in D::task_types() {
+            if let TaskType::CompMut(type_id) = task_type {
+                if let Some(component_id) = self.components.id_from_type(type_id) {
+                    self.unshare(component_id);
+                }
+            }
+        }
+        state.update(self);
+        unsafe { self.tuple_query::<D, F>(state.matched().iter().copied()) }
+    }
 
-            for &index in index_for_storage.iter() {
-                unsafe {
-                    // Access each archetype by index using the raw pointer.
-                    let archetype = &mut *archetypes_ptr.add(index);
-                    let storage_ptr = archetype.storage_mut::<T>();
+    pub fn fetch_query_read_cached<D: ReadOnlyQueryData, F: QueryFilter>(&self, state: &mut QueryState<D, F>) -> ArchetypeTupleQuery<'_, D> {
+        state.update(self);
+        unsafe { self.tuple_query::<D, F>(state.matched().iter().copied()) }
+    }
 
-                    if let Some(storage) = storage_ptr {
-                        // Push the mutable reference converted from a raw pointer.
-                        storages.push(&mut *(storage as *mut _));
+    // The query can hand out mutable references, the caller must have exclusive access to the components.
+    unsafe fn fetch_query_unchecked<D: QueryData, F: QueryFilter>(&self) -> ArchetypeTupleQuery<'_, D> {
+        let matched = self.archetypes.iter().enumerate()
+            .filter(|(_, archetype)| D::matches_archetype(self, archetype.index()) && F::matches_archetype(self, archetype.index()))
+            .map(|(index, _)| index);
+        self.tuple_query::<D, F>(matched)
+    }
 
-                        // Directly access indices function and convert to raw pointer and back to ref.
-                        let indices_ptr = archetype.indices() as *const _;
-                        indices.push(&*indices_ptr);
-                    }
+    unsafe fn tuple_query<D: QueryData, F: QueryFilter>(&self, matched: impl Iterator<Item=usize>) -> ArchetypeTupleQuery<'_, D> {
+        check_access(&D::task_types());
+
+        let mut chunks = vec![];
+        let mut locator = EntityLocator::new(Some(&self.entities));
+        for index in matched {
+            let archetype = &self.archetypes[index];
+            if let Some(column) = D::column(self, archetype) {
+                let entities = archetype.indices();
+                for run in self.filtered_runs::<F>(entities, true) {
+                    locator.push(index, EntityRows::Table(archetype.rows()), chunks.len(), run.clone());
+                    chunks.push(QueryChunk::new(&entities[run.clone()], run.start, column));
                 }
             }
         }
 
-        ArchetypeQueryMut::new(indices, storages)
+        ArchetypeTupleQuery::new(chunks, locator, &self.task_pool)
     }
 
-    fn set_all_contained(&mut self, archetype_index: &ArchetypeIndex, new_index: usize) {
-        for type_index in archetype_index.types() {
-            if self.archetypes_contains.get(&type_index).is_none() {
-                self.archetypes_contains.insert(*type_index, HashSet::new());
+    // Splits the rows in runs of consecutive entities accepted by the filter,
+    // so the query can keep handing out contiguous slices.
+    fn filtered_runs<F: QueryFilter>(&self, entities: &[EntityId], archetype_matched: bool) -> Vec<Range<usize>> {
+        if F::IS_EMPTY || (archetype_matched && !F::IS_SPARSE) {
+            return std::iter::once(0..entities.len()).collect();
+        }
+
+        let mut runs: Vec<Range<usize>> = vec![];
+        for (row, entity_id) in entities.iter().enumerate() {
+            let matches = (archetype_matched || F::matches_archetype(self, self.archetypes[self.entities[entity_id]].index()))
+                && F::matches_entity(self, *entity_id);
+            if !matches {
+                continue;
             }
 
-            if let Some(indices) = self.archetypes_contains.get_mut(&type_index) {
-                indices.insert(new_index);
+            match runs.last_mut() {
+                Some(run) if run.end == row => run.end += 1,
+                _ => runs.push(row..row + 1),
             }
         }
+        runs
     }
-}
\ No newline at end of file
+
+    // Copies the storages of the component a snapshot shares, before handing out pointers to write them.
+    // The rows count as changed when the query hands them out, see ArchetypeQueryMut.
+    fn unshare(&mut self, component_id: ComponentId) {
+        if let Some(sparse_set) = self.sparse_sets.get_mut(&component_id) {
+            unshare_sparse_set(sparse_set);
+        }
+
+        if let Some(archetypes) = self.archetypes_contains.get(&component_id) {
+            for index in archetypes {
+                self.archetypes[*index].column_mut(component_id);
+            }
+        }
+    }
+
+    // Unshares the component and counts the entity's one as changed.
+    fn touch_entity(&mut self, entity_id: EntityId, component_id: ComponentId) {
+        self.unshare(component_id);
+        let tick = self.change_tick;
+        if let Some(sparse_set) = self.sparse_sets.get_mut(&component_id) {
+            unshare_sparse_set(sparse_set).set_changed(entity_id, tick);
+        } else if let Some(arch_id) = self.entities.get(&entity_id) {
+            self.archetypes[*arch_id].set_changed(entity_id, component_id, tick);
+        }
+    }
+
+    fn find_or_create_archetype(&mut self, archetype_index: ArchetypeIndex) -> usize {
+        if let Some(arch_id) = self.archetypes_types.get(&archetype_index) {
+            return *arch_id;
+        }
+
+        //archetype doesn't exist, create it
+        let new_arch_id = self.archetypes.len();
+        self.archetypes.push(Archetype::new(archetype_index.clone(), &self.components));
+        self.set_all_contained(&archetype_index, new_arch_id);
+        self.archetypes_types.insert(archetype_index, new_arch_id);
+        new_arch_id
+    }
+
+    // Ensure that we have two distinct archetypes borrowed at once
+    fn archetype_pair(&mut self, first: usize, second: usize) -> (&mut Archetype, &mut Archetype) {
+        assert_ne!(first, second);
+        if first < second {
+            let (first_part, second_part) = self.archetypes.split_at_mut(second);
+            (&mut first_part[first], &mut second_part[0])
+        } else {
+            let (first_part, second_part) = self.archetypes.split_at_mut(first);
+            (&mut second_part[0], &mut first_part[second])
+        }
+    }
+
+    fn set_all_contained(&mut self, archetype_index: &ArchetypeIndex, new_index: usize) {
+        for component_id in archetype_index.components() {
+            self.archetypes_contains.entry(*component_id).or_default().insert(new_index);
+        }
+    }
+}
+
+fn unshare_sparse_set(sparse_set: &mut Arc<SparseSet>) -> &mut SparseSet {
+    if Arc::get_mut(sparse_set).is_none() {
+        *sparse_set = Arc::new(sparse_set.try_clone().expect("only cloneable sparse sets are shared"));
+    }
+    Arc::get_mut(sparse_set).unwrap()
+}
+
+fn split_runs_mut<'a, T>(mut storage: &'a mut [T], runs: &[Range<usize>]) -> Vec<&'a mut [T]> {
+    let mut slices = Vec::with_capacity(runs.len());
+    let mut offset = 0;
+    for run in runs {
+        let (_, rest) = std::mem::take(&mut storage).split_at_mut(run.start - offset);
+        let (slice, rest) = rest.split_at_mut(run.len());
+        slices.push(slice);
+        storage = rest;
+        offset = run.end;
+    }
+    slices
+}
+
+// a query can't write a component it also reads or writes somewhere else
+fn check_access(task_types: &[TaskType]) {
+    assert!(!aliases_access(task_types), "a query can't access a component mutably more than once");
+}
+
+// A component written by the query is also read or written by another part of it.
+pub(crate) fn aliases_access(task_types: &[TaskType]) -> bool {
+    task_types.iter().enumerate().any(|(i, task_type)| task_types[i + 1..].iter().any(|other| match (task_type, other) {
+        (TaskType::CompMut(type_id), TaskType::Comp(other_id) | TaskType::CompMut(other_id)) => type_id == other_id,
+        (TaskType::Comp(type_id), TaskType::CompMut(other_id)) => type_id == other_id,
+        _ => false,
+    }))
+}
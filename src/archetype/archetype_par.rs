@@ -0,0 +1,140 @@
+use std::ops::Range;
+use crate::archetype::archetype_query::QueryChunk;
+use crate::archetype::query_data::QueryData;
+use crate::entity::entity::EntityId;
+use crate::schedule::task_pool::TaskPool;
+
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+// Runs a closure on every row of a query from several threads.
+// The rows of each archetype are split in batches of batch_size rows, run on the task pool of the world.
+pub struct ParIter<'q, D: QueryData> {
+    chunks: Vec<QueryChunk<'q, D>>,
+    pool: &'q TaskPool,
+    batch_size: usize,
+    threads: usize,
+}
+
+impl<'q, D: QueryData> ParIter<'q, D> {
+    pub fn new(chunks: Vec<QueryChunk<'q, D>>, pool: &'q TaskPool) -> Self {
+        Self { chunks, pool, batch_size: DEFAULT_BATCH_SIZE, threads: pool.threads() }
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "the batch size can't be 0");
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn for_each(self, f: impl Fn(EntityId, D::Item<'q>) + Sync) {
+        let batches = self.batches();
+        let chunks = SyncChunks(&self.chunks);
+        self.pool.run_batches(batches.len(), self.threads, |batch| {
+            let (chunk_index, rows) = &batches[batch];
+            let chunk = &chunks.get()[*chunk_index];
+            for index in rows.clone() {
+                // the batches don't overlap, so no row is handed out twice
+                if let Some((entity_id, item)) = unsafe { chunk.item(index) } {
+                    f(entity_id, item);
+                }
+            }
+        });
+    }
+
+    // (chunk, rows in the chunk) of each batch, a batch never spans two chunks
+    fn batches(&self) -> Vec<(usize, Range<usize>)> {
+        let mut batches = vec![];
+        for (chunk_index, chunk) in self.chunks.iter().enumerate() {
+            let mut start = 0;
+            while start < chunk.len() {
+                let end = (start + self.batch_size).min(chunk.len());
+                batches.push((chunk_index, start..end));
+                start = end;
+            }
+        }
+        batches
+    }
+}
+
+// The chunks only hold pointers to components, which are Send + Sync.
+struct SyncChunks<'c, 'q, D: QueryData>(&'c [QueryChunk<'q, D>]);
+
+unsafe impl<D: QueryData> Sync for SyncChunks<'_, '_, D> {}
+
+impl<'c, 'q, D: QueryData> SyncChunks<'c, 'q, D> {
+    fn get(&self) -> &'c [QueryChunk<'q, D>] {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::cow_macros::Component;
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Value(u32);
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Marker;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Weight(u32);
+
+    // 10 entities with Value, 4 of them also with Marker, 3 with Weight
+    fn world() -> World {
+        let mut world = World::new();
+        for i in 0..10 {
+            let entity_id = world.create();
+            world.add(entity_id, Value(0));
+            if i % 3 == 0 {
+                world.add(entity_id, Marker);
+            }
+            if i < 3 {
+                world.add(entity_id, Weight(i));
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn batches_split_each_chunk() {
+        let world = world();
+        let comps = world.comps::<Value>();
+        let batches = comps.par_iter().batch_size(2).batches();
+        let lens: Vec<usize> = world.comps::<Value>().chunks().map(|(entities, _)| entities.len()).collect();
+        let expected: Vec<(usize, std::ops::Range<usize>)> = lens.iter().enumerate()
+            .flat_map(|(chunk, len)| (0..*len).step_by(2).map(move |start| (chunk, start..(start + 2).min(*len))))
+            .collect();
+        assert_eq!(batches, expected);
+
+        // a batch larger than the chunks takes them whole
+        let batches = comps.par_iter().batch_size(100).batches();
+        assert_eq!(batches, lens.iter().enumerate().map(|(chunk, len)| (chunk, 0..*len)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn par_iter_mut_writes_every_row_once() {
+        let mut world = world();
+        for batch_size in [1, 3, 1000] {
+            world.comps_mut::<Value>().par_iter_mut().batch_size(batch_size).threads(4).for_each(|_, value| value.0 += 1);
+        }
+        assert!(world.comps::<Value>().iter().all(|(_, value)| value.0 == 3));
+
+        let visited = AtomicUsize::new(0);
+        world.query_tuple_mut::<(&mut Value, &Weight), ()>().par_iter_mut().batch_size(2).for_each(|_, (value, weight)| {
+            value.0 += weight.0;
+            visited.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(visited.load(Ordering::Relaxed), 3);
+        let mut values: Vec<u32> = world.comps::<Value>().iter().map(|(_, value)| value.0).collect();
+        values.sort();
+        assert_eq!(values, [3, 3, 3, 3, 3, 3, 3, 3, 4, 5]);
+    }
+}
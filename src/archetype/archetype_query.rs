@@ -1,86 +1,453 @@
-use crate::archetype::archetype_iter::{ArchetypeQueryIter, ArchetypeQueryIterMut};
+use crate::archetype::archetype_iter::{ArchetypeQueryIter, ArchetypeQueryIterMut, ArchetypeTupleQueryIter};
+use crate::archetype::archetype_par::ParIter;
+use crate::archetype::query_data::{CompColumn, QueryData, ReadOnlyQueryData};
 use crate::component::component::Component;
+use crate::component::tick::{set_changed_cell, ComponentTicks, Tick};
 use crate::entity::entity::EntityId;
+use crate::schedule::task_pool::TaskPool;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum QueryEntityError {
+    // the entity is not matched by the query
+    NoSuchEntity(EntityId),
+    // the entity was asked more than once, it can't be borrowed mutably twice
+    AliasedMutability(EntityId),
+}
+
+impl Display for QueryEntityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryEntityError::NoSuchEntity(entity_id) => write!(f, "the entity {entity_id} is not matched by the query"),
+            QueryEntityError::AliasedMutability(entity_id) => write!(f, "the entity {entity_id} is borrowed mutably more than once"),
+        }
+    }
+}
+
+impl std::error::Error for QueryEntityError {}
+
+// Finds where each entity is stored, the locations must all differ before handing out &mut.
+fn locate_distinct<const N: usize>(entities: [EntityId; N], locate: impl Fn(EntityId) -> Option<(usize, usize)>) -> Result<[(usize, usize); N], QueryEntityError> {
+    let mut locations = [(0, 0); N];
+    for (i, entity_id) in entities.into_iter().enumerate() {
+        let location = locate(entity_id).ok_or(QueryEntityError::NoSuchEntity(entity_id))?;
+        if locations[..i].contains(&location) {
+            return Err(QueryEntityError::AliasedMutability(entity_id));
+        }
+        locations[i] = location;
+    }
+    Ok(locations)
+}
+
+// The rows of the entities in an archetype or a sparse set.
+#[derive(Clone, Copy)]
+pub enum EntityRows<'a> {
+    Table(&'a HashMap<EntityId, usize>),
+    // indexed by entity id
+    Sparse(&'a [Option<usize>]),
+}
+
+impl EntityRows<'_> {
+    fn row(&self, entity_id: EntityId) -> Option<usize> {
+        match self {
+            EntityRows::Table(rows) => rows.get(&entity_id).copied(),
+            EntityRows::Sparse(rows) => rows.get(entity_id as usize).copied().flatten(),
+        }
+    }
+}
+
+// the rows of an archetype and the chunks cut from it as (chunk, rows), in row order
+type MatchedArchetype<'a> = (EntityRows<'a>, Vec<(usize, Range<usize>)>);
+
+// Finds the chunk holding an entity from its archetype and row instead of scanning the chunks.
+#[derive(Default)]
+pub struct EntityLocator<'a> {
+    // entity to archetype, None when the chunks are cut from a sparse set
+    archetypes: Option<&'a HashMap<EntityId, usize>>,
+    matched: HashMap<usize, MatchedArchetype<'a>>,
+}
+
+impl<'a> EntityLocator<'a> {
+    pub fn new(archetypes: Option<&'a HashMap<EntityId, usize>>) -> Self {
+        Self { archetypes, matched: HashMap::new() }
+    }
+
+    // The chunk holds the rows run of the archetype, a sparse set is archetype 0.
+    pub fn push(&mut self, archetype: usize, rows: EntityRows<'a>, chunk: usize, run: Range<usize>) {
+        self.matched.entry(archetype).or_insert_with(|| (rows, vec![])).1.push((chunk, run));
+    }
+
+    // (chunk, index in the chunk) of the entity
+    pub fn locate(&self, entity_id: EntityId) -> Option<(usize, usize)> {
+        let archetype = match self.archetypes {
+            Some(archetypes) => *archetypes.get(&entity_id)?,
+            None => 0,
+        };
+        let (rows, chunks) = self.matched.get(&archetype)?;
+        let row = rows.row(entity_id)?;
+        // the last chunk starting at or before the row, a filter may have left the row out
+        let (chunk, run) = &chunks[..chunks.partition_point(|(_, run)| run.start <= row)].last()?;
+        run.contains(&row).then_some((*chunk, row - run.start))
+    }
+}
 
 pub struct ArchetypeQuery<'a, T: Component + 'static> {
-    indices: Vec<&'a Vec<EntityId>>,
-    storages: Vec<&'a Vec<T>>,
+    indices: Vec<&'a [EntityId]>,
+    storages: Vec<&'a [T]>,
+    locator: EntityLocator<'a>,
+    pool: &'a TaskPool,
 }
 
 impl<'a, T: Component + 'static> ArchetypeQuery<'a, T> {
-    pub fn new(indices: Vec<&'a Vec<EntityId>>,
-               storages: Vec<&'a Vec<T>>) -> Self {
-        Self { indices, storages }
+    pub fn new(indices: Vec<&'a [EntityId]>,
+               storages: Vec<&'a [T]>,
+               locator: EntityLocator<'a>,
+               pool: &'a TaskPool) -> Self {
+        Self { indices, storages, locator, pool }
     }
 
-    pub fn iter(&self) -> ArchetypeQueryIter<T> {
+    pub fn iter(&self) -> ArchetypeQueryIter<'_, T> {
         ArchetypeQueryIter::new(self)
     }
 
-    pub fn indices(&self) -> &Vec<&'a Vec<EntityId>> {
+    pub fn indices(&self) -> &Vec<&'a [EntityId]> {
         &self.indices
     }
 
-    pub fn storage(&self) -> &Vec<&'a Vec<T>> {
+    pub fn storage(&self) -> &Vec<&'a [T]> {
         &self.storages
     }
 
-    pub fn query(&self, entity_query: EntityId) -> Option<&T> {
-        for (i, indices) in self.indices.iter().enumerate() {
-            for (j, entity) in indices.iter().enumerate() {
-                if *entity == entity_query {
-                    return Some(&self.storages[i][j]);
-                }
-            }
-        }
+    // The entities and components of each archetype as contiguous slices.
+    pub fn chunks(&self) -> impl Iterator<Item=(&'a [EntityId], &'a [T])> + '_ {
+        self.indices.iter().copied().zip(self.storages.iter().copied())
+    }
 
-        None
+    pub fn par_iter(&self) -> ParIter<'_, &T> {
+        let chunks = self.indices.iter().zip(self.storages.iter())
+            .map(|(indices, storage)| QueryChunk::new(indices, 0, CompColumn::from_slice(storage)))
+            .collect();
+        ParIter::new(chunks, self.pool)
+    }
+
+    pub fn query(&self, entity_query: EntityId) -> Option<&T> {
+        let (i, j) = self.locator.locate(entity_query)?;
+        Some(&self.storages[i][j])
     }
 }
 
+// The rows are stamped as changed when they are handed out mutably, not when the query is fetched.
 pub struct ArchetypeQueryMut<'a, T: Component + 'static> {
-    indices: Vec<&'a Vec<EntityId>>,
-    storages: Vec<&'a mut Vec<T>>,
+    indices: Vec<&'a [EntityId]>,
+    storages: Vec<&'a mut [T]>,
+    // aligned with the storages, empty for tags
+    ticks: Vec<&'a [Cell<ComponentTicks>]>,
+    locator: EntityLocator<'a>,
+    // the tick the rows handed out are stamped with
+    change_tick: Tick,
+    pool: &'a TaskPool,
 }
 
 impl<'a, T: Component + 'static> ArchetypeQueryMut<'a, T> {
-    pub fn new(indices: Vec<&'a Vec<EntityId>>, storages: Vec<&'a mut Vec<T>>) -> Self {
-        Self { indices, storages }
+    pub fn new(indices: Vec<&'a [EntityId]>, storages: Vec<&'a mut [T]>, ticks: Vec<&'a [Cell<ComponentTicks>]>, locator: EntityLocator<'a>, change_tick: Tick, pool: &'a TaskPool) -> Self {
+        Self { indices, storages, ticks, locator, change_tick, pool }
     }
 
     pub fn iter_mut(&mut self) -> ArchetypeQueryIterMut<'a, T> {
         ArchetypeQueryIterMut::new(self)
     }
 
-    pub fn indices(&self) -> &Vec<&'a Vec<EntityId>> {
+    pub fn indices(&self) -> &Vec<&'a [EntityId]> {
         &self.indices
     }
 
-    pub fn storages(&mut self) -> &mut Vec<&'a mut Vec<T>> {
+    // Every row counts as changed, chunks_mut only stamps the archetypes it reaches.
+    pub fn storages(&mut self) -> &mut Vec<&'a mut [T]> {
+        for ticks in &self.ticks {
+            set_rows_changed(ticks, self.change_tick);
+        }
         &mut self.storages
     }
 
+    pub fn chunks(&self) -> impl Iterator<Item=(&'a [EntityId], &[T])> + '_ {
+        self.indices.iter().copied().zip(self.storages.iter().map(|storage| &**storage))
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item=(&'a [EntityId], &mut [T])> + '_ {
+        let (ticks, change_tick) = (&self.ticks, self.change_tick);
+        self.indices.iter().copied().zip(self.storages.iter_mut()).enumerate().map(move |(storage, (indices, rows))| {
+            set_rows_changed(ticks[storage], change_tick);
+            (indices, &mut **rows)
+        })
+    }
+
+    pub fn par_iter_mut(&mut self) -> ParIter<'_, &mut T> {
+        let change_tick = self.change_tick;
+        let chunks = self.indices.iter().zip(self.storages.iter_mut()).zip(self.ticks.iter())
+            .map(|((indices, storage), ticks)| QueryChunk::new(indices, 0, CompColumn::from_mut_slice(storage, ticks, change_tick)))
+            .collect();
+        ParIter::new(chunks, self.pool)
+    }
+
     pub fn query(&self, entity_query: EntityId) -> Option<&T> {
-        for (i, indices) in self.indices.iter().enumerate() {
-            for (j, entity) in indices.iter().enumerate() {
-                if *entity == entity_query {
-                    return Some(&self.storages[i][j]);
-                }
-            }
+        let (i, j) = self.location(entity_query)?;
+        Some(&self.storages[i][j])
+    }
+
+    pub fn query_mut(&mut self, entity_query: EntityId) -> Option<&mut T> {
+        let (i, j) = self.location(entity_query)?;
+        Some(self.row_mut(i, j))
+    }
+
+    // The components of several distinct entities at once, e.g. both sides of a contact.
+    pub fn get_many_mut<const N: usize>(&mut self, entities: [EntityId; N]) -> Result<[&mut T; N], QueryEntityError> {
+        let locations = locate_distinct(entities, |entity_id| self.location(entity_id))?;
+        // the locations differ so the references don't overlap
+        Ok(unsafe { self.rows_mut(locations) })
+    }
+
+    // The components at each (storage, row), stamped as changed. The caller must make sure the rows differ.
+    pub(crate) unsafe fn rows_mut<const N: usize>(&mut self, rows: [(usize, usize); N]) -> [&mut T; N] {
+        for (storage, row) in rows {
+            self.set_changed(storage, row);
         }
+        // one base pointer per storage, borrowing a storage again would invalidate the rows taken from it
+        let mut bases = [std::ptr::null_mut::<T>(); N];
+        for i in 0..N {
+            let storage = rows[i].0;
+            bases[i] = match rows[..i].iter().position(|(other, _)| *other == storage) {
+                Some(j) => bases[j],
+                None => self.storages[storage].as_mut_ptr(),
+            };
+        }
+        std::array::from_fn(|i| &mut *bases[i].add(rows[i].1))
+    }
 
-        None
+    // The component at (storage, row), stamped as changed.
+    pub(crate) fn row_mut(&mut self, storage: usize, row: usize) -> &mut T {
+        self.set_changed(storage, row);
+        &mut self.storages[storage][row]
     }
 
-    pub fn query_mut(&mut self, entity_query: EntityId) -> Option<&mut T> {
-        for (i, indices) in self.indices.iter().enumerate() {
-            for (j, entity) in indices.iter().enumerate() {
-                if *entity == entity_query {
-                    return Some(&mut self.storages[i][j]);
-                }
+    // Stamps the row before it's handed out mutably.
+    pub(crate) fn set_changed(&self, storage: usize, row: usize) {
+        if let Some(row_ticks) = self.ticks[storage].get(row) {
+            set_changed_cell(row_ticks, self.change_tick);
+        }
+    }
+
+    // (storage, row) of the entity
+    fn location(&self, entity_query: EntityId) -> Option<(usize, usize)> {
+        self.locator.locate(entity_query)
+    }
+}
+fn set_rows_changed(ticks: &[Cell<ComponentTicks>], change_tick: Tick) {
+    for row_ticks in ticks {
+        set_changed_cell(row_ticks, change_tick);
+    }
+}
+
+// The rows of one archetype matched by a tuple query.
+pub struct QueryChunk<'a, D: QueryData> {
+    entities: &'a [EntityId],
+    // row of the first entity in the archetype
+    first_row: usize,
+    column: D::Column,
+}
+
+impl<D: QueryData> Clone for QueryChunk<'_, D> {
+    fn clone(&self) -> Self {
+        Self { entities: self.entities, first_row: self.first_row, column: self.column }
+    }
+}
+
+impl<'a, D: QueryData> QueryChunk<'a, D> {
+    pub fn new(entities: &'a [EntityId], first_row: usize, column: D::Column) -> Self {
+        Self { entities, first_row, column }
+    }
+
+    pub fn entities(&self) -> &'a [EntityId] {
+        self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    // The item of the entity at index in the chunk, None if it misses a sparse component.
+    // The caller must make sure the item is not borrowed mutably elsewhere.
+    pub(crate) unsafe fn item<'q>(&self, index: usize) -> Option<(EntityId, D::Item<'q>)> {
+        let entity_id = self.entities[index];
+        if !D::contains(&self.column, entity_id) {
+            return None;
+        }
+        Some((entity_id, D::item(&self.column, entity_id, self.first_row + index)))
+    }
+}
+
+pub struct ArchetypeTupleQuery<'a, D: QueryData> {
+    chunks: Vec<QueryChunk<'a, D>>,
+    locator: EntityLocator<'a>,
+    pool: &'a TaskPool,
+}
+
+impl<'a, D: QueryData> ArchetypeTupleQuery<'a, D> {
+    pub fn new(chunks: Vec<QueryChunk<'a, D>>, locator: EntityLocator<'a>, pool: &'a TaskPool) -> Self {
+        Self { chunks, locator, pool }
+    }
+
+    pub fn chunks(&self) -> &Vec<QueryChunk<'a, D>> {
+        &self.chunks
+    }
+
+    pub fn iter_mut(&mut self) -> ArchetypeTupleQueryIter<'_, 'a, D> {
+        ArchetypeTupleQueryIter::new(&self.chunks)
+    }
+
+    pub fn query_mut(&mut self, entity_query: EntityId) -> Option<D::Item<'_>> {
+        unsafe { self.find(entity_query) }
+    }
+
+    pub fn par_iter_mut(&mut self) -> ParIter<'_, D> {
+        ParIter::new(self.chunks.clone(), self.pool)
+    }
+
+    // Same as CompsMut::get_many_mut, for the items of several distinct entities.
+    pub fn get_many_mut<const N: usize>(&mut self, entities: [EntityId; N]) -> Result<[D::Item<'_>; N], QueryEntityError> {
+        let locations = locate_distinct(entities, |entity_id| self.location(entity_id))?;
+        Ok(locations.map(|(chunk, index)| unsafe { self.chunks[chunk].item(index).unwrap().1 }))
+    }
+
+    unsafe fn find<'q>(&self, entity_query: EntityId) -> Option<D::Item<'q>> {
+        let (chunk, index) = self.location(entity_query)?;
+        self.chunks[chunk].item(index).map(|(_, item)| item)
+    }
+
+    // (chunk, index in the chunk) of the entity, None if it misses a sparse component
+    fn location(&self, entity_query: EntityId) -> Option<(usize, usize)> {
+        let (chunk, index) = self.locator.locate(entity_query)?;
+        D::contains(&self.chunks[chunk].column, entity_query).then_some((chunk, index))
+    }
+}
+
+impl<'a, D: ReadOnlyQueryData> ArchetypeTupleQuery<'a, D> {
+    pub fn iter(&self) -> ArchetypeTupleQueryIter<'_, 'a, D> {
+        ArchetypeTupleQueryIter::new(&self.chunks)
+    }
+
+    pub fn query(&self, entity_query: EntityId) -> Option<D::Item<'_>> {
+        unsafe { self.find(entity_query) }
+    }
+
+    pub fn par_iter(&self) -> ParIter<'_, D> {
+        ParIter::new(self.chunks.clone(), self.pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archetype::archetype_filter::{With, Without};
+    use crate::archetype::archetype_query::QueryEntityError;
+    use crate::cow_macros::Component;
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Pos(i32);
+
+    #[derive(Component)]
+    struct Frozen;
+
+    #[derive(Component)]
+    struct Named;
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(storage = "sparse")]
+    struct Target(i32);
+
+    #[test]
+    fn get_many_mut_rejects_aliases() {
+        let mut world = World::new();
+        let (a, b, frozen) = (world.create(), world.create(), world.create());
+        world.add(a, Pos(1));
+        world.add(b, Pos(2));
+        world.add(frozen, Pos(3));
+        world.add(frozen, Frozen);
+
+        let mut positions = world.comps_filtered_mut::<Pos, Without<Frozen>>();
+        let [pos_a, pos_b] = positions.get_many_mut([a, b]).unwrap();
+        std::mem::swap(pos_a, pos_b);
+        assert_eq!(positions.query(a), Some(&Pos(2)));
+        assert_eq!(positions.get_many_mut([a, a]).err(), Some(QueryEntityError::AliasedMutability(a)));
+        assert_eq!(positions.get_many_mut([a, frozen]).err(), Some(QueryEntityError::NoSuchEntity(frozen)));
+    }
+
+    #[test]
+    fn tuple_get_many_mut_rejects_aliases() {
+        let mut world = World::new();
+        let (a, b, untargeted) = (world.create(), world.create(), world.create());
+        for (entity_id, value) in [(a, 1), (b, 2), (untargeted, 3)] {
+            world.add(entity_id, Pos(value));
+        }
+        world.add(a, Target(10));
+        world.add(b, Target(20));
+
+        let mut query = world.query_tuple_mut::<(&mut Pos, &Target), ()>();
+        let [(pos_a, target_a), (pos_b, target_b)] = query.get_many_mut([a, b]).unwrap();
+        pos_a.0 += target_b.0;
+        pos_b.0 += target_a.0;
+        assert_eq!(query.get_many_mut([b, b]).err(), Some(QueryEntityError::AliasedMutability(b)));
+        assert_eq!(query.get_many_mut([a, untargeted]).err(), Some(QueryEntityError::NoSuchEntity(untargeted)));
+        assert_eq!((world.query::<Pos>(a), world.query::<Pos>(b)), (Some(&Pos(21)), Some(&Pos(12))));
+    }
+
+    #[test]
+    fn entities_are_found_in_the_chunks_a_filter_splits() {
+        let mut world = World::new();
+        let entities: Vec<u32> = (0..12).map(|_| world.create()).collect();
+        for (i, entity_id) in entities.iter().copied().enumerate() {
+            world.add(entity_id, Pos(entity_id as i32));
+            if i % 2 == 0 {
+                world.add(entity_id, Named);
+            }
+            if i % 3 == 0 {
+                world.add(entity_id, Target(entity_id as i32));
             }
         }
+        // (entity, kept by Without<Target>, named)
+        let kept: Vec<(u32, bool, bool)> = entities.iter().enumerate().map(|(i, entity_id)| (*entity_id, i % 3 != 0, i % 2 == 0)).collect();
+        let expected = |entity_id: u32, kept: bool| kept.then_some(Pos(entity_id as i32));
 
-        None
+        let positions = world.comps_filtered::<Pos, Without<Target>>();
+        assert!(positions.chunks().count() > 2);
+        for (entity_id, kept, _) in kept.iter().copied() {
+            assert_eq!(positions.query(entity_id), expected(entity_id, kept).as_ref());
+        }
+
+        let mut positions = world.comps_filtered_mut::<Pos, Without<Target>>();
+        for (entity_id, kept, _) in kept.iter().copied() {
+            assert_eq!(positions.query_mut(entity_id).map(|pos| &*pos), expected(entity_id, kept).as_ref());
+        }
+        let [first, last] = [kept[1].0, kept[11].0];
+        let [pos_first, pos_last] = positions.get_many_mut([first, last]).unwrap();
+        std::mem::swap(pos_first, pos_last);
+
+        let mut query = world.query_tuple_mut::<(&mut Pos,), Without<Target>>();
+        for (entity_id, kept, _) in kept.iter().copied() {
+            assert_eq!(query.query_mut(entity_id).is_some(), kept);
+        }
+        assert_eq!(query.query_mut(first).map(|(pos,)| pos.0), Some(last as i32));
+
+        // the sparse set is split by the table filter
+        let targets = world.comps_filtered::<Target, With<Named>>();
+        for (entity_id, kept, named) in kept {
+            assert_eq!(targets.query(entity_id).map(|target| target.0), (named && !kept).then_some(entity_id as i32));
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,11 @@
+#[allow(clippy::module_inception)]
 pub mod archetype;
 pub mod archetype_manager;
+pub mod archetype_info;
 pub mod archetype_iter;
-pub mod archetype_query;
\ No newline at end of file
+pub mod archetype_query;
+pub mod archetype_filter;
+pub mod archetype_dynamic;
+pub mod archetype_par;
+pub mod query_data;
+pub mod query_state;
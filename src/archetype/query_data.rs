@@ -0,0 +1,212 @@
+use std::any::TypeId;
+use std::cell::Cell;
+use std::ptr::NonNull;
+use crate::archetype::archetype::{Archetype, ArchetypeIndex};
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::component::component::{is_tag, Component, StorageType};
+use crate::component::sparse_set::SparseSet;
+use crate::component::tick::{set_changed_cell, ComponentTicks, Tick};
+use crate::entity::entity::EntityId;
+use crate::schedule::task_type::TaskType;
+
+// What a tuple query fetches, e.g. (&mut Position, &Velocity)
+pub trait QueryData {
+    type Item<'a>;
+    // how the rows of a matching archetype are reached
+    type Column: Copy;
+
+    fn task_types() -> Vec<TaskType>;
+
+    fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool;
+
+    fn column(archs: &ArchetypeManager, archetype: &Archetype) -> Option<Self::Column>;
+
+    // sparse components are not part of the archetype, they are checked for each entity
+    fn contains(column: &Self::Column, entity_id: EntityId) -> bool;
+
+    /// The item of the entity at row.
+    ///
+    /// # Safety
+    /// The row must exist in the column and must not be borrowed mutably elsewhere.
+    unsafe fn item<'a>(column: &Self::Column, entity_id: EntityId, row: usize) -> Self::Item<'a>;
+}
+
+/// Queries that never hand out mutable references.
+///
+/// # Safety
+/// item must only give shared references, so the same row can be fetched twice.
+pub unsafe trait ReadOnlyQueryData: QueryData {}
+
+// The tick is the one the rows handed out mutably are stamped with.
+pub enum CompColumn<T> {
+    // the first row and the ticks aligned with the rows, null for tags and read-only slices
+    Table(*mut T, *const Cell<ComponentTicks>, Tick),
+    Sparse(*const SparseSet, Tick),
+}
+
+impl<T> Clone for CompColumn<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for CompColumn<T> {}
+
+impl<T: Component + 'static> CompColumn<T> {
+    fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool {
+        match archs.component_id::<T>() {
+            // a sparse component may be on any entity
+            Some(component_id) => T::STORAGE == StorageType::Sparse || index.contains(component_id),
+            None => false,
+        }
+    }
+
+    fn new(archs: &ArchetypeManager, archetype: &Archetype) -> Option<Self> {
+        let component_id = archs.component_id::<T>()?;
+        let tick = archs.change_tick();
+        if T::STORAGE == StorageType::Sparse {
+            return archs.sparse_set(component_id).map(|sparse_set| CompColumn::Sparse(sparse_set as *const SparseSet, tick));
+        }
+
+        if !archetype.index().contains(component_id) {
+            return None;
+        }
+
+        match archetype.column(component_id) {
+            Some(column) if column.type_id() == Some(TypeId::of::<T>()) => {
+                Some(CompColumn::Table(column.as_ptr().cast::<T>(), column.ticks_cells().as_ptr(), tick))
+            }
+            // tags don't have a column
+            None if is_tag::<T>() => Some(CompColumn::Table(NonNull::<T>::dangling().as_ptr(), std::ptr::null(), tick)),
+            _ => None,
+        }
+    }
+
+    pub fn from_slice(storage: &[T]) -> Self {
+        CompColumn::Table(storage.as_ptr() as *mut T, std::ptr::null(), 0)
+    }
+
+    // The ticks are aligned with the storage, or empty for tags.
+    pub fn from_mut_slice(storage: &mut [T], ticks: &[Cell<ComponentTicks>], tick: Tick) -> Self {
+        let ticks = if ticks.is_empty() { std::ptr::null() } else { ticks.as_ptr() };
+        CompColumn::Table(storage.as_mut_ptr(), ticks, tick)
+    }
+
+    fn contains(&self, entity_id: EntityId) -> bool {
+        match self {
+            CompColumn::Table(..) => true,
+            CompColumn::Sparse(sparse_set, _) => unsafe { (**sparse_set).contains(entity_id) },
+        }
+    }
+
+    unsafe fn get(&self, entity_id: EntityId, row: usize) -> *mut T {
+        match self {
+            CompColumn::Table(first, _, _) => first.add(row),
+            CompColumn::Sparse(sparse_set, _) => (**sparse_set).get_ptr(entity_id).unwrap().cast::<T>(),
+        }
+    }
+
+    // Same as get, the row is stamped as changed.
+    unsafe fn get_mut(&self, entity_id: EntityId, row: usize) -> *mut T {
+        match self {
+            CompColumn::Table(_, ticks, tick) if !ticks.is_null() => set_changed_cell(&*ticks.add(row), *tick),
+            CompColumn::Sparse(sparse_set, tick) => set_changed_cell((**sparse_set).ticks_cell(entity_id).unwrap(), *tick),
+            CompColumn::Table(..) => {}
+        }
+        self.get(entity_id, row)
+    }
+}
+
+impl<T: Component + 'static> QueryData for &T {
+    type Item<'a> = &'a T;
+    type Column = CompColumn<T>;
+
+    fn task_types() -> Vec<TaskType> {
+        vec![TaskType::Comp(TypeId::of::<T>())]
+    }
+
+    fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool {
+        CompColumn::<T>::matches_archetype(archs, index)
+    }
+
+    fn column(archs: &ArchetypeManager, archetype: &Archetype) -> Option<Self::Column> {
+        CompColumn::new(archs, archetype)
+    }
+
+    fn contains(column: &Self::Column, entity_id: EntityId) -> bool {
+        column.contains(entity_id)
+    }
+
+    unsafe fn item<'a>(column: &Self::Column, entity_id: EntityId, row: usize) -> Self::Item<'a> {
+        &*column.get(entity_id, row)
+    }
+}
+
+unsafe impl<T: Component + 'static> ReadOnlyQueryData for &T {}
+
+impl<T: Component + 'static> QueryData for &mut T {
+    type Item<'a> = &'a mut T;
+    type Column = CompColumn<T>;
+
+    fn task_types() -> Vec<TaskType> {
+        vec![TaskType::CompMut(TypeId::of::<T>())]
+    }
+
+    fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool {
+        CompColumn::<T>::matches_archetype(archs, index)
+    }
+
+    fn column(archs: &ArchetypeManager, archetype: &Archetype) -> Option<Self::Column> {
+        CompColumn::new(archs, archetype)
+    }
+
+    fn contains(column: &Self::Column, entity_id: EntityId) -> bool {
+        column.contains(entity_id)
+    }
+
+    unsafe fn item<'a>(column: &Self::Column, entity_id: EntityId, row: usize) -> Self::Item<'a> {
+        &mut *column.get_mut(entity_id, row)
+    }
+}
+
+macro_rules! impl_query_data_tuple {
+    ($(($data:ident, $index:tt)),+) => {
+        impl<$($data: QueryData),+> QueryData for ($($data,)+) {
+            type Item<'a> = ($($data::Item<'a>,)+);
+            type Column = ($($data::Column,)+);
+
+            fn task_types() -> Vec<TaskType> {
+                let mut task_types = vec![];
+                $(task_types.extend($data::task_types());)+
+                task_types
+            }
+
+            fn matches_archetype(archs: &ArchetypeManager, index: &ArchetypeIndex) -> bool {
+                $($data::matches_archetype(archs, index))&&+
+            }
+
+            fn column(archs: &ArchetypeManager, archetype: &Archetype) -> Option<Self::Column> {
+                Some(($($data::column(archs, archetype)?,)+))
+            }
+
+            fn contains(column: &Self::Column, entity_id: EntityId) -> bool {
+                $($data::contains(&column.$index, entity_id))&&+
+            }
+
+            unsafe fn item<'a>(column: &Self::Column, entity_id: EntityId, row: usize) -> Self::Item<'a> {
+                ($($data::item(&column.$index, entity_id, row),)+)
+            }
+        }
+
+        unsafe impl<$($data: ReadOnlyQueryData),+> ReadOnlyQueryData for ($($data,)+) {}
+    };
+}
+
+impl_query_data_tuple!((A, 0));
+impl_query_data_tuple!((A, 0), (B, 1));
+impl_query_data_tuple!((A, 0), (B, 1), (C, 2));
+impl_query_data_tuple!((A, 0), (B, 1), (C, 2), (D, 3));
+impl_query_data_tuple!((A, 0), (B, 1), (C, 2), (D, 3), (E, 4));
+impl_query_data_tuple!((A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5));
+impl_query_data_tuple!((A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6));
+impl_query_data_tuple!((A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6), (H, 7));
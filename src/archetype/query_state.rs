@@ -0,0 +1,51 @@
+use std::marker::PhantomData;
+use crate::archetype::archetype_filter::QueryFilter;
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::archetype::query_data::QueryData;
+
+// The archetypes matched by a query, kept between runs so only the archetypes created
+// since the last run are checked. D is what the query fetches, e.g. &Position for Comps<Position>.
+// The state belongs to one ArchetypeManager, it starts over when used with another one
+// (a fork or a snapshot count as another one) or after the manager was compacted.
+pub struct QueryState<D: QueryData, F: QueryFilter = ()> {
+    // id of the manager the archetypes below belong to, 0 before the first run
+    manager_id: u64,
+    // the archetypes before this one were already checked
+    checked: usize,
+    matched: Vec<usize>,
+    marker: PhantomData<fn() -> (D, F)>,
+}
+
+impl<D: QueryData, F: QueryFilter> Default for QueryState<D, F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: QueryData, F: QueryFilter> QueryState<D, F> {
+    pub const fn new() -> Self {
+        Self { manager_id: 0, checked: 0, matched: Vec::new(), marker: PhantomData }
+    }
+
+    // Checks the archetypes created since the last update.
+    pub fn update(&mut self, archs: &ArchetypeManager) {
+        if self.manager_id != archs.id() {
+            self.manager_id = archs.id();
+            self.checked = 0;
+            self.matched.clear();
+        }
+
+        let archetypes = archs.archetypes();
+        for (index, archetype) in archetypes.iter().enumerate().skip(self.checked) {
+            if D::matches_archetype(archs, archetype.index()) && F::matches_archetype(archs, archetype.index()) {
+                self.matched.push(index);
+            }
+        }
+        self.checked = archetypes.len();
+    }
+
+    // The index of the matched archetypes in ArchetypeManager::archetypes, as of the last update.
+    pub fn matched(&self) -> &[usize] {
+        &self.matched
+    }
+}
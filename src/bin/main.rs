@@ -1,6 +1,5 @@
 use std::time::{Instant};
 use cow_macros::{cow_task, Resource};
-use cow_ecs::comps::{Comps, CompsMut, Res, ResMut};
 use cow_ecs::cow_macros::Component;
 use cow_ecs::scheduler::Scheduler;
 use cow_ecs::world::World;
@@ -8,22 +7,19 @@ use cow_ecs::world::World;
 #[derive(Component)]
 pub struct Value(i32);
 
-#[derive(Component)]
-pub struct ValueSecond(i32);
-
 #[derive(Resource)]
-pub struct Poop(i32);
+pub struct Poop(#[allow(dead_code)] i32);
 
 #[cow_task]
 fn test(mut values: CompsMut<Value>) {
-    for (entity, value) in values.iter() {
+    for (_, value) in values.iter() {
         value.0 += 1;
     }
 }
 
 #[cow_task]
 fn show(values: Comps<Value>) {
-    for (entity, value) in values.iter() {
+    for (_, value) in values.iter() {
         println!("{}", value.0);
     }
 }
@@ -1,41 +1,127 @@
-use std::any::TypeId;
-use std::collections::HashMap;
-use crate::component::component::{Component, ComponentAny};
+use crate::component::component::Component;
+use crate::component::component_box::ComponentBox;
 use crate::entity::entity::EntityId;
+use crate::entity::entity_manager::EntityManager;
+use crate::observer::observer::Event;
+use crate::relation::relation::Relation;
+use crate::world::World;
 
 
 pub enum EntityCommand {
-    NewEntity(EntityId, HashMap<TypeId, Box<dyn ComponentAny>>),
+    NewEntity(EntityId, Vec<ComponentBox>),
     ReleaseEntity(EntityId),
+    // child, parent
+    SetParent(EntityId, EntityId),
+    RemoveParent(EntityId),
+    ReleaseRecursive(EntityId),
+    // any change to the world, applied in order with the other commands
+    Custom(EntityId, Box<dyn FnOnce(&mut World)>),
+    // same as Custom for the changes that don't concern one entity, e.g. a global event
+    Global(Box<dyn FnOnce(&mut World)>),
 }
 
-pub struct EntityCommands {
+// Commands are recorded while a task runs and applied to the world once it is done.
+// New entities get their id right away so the task can refer to them.
+pub struct EntityCommands<'a> {
+    entities: &'a mut EntityManager,
     commands: Vec<EntityCommand>,
 }
 
-impl EntityCommands {
-    pub fn new() -> Self {
-        Self { commands: vec![] }
+impl<'a> EntityCommands<'a> {
+    pub fn new(entities: &'a mut EntityManager) -> Self {
+        Self { entities, commands: vec![] }
     }
 
     pub fn create(&mut self) -> &mut EntityCommand {
-        //self.commands.push(EntityCommand::NewEntity(HashMap::new()));
+        let entity_id = self.entities.create();
+        self.commands.push(EntityCommand::NewEntity(entity_id, vec![]));
         self.commands.last_mut().unwrap()
     }
 
+    // An id for an entity spawned later, e.g. by a scene whose components refer to each other.
+    pub(crate) fn reserve(&mut self) -> EntityId {
+        self.entities.create()
+    }
+
+    // Creates the reserved entity, its components are added to the returned command.
+    pub(crate) fn spawn_reserved(&mut self, entity_id: EntityId) -> &mut EntityCommand {
+        self.commands.push(EntityCommand::NewEntity(entity_id, vec![]));
+        self.commands.last_mut().unwrap()
+    }
+
+    // Gives back a reserved id that was never spawned.
+    pub(crate) fn unreserve(&mut self, entity_id: EntityId) {
+        self.entities.release(entity_id)
+    }
+
     pub fn release(&mut self, entity_id: EntityId) {
         self.commands.push(EntityCommand::ReleaseEntity(entity_id))
     }
+
+    pub fn set_parent(&mut self, child: EntityId, parent: EntityId) {
+        self.commands.push(EntityCommand::SetParent(child, parent))
+    }
+
+    pub fn remove_parent(&mut self, child: EntityId) {
+        self.commands.push(EntityCommand::RemoveParent(child))
+    }
+
+    pub fn release_recursive(&mut self, entity_id: EntityId) {
+        self.commands.push(EntityCommand::ReleaseRecursive(entity_id))
+    }
+
+    pub fn relate<R: Relation>(&mut self, source: EntityId, relation: R) {
+        self.commands.push(EntityCommand::Custom(source, Box::new(move |world| world.relate(source, relation))))
+    }
+
+    pub fn unrelate<R: Relation>(&mut self, source: EntityId, target: EntityId) {
+        self.commands.push(EntityCommand::Custom(source, Box::new(move |world| world.unrelate::<R>(source, target))))
+    }
+
+    // Adds a component to an entity that already exists.
+    pub fn add_box(&mut self, entity_id: EntityId, comp: ComponentBox) {
+        self.commands.push(EntityCommand::Custom(entity_id, Box::new(move |world| world.add_box(entity_id, comp))))
+    }
+
+    pub fn trigger<E: Event>(&mut self, event: E, target: EntityId) {
+        self.commands.push(EntityCommand::Custom(target, Box::new(move |world| world.trigger(event, target))))
+    }
+
+    pub fn trigger_global<E: Event>(&mut self, event: E) {
+        self.commands.push(EntityCommand::Global(Box::new(move |world| world.trigger_global(event))))
+    }
+
+    pub fn take_commands(&mut self) -> Vec<EntityCommand> {
+        std::mem::take(&mut self.commands)
+    }
 }
 
 impl EntityCommand {
-    pub fn add<T: Component + 'static>(&mut self, comp: T) {
+    // The entity the command is about, None for the global ones.
+    pub fn id(&self) -> Option<EntityId> {
         match self {
-            EntityCommand::NewEntity(_id, ref mut components) => {
-                let type_id = TypeId::of::<T>();
-                components.insert(type_id, Box::new(comp));
-            }
-            EntityCommand::ReleaseEntity(_) => {}
+            EntityCommand::NewEntity(entity_id, _) => Some(*entity_id),
+            EntityCommand::ReleaseEntity(entity_id) => Some(*entity_id),
+            EntityCommand::SetParent(entity_id, _) => Some(*entity_id),
+            EntityCommand::RemoveParent(entity_id) => Some(*entity_id),
+            EntityCommand::ReleaseRecursive(entity_id) => Some(*entity_id),
+            EntityCommand::Custom(entity_id, _) => Some(*entity_id),
+            EntityCommand::Global(_) => None,
+        }
+    }
+
+    pub fn add<T: Component + 'static>(&mut self, comp: T) -> &mut Self {
+        // only new entities carry components
+        if let EntityCommand::NewEntity(_id, ref mut components) = self {
+            components.push(ComponentBox::new(comp));
+        }
+        self
+    }
+
+    pub fn add_box(&mut self, comp: ComponentBox) -> &mut Self {
+        if let EntityCommand::NewEntity(_id, ref mut components) = self {
+            components.push(comp);
         }
+        self
     }
 }
@@ -0,0 +1,44 @@
+use std::marker::PhantomData;
+
+// Clones the value pointed by src into the uninitialised dest.
+pub type CloneFn = unsafe fn(*const u8, *mut u8);
+
+pub(crate) unsafe fn clone_value<T: Clone>(src: *const u8, dest: *mut u8) {
+    dest.cast::<T>().write((*src.cast::<T>()).clone())
+}
+
+// Finds the clone of a type that may not implement Clone, used by the derives:
+// (&&CloneProbe::<T>::new()).clone_fn() picks CloneFnOf when T is Clone and NoCloneFn otherwise.
+pub struct CloneProbe<T>(PhantomData<T>);
+
+impl<T> CloneProbe<T> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for CloneProbe<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait CloneFnOf {
+    fn clone_fn(&self) -> Option<CloneFn>;
+}
+
+impl<T: Clone> CloneFnOf for &CloneProbe<T> {
+    fn clone_fn(&self) -> Option<CloneFn> {
+        Some(clone_value::<T>)
+    }
+}
+
+pub trait NoCloneFn {
+    fn clone_fn(&self) -> Option<CloneFn>;
+}
+
+impl<T> NoCloneFn for CloneProbe<T> {
+    fn clone_fn(&self) -> Option<CloneFn> {
+        None
+    }
+}
@@ -0,0 +1,290 @@
+use std::alloc::{self, Layout};
+use std::any::TypeId;
+use std::cell::Cell;
+use std::ptr::NonNull;
+use crate::component::clone::CloneFn;
+use crate::component::registry::ComponentDescriptor;
+use crate::component::tick::{ComponentTicks, Tick};
+
+// Type-erased storage of one component type, the rows are packed like a Vec<T>.
+pub struct Column {
+    type_id: Option<TypeId>,
+    item_layout: Layout,
+    drop: Option<unsafe fn(*mut u8)>,
+    clone: Option<CloneFn>,
+    data: NonNull<u8>,
+    len: usize,
+    capacity: usize,
+    // aligned with the rows, a query stamps the rows it hands out mutably through a shared column
+    ticks: Vec<Cell<ComponentTicks>>,
+}
+
+// Components are Send + Sync, so are the bytes holding them.
+// The ticks are only written by the query owning the row, like the component.
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
+
+impl Column {
+    pub fn new(descriptor: &ComponentDescriptor) -> Self {
+        let item_layout = descriptor.layout().pad_to_align();
+        // zero-sized items never need memory
+        let capacity = if item_layout.size() == 0 { usize::MAX } else { 0 };
+        Self {
+            type_id: descriptor.type_id(),
+            item_layout,
+            drop: descriptor.drop_fn(),
+            clone: descriptor.clone_fn(),
+            data: dangling(item_layout),
+            len: 0,
+            capacity,
+            ticks: vec![],
+        }
+    }
+
+    pub fn type_id(&self) -> Option<TypeId> {
+        self.type_id
+    }
+
+    pub fn item_layout(&self) -> Layout {
+        self.item_layout
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Bytes allocated for the rows and their ticks.
+    pub fn memory(&self) -> usize {
+        let rows = if self.item_layout.size() == 0 { 0 } else { self.capacity * self.item_layout.size() };
+        rows + self.ticks.capacity() * std::mem::size_of::<ComponentTicks>()
+    }
+
+    // Dynamic components without a drop are plain bytes, the others need a clone fn.
+    pub fn is_cloneable(&self) -> bool {
+        self.clone.is_some() || (self.type_id.is_none() && self.drop.is_none())
+    }
+
+    // Copies every row in a new column, None if the component can't be cloned.
+    pub fn try_clone(&self) -> Option<Column> {
+        if !self.is_empty() && !self.is_cloneable() {
+            return None;
+        }
+
+        let mut column = Column {
+            type_id: self.type_id,
+            item_layout: self.item_layout,
+            drop: self.drop,
+            clone: self.clone,
+            data: dangling(self.item_layout),
+            len: 0,
+            capacity: if self.item_layout.size() == 0 { usize::MAX } else { 0 },
+            ticks: self.ticks.clone(),
+        };
+        if self.len > column.capacity {
+            column.realloc(self.len);
+        }
+
+        let size = self.item_layout.size();
+        match self.clone {
+            Some(clone) => {
+                for row in 0..self.len {
+                    unsafe { clone(self.get_ptr(row), column.data.as_ptr().add(row * size)) };
+                    // counted right away so a panicking clone only drops the copied rows
+                    column.len = row + 1;
+                }
+            }
+            None => {
+                unsafe { std::ptr::copy_nonoverlapping(self.data.as_ptr(), column.data.as_ptr(), self.len * size) };
+                column.len = self.len;
+            }
+        }
+        Some(column)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.data.as_ptr()
+    }
+
+    pub fn get_ptr(&self, row: usize) -> *mut u8 {
+        assert!(row < self.len, "row {} out of bounds for a column of {}", row, self.len);
+        unsafe { self.data.as_ptr().add(row * self.item_layout.size()) }
+    }
+
+    // Moves the component pointed by comp at the end of the column, added at tick.
+    // The caller must not use or drop the source after.
+    pub(crate) unsafe fn push(&mut self, comp: *const u8, tick: Tick) {
+        self.push_with_ticks(comp, ComponentTicks::new(tick));
+    }
+
+    unsafe fn push_with_ticks(&mut self, comp: *const u8, ticks: ComponentTicks) {
+        if self.len == self.capacity {
+            self.grow();
+        }
+
+        let size = self.item_layout.size();
+        std::ptr::copy_nonoverlapping(comp, self.data.as_ptr().add(self.len * size), size);
+        self.ticks.push(Cell::new(ticks));
+        self.len += 1;
+    }
+
+    // Drops the component at row and moves comp in its place, changed at tick.
+    pub(crate) unsafe fn replace(&mut self, row: usize, comp: *const u8, tick: Tick) {
+        let dest = self.get_ptr(row);
+        if let Some(drop) = self.drop {
+            drop(dest);
+        }
+        std::ptr::copy_nonoverlapping(comp, dest, self.item_layout.size());
+        self.ticks[row].get_mut().set_changed(tick);
+    }
+
+    pub fn ticks(&self, row: usize) -> ComponentTicks {
+        self.ticks[row].get()
+    }
+
+    pub fn set_changed(&mut self, row: usize, tick: Tick) {
+        self.ticks[row].get_mut().set_changed(tick);
+    }
+
+    // The ticks of the rows for the queries, see set_changed_cell
+    pub(crate) fn ticks_cells(&self) -> &[Cell<ComponentTicks>] {
+        &self.ticks
+    }
+
+    // Drops the component at row, the last row takes its place.
+    pub fn swap_remove(&mut self, row: usize) {
+        let removed = self.get_ptr(row);
+        if let Some(drop) = self.drop {
+            unsafe { drop(removed) };
+        }
+        unsafe { self.fill_hole(row) };
+    }
+
+    // Moves the component at row at the end of dest, the last row takes its place.
+    pub fn swap_remove_into(&mut self, row: usize, dest: &mut Column) {
+        debug_assert!(self.type_id == dest.type_id);
+        unsafe {
+            dest.push_with_ticks(self.get_ptr(row), self.ticks(row));
+            self.fill_hole(row);
+        }
+    }
+
+    // Forgets the rows past len without dropping them, they must have been moved out.
+    pub(crate) unsafe fn set_len(&mut self, len: usize) {
+        debug_assert!(len <= self.len);
+        self.len = len;
+        self.ticks.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        let len = self.len;
+        // the column is emptied first in case a drop panics
+        self.len = 0;
+        self.ticks.clear();
+        if let Some(drop) = self.drop {
+            for row in 0..len {
+                unsafe { drop(self.data.as_ptr().add(row * self.item_layout.size())) };
+            }
+        }
+    }
+
+    pub fn slice<T: 'static>(&self) -> Option<&[T]> {
+        (self.type_id == Some(TypeId::of::<T>())).then(|| unsafe { self.as_slice::<T>() })
+    }
+
+    pub fn slice_mut<T: 'static>(&mut self) -> Option<&mut [T]> {
+        (self.type_id == Some(TypeId::of::<T>())).then(|| unsafe { self.as_mut_slice::<T>() })
+    }
+
+    // The caller must make sure T is the type stored in the column.
+    pub(crate) unsafe fn as_slice<T: 'static>(&self) -> &[T] {
+        debug_assert!(self.type_id == Some(TypeId::of::<T>()));
+        std::slice::from_raw_parts(self.data.as_ptr().cast::<T>(), self.len)
+    }
+
+    pub(crate) unsafe fn as_mut_slice<T: 'static>(&mut self) -> &mut [T] {
+        debug_assert!(self.type_id == Some(TypeId::of::<T>()));
+        std::slice::from_raw_parts_mut(self.data.as_ptr().cast::<T>(), self.len)
+    }
+
+    // The rows and their ticks, a query stamps each row it hands out.
+    pub(crate) fn slice_and_ticks_mut<T: 'static>(&mut self) -> Option<(&mut [T], &[Cell<ComponentTicks>])> {
+        if self.type_id != Some(TypeId::of::<T>()) {
+            return None;
+        }
+        // the rows and the ticks don't overlap
+        let slice = unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr().cast::<T>(), self.len) };
+        Some((slice, &self.ticks))
+    }
+
+    // the row must have been dropped or moved out already
+    unsafe fn fill_hole(&mut self, row: usize) {
+        let last = self.len - 1;
+        if row != last {
+            let size = self.item_layout.size();
+            std::ptr::copy_nonoverlapping(self.data.as_ptr().add(last * size), self.data.as_ptr().add(row * size), size);
+        }
+        self.ticks.swap_remove(row);
+        self.len = last;
+    }
+
+    // Frees the capacity past the last row.
+    pub fn shrink_to_fit(&mut self) {
+        self.ticks.shrink_to_fit();
+        if self.item_layout.size() == 0 || self.capacity == self.len {
+            return;
+        }
+
+        if self.len == 0 {
+            unsafe { alloc::dealloc(self.data.as_ptr(), array_layout(self.item_layout, self.capacity)) };
+            self.data = dangling(self.item_layout);
+            self.capacity = 0;
+        } else {
+            self.realloc(self.len);
+        }
+    }
+
+    fn grow(&mut self) {
+        let new_capacity = if self.capacity == 0 { 4 } else { self.capacity * 2 };
+        self.realloc(new_capacity);
+    }
+
+    fn realloc(&mut self, new_capacity: usize) {
+        let new_layout = array_layout(self.item_layout, new_capacity);
+        let data = unsafe {
+            if self.capacity == 0 {
+                alloc::alloc(new_layout)
+            } else {
+                alloc::realloc(self.data.as_ptr(), array_layout(self.item_layout, self.capacity), new_layout.size())
+            }
+        };
+
+        self.data = NonNull::new(data).unwrap_or_else(|| alloc::handle_alloc_error(new_layout));
+        self.capacity = new_capacity;
+    }
+}
+
+impl Drop for Column {
+    fn drop(&mut self) {
+        self.clear();
+        if self.item_layout.size() != 0 && self.capacity != 0 {
+            unsafe { alloc::dealloc(self.data.as_ptr(), array_layout(self.item_layout, self.capacity)) };
+        }
+    }
+}
+
+fn array_layout(item_layout: Layout, capacity: usize) -> Layout {
+    let size = item_layout.size().checked_mul(capacity).expect("column capacity overflow");
+    Layout::from_size_align(size, item_layout.align()).expect("column capacity overflow")
+}
+
+fn dangling(layout: Layout) -> NonNull<u8> {
+    NonNull::new(std::ptr::without_provenance_mut(layout.align())).unwrap()
+}
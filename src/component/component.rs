@@ -1,7 +1,52 @@
 use std::any::Any;
+use crate::component::clone::CloneFn;
+use crate::component::hooks::ComponentHooks;
+use crate::component::required::RequiredComponent;
 
 pub trait ComponentAny {
     fn into_any(self: Box<Self>) -> Box<dyn Any>;
 }
 
-pub trait Component: ComponentAny + Send + Sync {}
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StorageType {
+    // stored in the archetype tables, fast to iterate but moved on every archetype change
+    Table,
+    // stored in a per-type sparse set, cheap to add and remove
+    Sparse,
+}
+
+pub trait Component: ComponentAny + Send + Sync {
+    const STORAGE: StorageType = StorageType::Table;
+
+    // how snapshots copy the component, None if it can't be cloned
+    fn clone_fn() -> Option<CloneFn> where Self: Sized {
+        None
+    }
+
+    fn hooks() -> ComponentHooks where Self: Sized {
+        ComponentHooks::default()
+    }
+
+    // the components inserted with their default along with this one
+    fn required() -> Vec<RequiredComponent> where Self: Sized {
+        vec![]
+    }
+}
+
+// Zero-sized components without a drop are tags, they are only recorded in the archetype index
+// and never get a storage or any per-row work. A zero-sized component with a drop gets a column
+// like any other component so it's dropped with the entity.
+pub fn is_tag<T: Component>() -> bool {
+    std::mem::size_of::<T>() == 0 && !std::mem::needs_drop::<T>()
+}
+
+// A tag has no bytes to point to, any well aligned pointer is a valid reference to it.
+pub(crate) fn tag_slice<'a, T: Component>(len: usize) -> &'a [T] {
+    debug_assert!(is_tag::<T>());
+    unsafe { std::slice::from_raw_parts(std::ptr::NonNull::<T>::dangling().as_ptr(), len) }
+}
+
+pub(crate) fn tag_slice_mut<'a, T: Component>(len: usize) -> &'a mut [T] {
+    debug_assert!(is_tag::<T>());
+    unsafe { std::slice::from_raw_parts_mut(std::ptr::NonNull::<T>::dangling().as_ptr(), len) }
+}
@@ -0,0 +1,39 @@
+use std::mem::ManuallyDrop;
+use crate::component::column::Column;
+use crate::component::component::Component;
+use crate::component::registry::ComponentDescriptor;
+
+// A component moved out of its type, so it can be inserted later from its raw bytes.
+pub struct ComponentBox {
+    descriptor: ComponentDescriptor,
+    data: Column,
+}
+
+impl ComponentBox {
+    pub fn new<T: Component + 'static>(comp: T) -> Self {
+        let descriptor = ComponentDescriptor::of::<T>();
+        let mut data = Column::new(&descriptor);
+        let comp = ManuallyDrop::new(comp);
+        unsafe { data.push(&*comp as *const T as *const u8, 0) };
+        Self { descriptor, data }
+    }
+
+    pub fn descriptor(&self) -> &ComponentDescriptor {
+        &self.descriptor
+    }
+
+    // Hands the component bytes to insert, they are considered moved once insert returns.
+    pub fn insert_with(mut self, insert: impl FnOnce(*const u8)) {
+        insert(self.data.get_ptr(0));
+        unsafe { self.data.set_len(0) };
+    }
+
+    // Same as insert_with for several components at once.
+    pub fn insert_all_with(boxes: Vec<ComponentBox>, insert: impl FnOnce(&[*const u8])) {
+        let comps: Vec<*const u8> = boxes.iter().map(|comp| comp.data.get_ptr(0) as *const u8).collect();
+        insert(&comps);
+        for mut comp in boxes {
+            unsafe { comp.data.set_len(0) };
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::commands::EntityCommands;
+use crate::component::component::Component;
+use crate::component::registry::ComponentId;
+use crate::comps::Commands;
+use crate::entity::entity::EntityId;
+use crate::resource::res_manager::ResManager;
+
+pub type ComponentHook = fn(&mut HookContext<'_>);
+
+// Called by the world when a component changes on an entity, set with
+// #[component(on_add = f, on_insert = f, on_replace = f, on_remove = f)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ComponentHooks {
+    // the entity didn't have the component
+    pub on_add: Option<ComponentHook>,
+    // after every insert, new or overwriting
+    pub on_insert: Option<ComponentHook>,
+    // before the value is overwritten or removed, it can still be read
+    pub on_replace: Option<ComponentHook>,
+    // before the component is removed, also when the entity is released
+    pub on_remove: Option<ComponentHook>,
+}
+
+impl ComponentHooks {
+    pub fn is_empty(&self) -> bool {
+        self.on_add.is_none() && self.on_insert.is_none() && self.on_replace.is_none() && self.on_remove.is_none()
+    }
+}
+
+// What a hook can reach: the components and resources to read, and commands
+// applied once the world is done with the change that triggered the hook.
+pub struct HookContext<'w> {
+    entity_id: EntityId,
+    component_id: ComponentId,
+    archetypes: &'w ArchetypeManager,
+    resources: &'w ResManager,
+    commands: EntityCommands<'w>,
+}
+
+impl<'w> HookContext<'w> {
+    pub(crate) fn new(entity_id: EntityId, component_id: ComponentId, archetypes: &'w ArchetypeManager,
+                      resources: &'w ResManager, commands: EntityCommands<'w>) -> Self {
+        Self { entity_id, component_id, archetypes, resources, commands }
+    }
+
+    pub fn entity(&self) -> EntityId {
+        self.entity_id
+    }
+
+    pub fn component_id(&self) -> ComponentId {
+        self.component_id
+    }
+
+    // e.g. the component of the hook, before it's replaced or removed
+    pub fn get<T: Component + 'static>(&self) -> Option<&T> {
+        self.archetypes.query::<T>(self.entity_id)
+    }
+
+    pub fn archetypes(&self) -> &ArchetypeManager {
+        self.archetypes
+    }
+
+    // Resources can be written through their lock.
+    pub fn resources(&self) -> &ResManager {
+        self.resources
+    }
+
+    pub fn commands(&mut self) -> Commands<'_, 'w> {
+        Commands::new(&mut self.commands)
+    }
+
+    pub(crate) fn into_commands(self) -> EntityCommands<'w> {
+        self.commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::component::component_box::ComponentBox;
+    use crate::component::hooks::HookContext;
+    use crate::cow_macros::{Component, Resource};
+    use crate::entity::entity::EntityId;
+    use crate::world::World;
+
+    // the hooks called and the Health each one saw
+    #[derive(Resource, Default)]
+    struct Log(Vec<(&'static str, Option<u32>)>);
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(on_add = log_add, on_insert = log_insert, on_replace = log_replace, on_remove = log_remove)]
+    struct Health(u32);
+
+    #[derive(Component)]
+    #[component(on_add = spawn_shadow, on_remove = leave_corpse)]
+    struct Tracked;
+
+    #[derive(Component)]
+    struct Seen;
+
+    #[derive(Component)]
+    struct Shadow(EntityId);
+
+    #[derive(Component)]
+    struct Corpse(EntityId);
+
+    fn log(context: &HookContext<'_>, hook: &'static str) {
+        let health = context.get::<Health>().map(|health| health.0);
+        context.resources().query::<Log>().unwrap().resource().write().unwrap().0.push((hook, health));
+    }
+
+    fn log_add(context: &mut HookContext<'_>) {
+        log(context, "add");
+    }
+
+    fn log_insert(context: &mut HookContext<'_>) {
+        log(context, "insert");
+    }
+
+    fn log_replace(context: &mut HookContext<'_>) {
+        log(context, "replace");
+    }
+
+    fn log_remove(context: &mut HookContext<'_>) {
+        log(context, "remove");
+    }
+
+    fn spawn_shadow(context: &mut HookContext<'_>) {
+        let entity_id = context.entity();
+        let mut commands = context.commands();
+        commands.add_box(entity_id, ComponentBox::new(Seen));
+        commands.create().add(Shadow(entity_id));
+    }
+
+    fn leave_corpse(context: &mut HookContext<'_>) {
+        let entity_id = context.entity();
+        context.commands().create().add(Corpse(entity_id));
+    }
+
+    fn take_log(world: &World) -> Vec<(&'static str, Option<u32>)> {
+        std::mem::take(&mut world.resources().query::<Log>().unwrap().resource().write().unwrap().0)
+    }
+
+    #[test]
+    fn hooks_run_in_order_around_each_change() {
+        let mut world = World::new();
+        world.set_res(Log::default());
+        let entity = world.create();
+
+        world.add(entity, Health(1));
+        assert_eq!(take_log(&world), [("add", Some(1)), ("insert", Some(1))]);
+
+        // the old value is still there for on_replace
+        world.add(entity, Health(2));
+        assert_eq!(take_log(&world), [("replace", Some(1)), ("insert", Some(2))]);
+
+        world.remove::<Health>(entity);
+        assert_eq!(take_log(&world), [("replace", Some(2)), ("remove", Some(2))]);
+        assert!(world.query::<Health>(entity).is_none());
+        world.remove::<Health>(entity);
+        assert!(take_log(&world).is_empty());
+
+        world.add(entity, Health(3));
+        take_log(&world);
+        world.release(entity);
+        assert_eq!(take_log(&world), [("replace", Some(3)), ("remove", Some(3))]);
+    }
+
+    #[test]
+    fn hook_commands_are_applied() {
+        let mut world = World::new();
+        let entity = world.create();
+        world.add(entity, Tracked);
+        assert!(world.query::<Seen>(entity).is_some());
+        let shadows: Vec<EntityId> = world.comps::<Shadow>().iter().map(|(_, shadow)| shadow.0).collect();
+        assert_eq!(shadows, [entity]);
+
+        world.release(entity);
+        let corpses: Vec<EntityId> = world.comps::<Corpse>().iter().map(|(_, corpse)| corpse.0).collect();
+        assert_eq!(corpses, [entity]);
+    }
+}
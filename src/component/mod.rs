@@ -1,2 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod component;
-pub mod comp_storage;
\ No newline at end of file
+pub mod clone;
+pub mod column;
+pub mod component_box;
+pub mod hooks;
+pub mod registry;
+pub mod required;
+pub mod sparse_set;
+pub mod tick;
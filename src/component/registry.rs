@@ -0,0 +1,260 @@
+use std::alloc::Layout;
+use std::any::TypeId;
+use std::collections::HashMap;
+use crate::component::clone::CloneFn;
+use crate::component::component::{Component, StorageType};
+use crate::component::hooks::ComponentHooks;
+use crate::component::required::RequiredComponent;
+
+// Dense index given to each registered component, archetypes and columns are keyed on it.
+pub type ComponentId = usize;
+
+#[derive(Clone, Debug)]
+pub struct ComponentDescriptor {
+    name: String,
+    // components defined at runtime don't have a rust type
+    type_id: Option<TypeId>,
+    layout: Layout,
+    drop: Option<unsafe fn(*mut u8)>,
+    clone: Option<CloneFn>,
+    storage: StorageType,
+    hooks: ComponentHooks,
+    required: Vec<RequiredComponent>,
+}
+
+impl ComponentDescriptor {
+    pub fn of<T: Component + 'static>() -> Self {
+        unsafe fn drop_ptr<T>(ptr: *mut u8) {
+            ptr.cast::<T>().drop_in_place()
+        }
+
+        Self {
+            name: std::any::type_name::<T>().to_string(),
+            type_id: Some(TypeId::of::<T>()),
+            layout: Layout::new::<T>(),
+            drop: std::mem::needs_drop::<T>().then_some(drop_ptr::<T> as unsafe fn(*mut u8)),
+            clone: T::clone_fn(),
+            storage: T::STORAGE,
+            hooks: T::hooks(),
+            required: T::required(),
+        }
+    }
+
+    // A component defined at runtime, e.g. by a script, made of layout.size() bytes.
+    // drop is called on the bytes of each component when it's removed.
+    pub fn new(name: impl Into<String>, layout: Layout, drop: Option<unsafe fn(*mut u8)>) -> Self {
+        Self { name: name.into(), type_id: None, layout: layout.pad_to_align(), drop, clone: None, storage: StorageType::Table, hooks: ComponentHooks::default(), required: vec![] }
+    }
+
+    // Components without a drop are copied byte by byte, the others need a clone to be shared with a snapshot.
+    pub fn with_clone(mut self, clone: CloneFn) -> Self {
+        self.clone = Some(clone);
+        self
+    }
+
+    pub fn with_storage(mut self, storage: StorageType) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn with_hooks(mut self, hooks: ComponentHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn with_required(mut self, required: Vec<RequiredComponent>) -> Self {
+        self.required = required;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn type_id(&self) -> Option<TypeId> {
+        self.type_id
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.type_id.is_none()
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn drop_fn(&self) -> Option<unsafe fn(*mut u8)> {
+        self.drop
+    }
+
+    pub fn clone_fn(&self) -> Option<CloneFn> {
+        self.clone
+    }
+
+    pub fn storage(&self) -> StorageType {
+        self.storage
+    }
+
+    pub fn hooks(&self) -> &ComponentHooks {
+        &self.hooks
+    }
+
+    pub fn required(&self) -> &[RequiredComponent] {
+        &self.required
+    }
+
+    // Dynamic components without a drop are plain bytes, the others need a clone fn.
+    pub fn is_cloneable(&self) -> bool {
+        self.clone.is_some() || (self.is_dynamic() && self.drop.is_none())
+    }
+
+    // zero-sized components without a drop only exist in the archetype index
+    pub fn is_tag(&self) -> bool {
+        self.layout.size() == 0 && self.drop.is_none()
+    }
+}
+
+#[derive(Clone)]
+pub struct ComponentInfo {
+    id: ComponentId,
+    descriptor: ComponentDescriptor,
+    // the name without the module paths, e.g. Related<Likes>
+    short_name: String,
+}
+
+impl ComponentInfo {
+    pub fn id(&self) -> ComponentId {
+        self.id
+    }
+
+    pub fn descriptor(&self) -> &ComponentDescriptor {
+        &self.descriptor
+    }
+
+    pub fn name(&self) -> &str {
+        self.descriptor.name()
+    }
+
+    pub fn short_name(&self) -> &str {
+        &self.short_name
+    }
+
+    pub fn type_id(&self) -> Option<TypeId> {
+        self.descriptor.type_id()
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.descriptor.is_dynamic()
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.descriptor.layout()
+    }
+
+    pub fn storage(&self) -> StorageType {
+        self.descriptor.storage()
+    }
+
+    pub fn hooks(&self) -> &ComponentHooks {
+        self.descriptor.hooks()
+    }
+
+    pub fn required(&self) -> &[RequiredComponent] {
+        self.descriptor.required()
+    }
+
+    pub fn is_tag(&self) -> bool {
+        self.descriptor.is_tag()
+    }
+
+    pub fn is_cloneable(&self) -> bool {
+        self.descriptor.is_cloneable()
+    }
+}
+
+#[derive(Clone)]
+pub struct ComponentRegistry {
+    infos: Vec<ComponentInfo>,
+    ids: HashMap<TypeId, ComponentId>,
+}
+
+impl Default for ComponentRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self { infos: vec![], ids: HashMap::new() }
+    }
+
+    pub fn register<T: Component + 'static>(&mut self) -> ComponentId {
+        match self.id::<T>() {
+            Some(id) => id,
+            None => self.register_descriptor(ComponentDescriptor::of::<T>()),
+        }
+    }
+
+    // A rust type is only registered once, every dynamic descriptor gets a new id.
+    pub fn register_descriptor(&mut self, descriptor: ComponentDescriptor) -> ComponentId {
+        if let Some(id) = descriptor.type_id().and_then(|type_id| self.ids.get(&type_id)) {
+            return *id;
+        }
+
+        let id = self.infos.len();
+        if let Some(type_id) = descriptor.type_id() {
+            self.ids.insert(type_id, id);
+        }
+        let short_name = short_type_name(descriptor.name());
+        self.infos.push(ComponentInfo { id, descriptor, short_name });
+        id
+    }
+
+    pub fn id<T: Component + 'static>(&self) -> Option<ComponentId> {
+        self.ids.get(&TypeId::of::<T>()).copied()
+    }
+
+    pub fn id_from_type(&self, type_id: TypeId) -> Option<ComponentId> {
+        self.ids.get(&type_id).copied()
+    }
+
+    pub fn info(&self, id: ComponentId) -> &ComponentInfo {
+        &self.infos[id]
+    }
+
+    pub fn get_info(&self, id: ComponentId) -> Option<&ComponentInfo> {
+        self.infos.get(id)
+    }
+
+    pub fn infos(&self) -> &Vec<ComponentInfo> {
+        &self.infos
+    }
+
+    pub fn len(&self) -> usize {
+        self.infos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.infos.is_empty()
+    }
+}
+
+// Strips the module paths of a type name and of its generics, game::Related<game::Likes> becomes Related<Likes>.
+pub fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut segment_start = 0;
+    for (index, c) in name.char_indices() {
+        if matches!(c, '<' | '>' | ',' | ';' | '(' | ')' | '[' | ']' | '&' | ' ') {
+            push_last_segment(&mut short, &name[segment_start..index]);
+            short.push(c);
+            segment_start = index + c.len_utf8();
+        }
+    }
+    push_last_segment(&mut short, &name[segment_start..]);
+    short
+}
+
+fn push_last_segment(short: &mut String, path: &str) {
+    short.push_str(path.rsplit("::").next().unwrap_or(path));
+}
@@ -0,0 +1,140 @@
+use std::any::TypeId;
+use crate::component::component::Component;
+use crate::component::component_box::ComponentBox;
+use crate::component::registry::ComponentDescriptor;
+
+// A component inserted with its default when a component requiring it is inserted
+// on an entity that doesn't have it, see #[component(requires(A, B))]
+#[derive(Clone, Copy, Debug)]
+pub struct RequiredComponent {
+    type_id: TypeId,
+    descriptor: fn() -> ComponentDescriptor,
+    default: fn() -> ComponentBox,
+}
+
+impl RequiredComponent {
+    pub fn of<T: Component + Default + 'static>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            descriptor: ComponentDescriptor::of::<T>,
+            default: || ComponentBox::new(T::default()),
+        }
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn descriptor(&self) -> ComponentDescriptor {
+        (self.descriptor)()
+    }
+
+    pub fn default_box(&self) -> ComponentBox {
+        (self.default)()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::EntityCommands;
+    use crate::component::component_box::ComponentBox;
+    use crate::component::hooks::HookContext;
+    use crate::comps::Commands;
+    use crate::cow_macros::{Component, Resource};
+    use crate::entity::entity::EntityId;
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Armor(u32);
+
+    impl Default for Armor {
+        fn default() -> Self {
+            Self(5)
+        }
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(requires(Armor))]
+    struct Health(u32);
+
+    impl Default for Health {
+        fn default() -> Self {
+            Self(100)
+        }
+    }
+
+    #[derive(Component, Default)]
+    #[component(requires(Health), on_add = check_required)]
+    struct Player;
+
+    // whether the requirements of each Player were there when it was added
+    #[derive(Resource, Default)]
+    struct Checked(Vec<bool>);
+
+    fn check_required(context: &mut HookContext<'_>) {
+        let found = context.get::<Health>().is_some() && context.get::<Armor>().is_some();
+        if let Some(checked) = context.resources().query::<Checked>() {
+            checked.resource().write().unwrap().0.push(found);
+        }
+    }
+
+    fn stats(world: &World, entity_id: EntityId) -> (Option<&Health>, Option<&Armor>) {
+        (world.query::<Health>(entity_id), world.query::<Armor>(entity_id))
+    }
+
+    #[test]
+    fn required_defaults_are_inserted_by_every_insert() {
+        let mut world = World::new();
+        let (added, boxed, existing) = (world.create(), world.create(), world.create());
+        world.add(added, Health(7));
+        world.add_boxes(boxed, vec![ComponentBox::new(Player)]);
+
+        let (_, _, entities) = world.managers();
+        let mut entity_commands = EntityCommands::new(entities);
+        let mut commands = Commands::new(&mut entity_commands);
+        let spawned = commands.create().add(Player).id().unwrap();
+        commands.add_box(existing, ComponentBox::new(Player));
+        let commands = entity_commands.take_commands();
+        world.apply_commands(commands);
+
+        assert_eq!(stats(&world, added), (Some(&Health(7)), Some(&Armor(5))));
+        // Player requires Health which requires Armor
+        for entity_id in [boxed, spawned, existing] {
+            assert!(world.query::<Player>(entity_id).is_some());
+            assert_eq!(stats(&world, entity_id), (Some(&Health(100)), Some(&Armor(5))));
+        }
+    }
+
+    #[test]
+    fn explicit_values_are_kept() {
+        let mut world = World::new();
+        let (armored, bundled, healthy) = (world.create(), world.create(), world.create());
+        world.add(armored, Armor(1));
+        world.add(armored, Health(2));
+        world.add_boxes(bundled, vec![ComponentBox::new(Player), ComponentBox::new(Armor(9))]);
+        world.add(healthy, Health(3));
+        world.add(healthy, Armor(4));
+        world.add(healthy, Player);
+
+        assert_eq!(stats(&world, armored), (Some(&Health(2)), Some(&Armor(1))));
+        assert_eq!(stats(&world, bundled), (Some(&Health(100)), Some(&Armor(9))));
+        assert_eq!(stats(&world, healthy), (Some(&Health(3)), Some(&Armor(4))));
+    }
+
+    #[test]
+    fn requirements_come_in_the_same_archetype_move() {
+        let mut world = World::new();
+        world.set_res(Checked::default());
+        let first = world.create();
+        let archetypes = world.archetypes().archetypes().len();
+        world.add(first, Player);
+        // only the archetype with the three components was created, the entity went there at once
+        assert_eq!(world.archetypes().archetypes().len(), archetypes + 1);
+        assert_eq!(world.archetypes().archetype_of(first).unwrap().index().components().len(), 3);
+
+        let second = world.create();
+        world.add(second, Player);
+        assert_eq!(world.archetypes().archetypes().len(), archetypes + 1);
+        assert_eq!(world.resources().query::<Checked>().unwrap().resource().read().unwrap().0, [true, true]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::Cell;
+use crate::component::column::Column;
+use crate::component::registry::ComponentDescriptor;
+use crate::component::tick::{ComponentTicks, Tick};
+use crate::entity::entity::EntityId;
+
+// Storage for components declared with `#[component(storage = "sparse")]`.
+// The components live outside of the archetypes, so adding or removing one
+// never moves the rest of the entity.
+pub struct SparseSet {
+    // entity id to dense index
+    sparse: Vec<Option<usize>>,
+    // dense entity list, aligned with the column
+    entities: Vec<EntityId>,
+    column: Column,
+}
+
+impl SparseSet {
+    pub fn new(descriptor: &ComponentDescriptor) -> Self {
+        Self { sparse: vec![], entities: vec![], column: Column::new(descriptor) }
+    }
+
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        self.dense_index(entity_id).is_some()
+    }
+
+    // Moves the component pointed by comp in the set, replacing the one the entity may already have.
+    pub(crate) unsafe fn insert(&mut self, entity_id: EntityId, comp: *const u8, tick: Tick) {
+        if let Some(dense_index) = self.dense_index(entity_id) {
+            self.column.replace(dense_index, comp, tick);
+            return;
+        }
+
+        let sparse_index = entity_id as usize;
+        if sparse_index >= self.sparse.len() {
+            self.sparse.resize(sparse_index + 1, None);
+        }
+        self.sparse[sparse_index] = Some(self.entities.len());
+        self.entities.push(entity_id);
+        self.column.push(comp, tick);
+    }
+
+    pub fn remove(&mut self, entity_id: EntityId) -> bool {
+        let dense_index = match self.sparse.get_mut(entity_id as usize).and_then(|index| index.take()) {
+            Some(dense_index) => dense_index,
+            None => return false,
+        };
+
+        // same swap and pop as the archetypes, the last entity takes the freed slot
+        self.column.swap_remove(dense_index);
+        self.entities.swap_remove(dense_index);
+        if let Some(moved_entity) = self.entities.get(dense_index) {
+            self.sparse[*moved_entity as usize] = Some(dense_index);
+        }
+        true
+    }
+
+    // Copies the set, None if the component can't be cloned.
+    pub fn try_clone(&self) -> Option<SparseSet> {
+        Some(Self { sparse: self.sparse.clone(), entities: self.entities.clone(), column: self.column.try_clone()? })
+    }
+
+    pub fn get_ptr(&self, entity_id: EntityId) -> Option<*mut u8> {
+        self.dense_index(entity_id).map(|dense_index| self.column.get_ptr(dense_index))
+    }
+
+    pub fn ticks(&self, entity_id: EntityId) -> Option<ComponentTicks> {
+        self.dense_index(entity_id).map(|dense_index| self.column.ticks(dense_index))
+    }
+
+    pub fn set_changed(&mut self, entity_id: EntityId, tick: Tick) {
+        if let Some(dense_index) = self.dense_index(entity_id) {
+            self.column.set_changed(dense_index, tick);
+        }
+    }
+
+    // The tick cell of the entity, see Column::ticks_cells
+    pub(crate) fn ticks_cell(&self, entity_id: EntityId) -> Option<&Cell<ComponentTicks>> {
+        self.dense_index(entity_id).map(|dense_index| &self.column.ticks_cells()[dense_index])
+    }
+
+    pub fn entities(&self) -> &Vec<EntityId> {
+        &self.entities
+    }
+
+    pub fn column(&self) -> &Column {
+        &self.column
+    }
+
+    pub fn entities_and_column_mut(&mut self) -> (&Vec<EntityId>, &mut Column) {
+        (&self.entities, &mut self.column)
+    }
+
+    // The dense index of each entity id, then the same as entities_and_column_mut.
+    pub(crate) fn rows_entities_and_column_mut(&mut self) -> (&[Option<usize>], &Vec<EntityId>, &mut Column) {
+        (&self.sparse, &self.entities, &mut self.column)
+    }
+
+    pub(crate) fn rows(&self) -> &[Option<usize>] {
+        &self.sparse
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        // the sparse part only needs to reach the highest entity in the set
+        let used = self.entities.iter().max().map_or(0, |entity_id| *entity_id as usize + 1);
+        self.sparse.truncate(used);
+        self.sparse.shrink_to_fit();
+        self.entities.shrink_to_fit();
+        self.column.shrink_to_fit();
+    }
+
+    fn dense_index(&self, entity_id: EntityId) -> Option<usize> {
+        self.sparse.get(entity_id as usize).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::ManuallyDrop;
+    use crate::archetype::archetype_filter::With;
+    use crate::component::registry::ComponentDescriptor;
+    use crate::component::sparse_set::SparseSet;
+    use crate::cow_macros::Component;
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    #[component(storage = "sparse")]
+    struct Burning(u32);
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Pos(i32);
+
+    fn insert(sparse_set: &mut SparseSet, entity_id: u32, value: u32) {
+        let value = ManuallyDrop::new(Burning(value));
+        unsafe { sparse_set.insert(entity_id, &*value as *const Burning as *const u8, 1) };
+    }
+
+    fn value(sparse_set: &SparseSet, entity_id: u32) -> Option<u32> {
+        sparse_set.get_ptr(entity_id).map(|ptr| unsafe { (*ptr.cast::<Burning>()).0 })
+    }
+
+    #[test]
+    fn remove_keeps_the_other_entities() {
+        let mut sparse_set = SparseSet::new(&ComponentDescriptor::of::<Burning>());
+        insert(&mut sparse_set, 3, 30);
+        insert(&mut sparse_set, 7, 70);
+        insert(&mut sparse_set, 5, 50);
+        insert(&mut sparse_set, 7, 71);
+        assert_eq!(sparse_set.len(), 3);
+
+        assert!(sparse_set.remove(3));
+        assert!(!sparse_set.remove(3));
+        assert_eq!(sparse_set.entities(), &[5, 7]);
+        assert_eq!((value(&sparse_set, 5), value(&sparse_set, 7), value(&sparse_set, 3)), (Some(50), Some(71), None));
+
+        sparse_set.shrink_to_fit();
+        assert_eq!(value(&sparse_set, 7), Some(71));
+    }
+
+    #[test]
+    fn sparse_components_dont_move_the_entity() {
+        let mut world = World::new();
+        let (a, b) = (world.create(), world.create());
+        world.add(a, Pos(1));
+        world.add(b, Pos(2));
+        let archetype = world.archetypes().archetype_of(a).unwrap().index().components().clone();
+
+        world.add(a, Burning(3));
+        assert_eq!(world.archetypes().archetype_of(a).unwrap().index().components(), &archetype);
+        assert_eq!(world.query::<Burning>(a), Some(&Burning(3)));
+
+        world.comps_mut::<Burning>().iter().for_each(|(_, burning)| burning.0 += 1);
+        let burning: Vec<_> = world.query_tuple::<(&Pos, &Burning), ()>().iter().map(|(entity_id, (pos, burning))| (entity_id, pos.0, burning.0)).collect();
+        assert_eq!(burning, [(a, 1, 4)]);
+        assert_eq!(world.comps_filtered::<Pos, With<Burning>>().iter().count(), 1);
+
+        world.remove::<Burning>(a);
+        assert_eq!(world.query::<Burning>(a), None);
+        assert_eq!(world.archetypes().archetype_of(a).unwrap().index().components(), &archetype);
+    }
+}
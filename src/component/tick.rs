@@ -0,0 +1,45 @@
+use std::cell::Cell;
+
+// Counter the world advances once per frame, writes are stamped with the current one.
+pub type Tick = u32;
+
+// When a component was added to its entity and last handed out mutably.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ComponentTicks {
+    added: Tick,
+    changed: Tick,
+}
+
+impl ComponentTicks {
+    pub fn new(tick: Tick) -> Self {
+        Self { added: tick, changed: tick }
+    }
+
+    pub fn added(&self) -> Tick {
+        self.added
+    }
+
+    pub fn changed(&self) -> Tick {
+        self.changed
+    }
+
+    pub fn is_added_after(&self, tick: Tick) -> bool {
+        self.added > tick
+    }
+
+    pub fn is_changed_after(&self, tick: Tick) -> bool {
+        self.changed > tick
+    }
+
+    pub fn set_changed(&mut self, tick: Tick) {
+        self.changed = self.changed.max(tick);
+    }
+}
+
+// Stamps a row through a shared column, queries do it when they hand the row out mutably.
+// The caller must own the row, like the component it hands out.
+pub(crate) fn set_changed_cell(ticks: &Cell<ComponentTicks>, tick: Tick) {
+    let mut changed = ticks.get();
+    changed.set_changed(tick);
+    ticks.set(changed);
+}
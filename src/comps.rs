@@ -1,35 +1,59 @@
-use crate::archetype::archetype_iter::{ArchetypeQueryIter, ArchetypeQueryIterMut};
-use crate::archetype::archetype_query::{ArchetypeQuery, ArchetypeQueryMut};
+use std::marker::PhantomData;
+use crate::archetype::archetype_filter::QueryFilter;
+use crate::archetype::archetype_iter::{ArchetypeQueryIter, ArchetypeQueryIterMut, ArchetypeTupleQueryIter, CombinationIter, CombinationIterMut};
+use crate::archetype::archetype_par::ParIter;
+use crate::archetype::archetype_query::{ArchetypeQuery, ArchetypeQueryMut, ArchetypeTupleQuery, QueryEntityError};
+use crate::archetype::query_data::{QueryData, ReadOnlyQueryData};
 use crate::commands::{EntityCommand, EntityCommands};
 use crate::component::component::Component;
+use crate::component::component_box::ComponentBox;
 use crate::entity::entity::EntityId;
+use crate::observer::observer::Event;
+use crate::relation::relation::Relation;
 use crate::resource::resource::Resource;
 
-pub struct Comps<'a, T: Component + 'static> {
+// F filters the entities, e.g. Comps<Position, With<Player>>
+pub struct Comps<'a, T: Component + 'static, F: QueryFilter = ()> {
     query: ArchetypeQuery<'a, T>,
+    filter: PhantomData<F>,
 }
 
-impl<'a, T: Component + 'static> Comps<'a, T> {
+impl<'a, T: Component + 'static, F: QueryFilter> Comps<'a, T, F> {
     pub fn new(query: ArchetypeQuery<'a, T>) -> Self {
-        Self { query }
+        Self { query, filter: PhantomData }
     }
 
-    pub fn iter(&self) -> ArchetypeQueryIter<T> {
+    pub fn iter(&self) -> ArchetypeQueryIter<'_, T> {
         ArchetypeQueryIter::new(&self.query)
     }
 
     pub fn query(&self, entity_id: EntityId) -> Option<&T> {
         self.query.query(entity_id)
     }
+
+    // e.g. for [(a, pos_a), (b, pos_b)] in positions.iter_combinations::<2>()
+    pub fn iter_combinations<const K: usize>(&self) -> CombinationIter<'_, T, K> {
+        CombinationIter::new(&self.query)
+    }
+
+    // One (entities, components) pair of slices per archetype, for loops the compiler can vectorise.
+    pub fn chunks(&self) -> impl Iterator<Item=(&'a [EntityId], &'a [T])> + '_ {
+        self.query.chunks()
+    }
+
+    pub fn par_iter(&self) -> ParIter<'_, &T> {
+        self.query.par_iter()
+    }
 }
 
-pub struct CompsMut<'a, T: Component + 'static> {
+pub struct CompsMut<'a, T: Component + 'static, F: QueryFilter = ()> {
     query: ArchetypeQueryMut<'a, T>,
+    filter: PhantomData<F>,
 }
 
-impl<'a, T: Component + 'static> CompsMut<'a, T> {
+impl<'a, T: Component + 'static, F: QueryFilter> CompsMut<'a, T, F> {
     pub fn new(query: ArchetypeQueryMut<'a, T>) -> Self {
-        Self { query }
+        Self { query, filter: PhantomData }
     }
 
 
@@ -41,9 +65,73 @@ impl<'a, T: Component + 'static> CompsMut<'a, T> {
         self.query.query_mut(id)
     }
 
+    // Not an Iterator, see CombinationIterMut::fetch_next
+    pub fn iter_combinations_mut<const K: usize>(&mut self) -> CombinationIterMut<'_, 'a, T, K> {
+        CombinationIterMut::new(&mut self.query)
+    }
+
+    // e.g. let [a, b] = bodies.get_many_mut([contact.a, contact.b])?;
+    pub fn get_many_mut<const N: usize>(&mut self, entities: [EntityId; N]) -> Result<[&mut T; N], QueryEntityError> {
+        self.query.get_many_mut(entities)
+    }
+
     pub fn query(&self, id: EntityId) -> Option<&T> {
         self.query.query(id)
     }
+
+    pub fn chunks(&self) -> impl Iterator<Item=(&'a [EntityId], &[T])> + '_ {
+        self.query.chunks()
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item=(&'a [EntityId], &mut [T])> + '_ {
+        self.query.chunks_mut()
+    }
+
+    pub fn par_iter_mut(&mut self) -> ParIter<'_, &mut T> {
+        self.query.par_iter_mut()
+    }
+}
+
+// Several components of the same entities, e.g. Query<(&mut Position, &Velocity), With<Player>>
+pub struct Query<'a, D: QueryData, F: QueryFilter = ()> {
+    query: ArchetypeTupleQuery<'a, D>,
+    filter: PhantomData<F>,
+}
+
+impl<'a, D: QueryData, F: QueryFilter> Query<'a, D, F> {
+    pub fn new(query: ArchetypeTupleQuery<'a, D>) -> Self {
+        Self { query, filter: PhantomData }
+    }
+
+    pub fn iter_mut(&mut self) -> ArchetypeTupleQueryIter<'_, 'a, D> {
+        self.query.iter_mut()
+    }
+
+    pub fn query_mut(&mut self, entity_id: EntityId) -> Option<D::Item<'_>> {
+        self.query.query_mut(entity_id)
+    }
+
+    pub fn get_many_mut<const N: usize>(&mut self, entities: [EntityId; N]) -> Result<[D::Item<'_>; N], QueryEntityError> {
+        self.query.get_many_mut(entities)
+    }
+
+    pub fn par_iter_mut(&mut self) -> ParIter<'_, D> {
+        self.query.par_iter_mut()
+    }
+}
+
+impl<'a, D: ReadOnlyQueryData, F: QueryFilter> Query<'a, D, F> {
+    pub fn iter(&self) -> ArchetypeTupleQueryIter<'_, 'a, D> {
+        self.query.iter()
+    }
+
+    pub fn query(&self, entity_id: EntityId) -> Option<D::Item<'_>> {
+        self.query.query(entity_id)
+    }
+
+    pub fn par_iter(&self) -> ParIter<'_, D> {
+        self.query.par_iter()
+    }
 }
 
 pub struct Res<'a, T: Resource> {
@@ -56,7 +144,7 @@ impl<'a, T: Resource> Res<'a, T> {
     }
 
     pub fn get(&self) -> &T {
-        &self.resource
+        self.resource
     }
 }
 
@@ -71,20 +159,20 @@ impl<'a, T: Resource> ResMut<'a, T> {
     }
 
     pub fn get(&self) -> &T {
-        &self.resource
+        self.resource
     }
 
     pub fn get_mut(&mut self) -> &mut T {
-        &mut self.resource
+        self.resource
     }
 }
 
-pub struct Commands<'a> {
-    commands: &'a mut EntityCommands,
+pub struct Commands<'a, 'w> {
+    commands: &'a mut EntityCommands<'w>,
 }
 
-impl<'a> Commands<'a> {
-    pub fn new(commands: &'a mut EntityCommands) -> Self {
+impl<'a, 'w> Commands<'a, 'w> {
+    pub fn new(commands: &'a mut EntityCommands<'w>) -> Self {
         Self { commands }
     }
 
@@ -95,4 +183,160 @@ impl<'a> Commands<'a> {
     pub fn remove(&mut self, entity_id: EntityId) {
         self.commands.release(entity_id)
     }
-}
\ No newline at end of file
+
+    pub(crate) fn reserve(&mut self) -> EntityId {
+        self.commands.reserve()
+    }
+
+    pub(crate) fn spawn_reserved(&mut self, entity_id: EntityId) -> &mut EntityCommand {
+        self.commands.spawn_reserved(entity_id)
+    }
+
+    pub(crate) fn unreserve(&mut self, entity_id: EntityId) {
+        self.commands.unreserve(entity_id)
+    }
+
+    pub fn add_box(&mut self, entity_id: EntityId, comp: ComponentBox) {
+        self.commands.add_box(entity_id, comp)
+    }
+
+    pub fn set_parent(&mut self, child: EntityId, parent: EntityId) {
+        self.commands.set_parent(child, parent)
+    }
+
+    pub fn remove_parent(&mut self, child: EntityId) {
+        self.commands.remove_parent(child)
+    }
+
+    // Removes the entity and all its descendants.
+    pub fn remove_recursive(&mut self, entity_id: EntityId) {
+        self.commands.release_recursive(entity_id)
+    }
+
+    pub fn relate<R: Relation>(&mut self, source: EntityId, relation: R) {
+        self.commands.relate(source, relation)
+    }
+
+    pub fn unrelate<R: Relation>(&mut self, source: EntityId, target: EntityId) {
+        self.commands.unrelate::<R>(source, target)
+    }
+
+    // The observers run when the commands are applied.
+    pub fn trigger<E: Event>(&mut self, event: E, target: EntityId) {
+        self.commands.trigger(event, target)
+    }
+
+    pub fn trigger_global<E: Event>(&mut self, event: E) {
+        self.commands.trigger_global(event)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::archetype::archetype_filter::{With, Without};
+    use crate::cow_macros::Component;
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Pos(u32);
+
+    #[derive(Component)]
+    struct Frozen;
+
+    #[derive(Component)]
+    struct Named;
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(storage = "sparse")]
+    struct Heat(u32);
+
+    // Pos(entity) on 9 entities spread over 3 archetypes, Heat(entity) on the odd ones
+    fn world() -> (World, Vec<u32>) {
+        let mut world = World::new();
+        let mut entities = vec![];
+        for i in 0..9 {
+            let entity_id = world.create();
+            world.add(entity_id, Pos(entity_id));
+            match i % 3 {
+                1 => world.add(entity_id, Frozen),
+                2 => world.add(entity_id, Named),
+                _ => (),
+            }
+            if entity_id % 2 == 1 {
+                world.add(entity_id, Heat(entity_id));
+            }
+            entities.push(entity_id);
+        }
+        (world, entities)
+    }
+
+    #[test]
+    fn chunks_line_up_the_entities_with_their_components() {
+        let (mut world, entities) = world();
+        let comps = world.comps::<Pos>();
+        assert_eq!(comps.chunks().count(), 3);
+        assert_eq!(comps.chunks().map(|(entities, positions)| {
+            assert_eq!(entities.len(), positions.len());
+            entities.iter().zip(positions).filter(|(entity_id, pos)| pos.0 == **entity_id).count()
+        }).sum::<usize>(), 9);
+
+        let heat = world.comps::<Heat>();
+        assert!(heat.chunks().all(|(entities, heat)| entities.iter().zip(heat).all(|(entity_id, heat)| heat.0 == *entity_id)));
+        assert_eq!(heat.chunks().map(|(entities, _)| entities.len()).sum::<usize>(), entities.iter().filter(|entity_id| *entity_id % 2 == 1).count());
+
+        // the filter splits the sparse set in runs, each still lined up
+        let mut positions = world.comps_filtered_mut::<Pos, Without<Frozen>>();
+        for (entities, positions) in positions.chunks_mut() {
+            assert_eq!(entities.len(), positions.len());
+            for (entity_id, pos) in entities.iter().zip(positions) {
+                assert_eq!(pos.0, *entity_id);
+                pos.0 += 100;
+            }
+        }
+        let mut heat = world.comps_filtered_mut::<Heat, Without<Frozen>>();
+        for (entities, heat) in heat.chunks_mut() {
+            for (entity_id, heat) in entities.iter().zip(heat) {
+                assert_eq!(heat.0, *entity_id);
+                heat.0 += 100;
+            }
+        }
+
+        let frozen: Vec<u32> = world.comps_filtered::<Pos, With<Frozen>>().iter().map(|(entity_id, _)| entity_id).collect();
+        for entity_id in entities {
+            let moved = if frozen.contains(&entity_id) { 0 } else { 100 };
+            assert_eq!(world.query::<Pos>(entity_id), Some(&Pos(entity_id + moved)));
+            if entity_id % 2 == 1 {
+                assert_eq!(world.query::<Heat>(entity_id), Some(&Heat(entity_id + moved)));
+            }
+        }
+    }
+
+    #[test]
+    fn tuple_queries_apply_their_filters() {
+        let (mut world, entities) = world();
+        let frozen: Vec<u32> = entities.iter().copied().skip(1).step_by(3).collect();
+        let named: Vec<u32> = entities.iter().copied().skip(2).step_by(3).collect();
+        let sorted = |mut entities: Vec<u32>| { entities.sort(); entities };
+
+        let query = world.query_tuple::<(&Pos, &Heat), Without<Frozen>>();
+        let hot = sorted(query.iter().map(|(entity_id, (pos, heat))| {
+            assert_eq!((pos.0, heat.0), (entity_id, entity_id));
+            entity_id
+        }).collect());
+        assert_eq!(hot, entities.iter().copied().filter(|entity_id| entity_id % 2 == 1 && !frozen.contains(entity_id)).collect::<Vec<_>>());
+        assert!(query.query(frozen[0]).is_none());
+
+        // a sparse filter is checked on each entity
+        let query = world.query_tuple::<(&Pos,), (With<Named>, Without<Heat>)>();
+        let cold = sorted(query.iter().map(|(entity_id, _)| entity_id).collect());
+        assert_eq!(cold, named.iter().copied().filter(|entity_id| entity_id % 2 == 0).collect::<Vec<_>>());
+
+        let mut query = world.query_tuple_mut::<(&mut Pos, &Heat), With<Frozen>>();
+        for (_, (pos, heat)) in query.iter_mut() {
+            pos.0 += heat.0;
+        }
+        for entity_id in entities {
+            let heated = frozen.contains(&entity_id) && entity_id % 2 == 1;
+            assert_eq!(world.query::<Pos>(entity_id), Some(&Pos(if heated { entity_id * 2 } else { entity_id })));
+        }
+    }
+}
@@ -1,31 +1,63 @@
 use std::collections::{HashSet};
 use crate::entity::entity::{EntityId};
 
+#[derive(Clone)]
 pub struct EntityManager {
     current: EntityId,
     frees: Vec<EntityId>,
     allocated: HashSet<EntityId>,
 }
 
+impl Default for EntityManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EntityManager {
     pub fn new() -> Self {
         Self { current: 0, frees: vec![], allocated: HashSet::new() }
     }
 
+    // Rebuilds the manager from the state given by current, frees and allocated.
+    pub fn from_parts(current: EntityId, frees: Vec<EntityId>, allocated: impl IntoIterator<Item=EntityId>) -> Self {
+        Self { current, frees, allocated: allocated.into_iter().collect() }
+    }
+
     pub fn create(&mut self) -> EntityId {
-        return if !self.frees.is_empty() {
-            self.frees.pop().unwrap()
+        if let Some(value) = self.frees.pop() {
+            self.allocated.insert(value);
+            value
         } else {
             self.current += 1; // entity 0 must never exist
             let value = self.current;
             self.allocated.insert(value);
             value
-        };
+        }
     }
 
     pub fn release(&mut self, id: EntityId) {
-        self.allocated.remove(&id);
-        self.frees.push(id)
+        // a dead id must not be handed out twice
+        if self.allocated.remove(&id) {
+            self.frees.push(id)
+        }
+    }
+
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        self.allocated.contains(&id)
+    }
+
+    // the last id given, ids past it were never used
+    pub fn current(&self) -> EntityId {
+        self.current
+    }
+
+    pub fn frees(&self) -> &Vec<EntityId> {
+        &self.frees
+    }
+
+    pub fn allocated(&self) -> impl Iterator<Item=EntityId> + '_ {
+        self.allocated.iter().copied()
     }
 
     pub fn count(&self) -> usize {
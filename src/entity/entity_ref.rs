@@ -0,0 +1,246 @@
+use std::fmt::{Display, Formatter};
+use crate::archetype::archetype_manager::aliases_access;
+use crate::archetype::query_data::{QueryData, ReadOnlyQueryData};
+use crate::component::component::Component;
+use crate::component::component_box::ComponentBox;
+use crate::component::registry::{ComponentId, ComponentInfo};
+use crate::entity::entity::EntityId;
+use crate::world::World;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EntityError {
+    // the entity was released or never created
+    Dead(EntityId),
+    // the entity lacks some of the requested components
+    MissingComponents(EntityId),
+    // the same component was requested mutably and a second time
+    AliasedMutability(EntityId),
+}
+
+impl Display for EntityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntityError::Dead(entity_id) => write!(f, "the entity {entity_id} is not alive"),
+            EntityError::MissingComponents(entity_id) => write!(f, "the entity {entity_id} lacks some of the components"),
+            EntityError::AliasedMutability(entity_id) => write!(f, "a component of the entity {entity_id} is borrowed mutably more than once"),
+        }
+    }
+}
+
+impl std::error::Error for EntityError {}
+
+// Read access to one entity, see World::entity
+#[derive(Clone, Copy)]
+pub struct EntityRef<'w> {
+    world: &'w World,
+    entity_id: EntityId,
+}
+
+impl<'w> EntityRef<'w> {
+    pub(crate) fn new(world: &'w World, entity_id: EntityId) -> Result<Self, EntityError> {
+        if !world.is_alive(entity_id) {
+            return Err(EntityError::Dead(entity_id));
+        }
+        Ok(Self { world, entity_id })
+    }
+
+    pub fn id(&self) -> EntityId {
+        self.entity_id
+    }
+
+    // The components of the entity, the ones of its archetype then the sparse ones.
+    pub fn components(&self) -> impl Iterator<Item=&'w ComponentInfo> + 'w {
+        let archetypes = self.world.archetypes();
+        archetypes.components_of(self.entity_id).map(|component_id| archetypes.components().info(component_id))
+    }
+
+    pub fn contains<T: Component + 'static>(&self) -> bool {
+        self.get::<T>().is_some()
+    }
+
+    pub fn contains_id(&self, component_id: ComponentId) -> bool {
+        self.world.archetypes().get_ptr(self.entity_id, component_id).is_some()
+    }
+
+    pub fn get<T: Component + 'static>(&self) -> Option<&'w T> {
+        self.world.archetypes().query::<T>(self.entity_id)
+    }
+
+    // Several components at once, e.g. entity.get_many::<(&Position, &Velocity)>()
+    // None unless the entity has all of them.
+    pub fn get_many<D: ReadOnlyQueryData>(&self) -> Option<D::Item<'w>> {
+        self.world.archetypes().query_many::<D>(self.entity_id)
+    }
+}
+
+// Read and write access to one entity, see World::entity_mut
+pub struct EntityMut<'w> {
+    world: &'w mut World,
+    entity_id: EntityId,
+}
+
+impl<'w> EntityMut<'w> {
+    pub(crate) fn new(world: &'w mut World, entity_id: EntityId) -> Result<Self, EntityError> {
+        if !world.is_alive(entity_id) {
+            return Err(EntityError::Dead(entity_id));
+        }
+        Ok(Self { world, entity_id })
+    }
+
+    pub fn id(&self) -> EntityId {
+        self.entity_id
+    }
+
+    pub fn as_readonly(&self) -> EntityRef<'_> {
+        EntityRef { world: self.world, entity_id: self.entity_id }
+    }
+
+    pub fn components(&self) -> impl Iterator<Item=&ComponentInfo> + '_ {
+        self.as_readonly().components()
+    }
+
+    pub fn contains<T: Component + 'static>(&self) -> bool {
+        self.as_readonly().contains::<T>()
+    }
+
+    pub fn contains_id(&self, component_id: ComponentId) -> bool {
+        self.as_readonly().contains_id(component_id)
+    }
+
+    pub fn get<T: Component + 'static>(&self) -> Option<&T> {
+        self.world.archetypes().query::<T>(self.entity_id)
+    }
+
+    // The component counts as changed at the current tick.
+    pub fn get_mut<T: Component + 'static>(&mut self) -> Option<&mut T> {
+        let (archetypes, _, _) = self.world.managers();
+        archetypes.query_mut::<T>(self.entity_id)
+    }
+
+    pub fn get_many<D: ReadOnlyQueryData>(&self) -> Option<D::Item<'_>> {
+        self.world.archetypes().query_many::<D>(self.entity_id)
+    }
+
+    // e.g. entity.get_many_mut::<(&mut Position, &Velocity)>(), a component can't be borrowed twice
+    pub fn get_many_mut<D: QueryData>(&mut self) -> Result<D::Item<'_>, EntityError> {
+        let entity_id = self.entity_id;
+        if aliases_access(&D::task_types()) {
+            return Err(EntityError::AliasedMutability(entity_id));
+        }
+        let (archetypes, _, _) = self.world.managers();
+        archetypes.query_many_mut::<D>(entity_id).ok_or(EntityError::MissingComponents(entity_id))
+    }
+
+    // Replaces the component if the entity already has one.
+    pub fn insert<T: Component + 'static>(&mut self, comp: T) -> &mut Self {
+        self.world.add(self.entity_id, comp);
+        self
+    }
+
+    pub fn insert_box(&mut self, comp: ComponentBox) -> &mut Self {
+        self.world.add_box(self.entity_id, comp);
+        self
+    }
+
+    pub fn remove<T: Component + 'static>(&mut self) -> &mut Self {
+        self.world.remove::<T>(self.entity_id);
+        self
+    }
+
+    pub fn remove_by_id(&mut self, component_id: ComponentId) -> &mut Self {
+        self.world.remove_by_id(self.entity_id, component_id);
+        self
+    }
+
+    pub fn world(&self) -> &World {
+        self.world
+    }
+
+    // Releases the entity, its children become roots, see World::release
+    pub fn despawn(self) {
+        self.world.release(self.entity_id);
+    }
+
+    pub fn despawn_recursive(self) {
+        self.world.despawn_recursive(self.entity_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cow_macros::Component;
+    use crate::entity::entity_ref::EntityError;
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Pos(i32);
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Vel(i32);
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    #[component(storage = "sparse")]
+    struct Marker(i32);
+
+    fn names(world: &World, entity_id: u32) -> Vec<String> {
+        world.entity(entity_id).unwrap().components().map(|info| info.short_name().to_string()).collect()
+    }
+
+    #[test]
+    fn components_get_and_get_many() {
+        let mut world = World::new();
+        let entity = world.create();
+        world.add(entity, Pos(1));
+        world.add(entity, Vel(2));
+        world.add(entity, Marker(3));
+
+        assert_eq!(names(&world, entity), ["Pos", "Vel", "Marker"]);
+        let entity_ref = world.entity(entity).unwrap();
+        assert_eq!(entity_ref.id(), entity);
+        assert_eq!(entity_ref.get::<Vel>(), Some(&Vel(2)));
+        assert!(entity_ref.contains::<Marker>());
+        assert_eq!(entity_ref.get_many::<(&Pos, &Marker)>(), Some((&Pos(1), &Marker(3))));
+
+        let mut entity_mut = world.entity_mut(entity).unwrap();
+        let (pos, vel) = entity_mut.get_many_mut::<(&mut Pos, &Vel)>().unwrap();
+        pos.0 += vel.0;
+        entity_mut.get_mut::<Marker>().unwrap().0 = 4;
+        assert_eq!(entity_mut.get_many::<(&Pos, &Marker)>(), Some((&Pos(3), &Marker(4))));
+    }
+
+    #[test]
+    fn get_many_mut_errors() {
+        let mut world = World::new();
+        let entity = world.create();
+        world.add(entity, Pos(1));
+        let tick = world.advance_tick();
+        let pos_id = world.archetypes().component_id::<Pos>().unwrap();
+
+        let mut entity_mut = world.entity_mut(entity).unwrap();
+        assert_eq!(entity_mut.get_many_mut::<(&mut Pos, &Pos)>().err(), Some(EntityError::AliasedMutability(entity)));
+        assert_eq!(entity_mut.get_many_mut::<(&mut Pos, &mut Pos)>().err(), Some(EntityError::AliasedMutability(entity)));
+        assert_eq!(entity_mut.get_many_mut::<(&mut Pos, &Vel)>().err(), Some(EntityError::MissingComponents(entity)));
+        // a miss doesn't count as a change
+        assert!(!world.archetypes().ticks(entity, pos_id).unwrap().is_changed_after(tick - 1));
+    }
+
+    #[test]
+    fn insert_remove_and_despawn() {
+        let mut world = World::new();
+        let entity = world.create();
+        let mut entity_mut = world.entity_mut(entity).unwrap();
+        entity_mut.insert(Pos(1)).insert(Marker(2)).insert(Pos(5));
+        assert_eq!(entity_mut.get::<Pos>(), Some(&Pos(5)));
+        entity_mut.remove::<Marker>();
+        assert!(!entity_mut.contains::<Marker>());
+        let pos_id = entity_mut.world().archetypes().component_id::<Pos>().unwrap();
+        entity_mut.remove_by_id(pos_id);
+        assert_eq!(entity_mut.components().count(), 0);
+
+        entity_mut.insert(Vel(1));
+        entity_mut.despawn();
+        assert!(!world.is_alive(entity));
+        assert_eq!(world.entity(entity).err(), Some(EntityError::Dead(entity)));
+        assert_eq!(world.entity_mut(entity).err(), Some(EntityError::Dead(entity)));
+    }
+}
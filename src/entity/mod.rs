@@ -1,2 +1,3 @@
+#[allow(clippy::module_inception)]
 pub mod entity;
-pub mod entity_manager;
\ No newline at end of file
+pub mod entity_manager;pub mod entity_ref;
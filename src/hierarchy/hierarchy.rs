@@ -0,0 +1,228 @@
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use crate::archetype::archetype_filter::QueryFilter;
+use crate::component::clone::{clone_value, CloneFn};
+use crate::component::component::{Component, ComponentAny};
+use crate::comps::Comps;
+use crate::entity::entity::EntityId;
+use crate::hierarchy::hierarchy_iter::{Ancestors, DescendantsBreadthFirst, DescendantsDepthFirst};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HierarchyError {
+    // the entity was released or never created
+    Dead(EntityId),
+    // an entity can't be its own parent
+    SelfParent(EntityId),
+    // the child is an ancestor of the parent, attaching it would make a cycle
+    Cycle { child: EntityId, parent: EntityId },
+}
+
+impl Display for HierarchyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HierarchyError::Dead(entity_id) => write!(f, "the entity {entity_id} is not alive"),
+            HierarchyError::SelfParent(entity_id) => write!(f, "the entity {entity_id} can't be its own parent"),
+            HierarchyError::Cycle { child, parent } => write!(f, "the entity {child} is an ancestor of {parent}"),
+        }
+    }
+}
+
+impl std::error::Error for HierarchyError {}
+
+// The parent of an entity, kept in sync with the Children of the parent by the world.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Parent(EntityId);
+
+impl Parent {
+    pub(crate) fn new(parent: EntityId) -> Self {
+        Self(parent)
+    }
+
+    pub fn get(&self) -> EntityId {
+        self.0
+    }
+}
+
+impl ComponentAny for Parent {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Component for Parent {
+    fn clone_fn() -> Option<CloneFn> {
+        Some(clone_value::<Self>)
+    }
+}
+
+// The children of an entity in insertion order, never empty.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Children(Vec<EntityId>);
+
+impl Children {
+    pub(crate) fn new(child: EntityId) -> Self {
+        Self(vec![child])
+    }
+
+    pub(crate) fn push(&mut self, child: EntityId) {
+        if !self.0.contains(&child) {
+            self.0.push(child);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, child: EntityId) {
+        self.0.retain(|entity_id| *entity_id != child);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, EntityId> {
+        self.0.iter()
+    }
+
+    pub fn as_slice(&self) -> &[EntityId] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl ComponentAny for Children {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Component for Children {
+    fn clone_fn() -> Option<CloneFn> {
+        Some(clone_value::<Self>)
+    }
+}
+
+// Hierarchy traversal from tasks, e.g. parents.ancestors(turret)
+impl<F: QueryFilter> Comps<'_, Parent, F> {
+    pub fn ancestors(&self, entity_id: EntityId) -> impl Iterator<Item=EntityId> + '_ {
+        Ancestors::new(entity_id, |entity_id| self.query(entity_id).map(Parent::get))
+    }
+}
+
+impl<F: QueryFilter> Comps<'_, Children, F> {
+    pub fn descendants_depth_first(&self, entity_id: EntityId) -> impl Iterator<Item=EntityId> + '_ {
+        DescendantsDepthFirst::new(entity_id, |entity_id| self.query(entity_id).map(Children::as_slice))
+    }
+
+    pub fn descendants_breadth_first(&self, entity_id: EntityId) -> impl Iterator<Item=EntityId> + '_ {
+        DescendantsBreadthFirst::new(entity_id, |entity_id| self.query(entity_id).map(Children::as_slice))
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl serde::Serialize for Parent {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de> serde::Deserialize<'de> for Parent {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        EntityId::deserialize(deserializer).map(Parent)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl serde::Serialize for Children {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de> serde::Deserialize<'de> for Children {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<EntityId>::deserialize(deserializer).map(Children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::comps::Commands;
+    use crate::commands::EntityCommands;
+    use crate::hierarchy::hierarchy::HierarchyError;
+    use crate::world::World;
+
+    #[test]
+    fn set_parent_keeps_children_in_sync() {
+        let mut world = World::new();
+        let (root, first, second) = (world.create(), world.create(), world.create());
+        world.set_parent(first, root).unwrap();
+        world.set_parent(second, root).unwrap();
+        assert_eq!(world.children(root), &[first, second]);
+
+        world.set_parent(second, first).unwrap();
+        assert_eq!(world.children(root), &[first]);
+        assert_eq!(world.children(first), &[second]);
+        assert_eq!(world.ancestors(second).collect::<Vec<_>>(), vec![first, root]);
+
+        world.remove_parent(first);
+        assert!(world.children(root).is_empty());
+        assert_eq!(world.parent(first), None);
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut world = World::new();
+        let (root, child, grandchild) = (world.create(), world.create(), world.create());
+        world.set_parent(child, root).unwrap();
+        world.set_parent(grandchild, child).unwrap();
+
+        assert_eq!(world.set_parent(root, root), Err(HierarchyError::SelfParent(root)));
+        assert_eq!(world.set_parent(root, grandchild), Err(HierarchyError::Cycle { child: root, parent: grandchild }));
+        let dead = world.create();
+        world.release(dead);
+        assert_eq!(world.set_parent(dead, dead), Err(HierarchyError::Dead(dead)));
+        assert_eq!(world.set_parent(child, dead), Err(HierarchyError::Dead(dead)));
+
+        // nothing moved
+        assert_eq!(world.parent(root), None);
+        assert_eq!(world.parent(child), Some(root));
+        assert!(world.children(grandchild).is_empty());
+    }
+
+    #[test]
+    fn commands_making_a_cycle_are_dropped() {
+        let mut world = World::new();
+        let (parent, child) = (world.create(), world.create());
+        world.set_parent(child, parent).unwrap();
+
+        let (_, _, entities) = world.managers();
+        let mut entity_commands = EntityCommands::new(entities);
+        let mut commands = Commands::new(&mut entity_commands);
+        commands.set_parent(parent, child);
+        let commands = entity_commands.take_commands();
+        world.apply_commands(commands);
+        assert_eq!(world.parent(parent), None);
+        assert_eq!(world.parent(child), Some(parent));
+    }
+
+    #[test]
+    fn despawn_recursive_releases_the_descendants() {
+        let mut world = World::new();
+        let (root, child, grandchild) = (world.create(), world.create(), world.create());
+        let (other_root, other) = (world.create(), world.create());
+        world.set_parent(child, root).unwrap();
+        world.set_parent(grandchild, child).unwrap();
+        world.set_parent(other, other_root).unwrap();
+
+        world.despawn_recursive(child);
+        assert!(world.is_alive(root));
+        assert!(!world.is_alive(child));
+        assert!(!world.is_alive(grandchild));
+        assert!(world.is_alive(other));
+        assert!(world.children(root).is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use crate::entity::entity::EntityId;
+
+// Walks up the hierarchy, from the parent of an entity to its root.
+pub struct Ancestors<P> {
+    parent_of: P,
+    current: EntityId,
+}
+
+impl<P: Fn(EntityId) -> Option<EntityId>> Ancestors<P> {
+    pub fn new(entity_id: EntityId, parent_of: P) -> Self {
+        Self { parent_of, current: entity_id }
+    }
+}
+
+impl<P: Fn(EntityId) -> Option<EntityId>> Iterator for Ancestors<P> {
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let parent = (self.parent_of)(self.current)?;
+        self.current = parent;
+        Some(parent)
+    }
+}
+
+// Visits every descendant of an entity, each subtree before the next sibling.
+pub struct DescendantsDepthFirst<'a, C> {
+    children_of: C,
+    stack: Vec<EntityId>,
+    _marker: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a, C: Fn(EntityId) -> Option<&'a [EntityId]>> DescendantsDepthFirst<'a, C> {
+    pub fn new(entity_id: EntityId, children_of: C) -> Self {
+        let stack = children_of(entity_id).map(|children| children.iter().rev().copied().collect()).unwrap_or_default();
+        Self { children_of, stack, _marker: std::marker::PhantomData }
+    }
+}
+
+impl<'a, C: Fn(EntityId) -> Option<&'a [EntityId]>> Iterator for DescendantsDepthFirst<'a, C> {
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entity_id = self.stack.pop()?;
+        if let Some(children) = (self.children_of)(entity_id) {
+            self.stack.extend(children.iter().rev());
+        }
+        Some(entity_id)
+    }
+}
+
+// Visits every descendant of an entity, level by level.
+pub struct DescendantsBreadthFirst<'a, C> {
+    children_of: C,
+    queue: VecDeque<EntityId>,
+    _marker: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a, C: Fn(EntityId) -> Option<&'a [EntityId]>> DescendantsBreadthFirst<'a, C> {
+    pub fn new(entity_id: EntityId, children_of: C) -> Self {
+        let queue = children_of(entity_id).map(|children| children.iter().copied().collect()).unwrap_or_default();
+        Self { children_of, queue, _marker: std::marker::PhantomData }
+    }
+}
+
+impl<'a, C: Fn(EntityId) -> Option<&'a [EntityId]>> Iterator for DescendantsBreadthFirst<'a, C> {
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entity_id = self.queue.pop_front()?;
+        if let Some(children) = (self.children_of)(entity_id) {
+            self.queue.extend(children.iter());
+        }
+        Some(entity_id)
+    }
+}
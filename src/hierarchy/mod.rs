@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod hierarchy;
+pub mod hierarchy_iter;
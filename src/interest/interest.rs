@@ -0,0 +1,163 @@
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use crate::component::component::{Component, ComponentAny};
+use crate::component::tick::Tick;
+use crate::entity::entity::EntityId;
+use crate::interest::interest_grid::SpatialGrid;
+use crate::resource::resource::Resource;
+
+// A position the interest task groups the entities by, e.g. [x, y, 0.0] for a 2d game.
+pub trait InterestPosition: Component + 'static {
+    fn position(&self) -> [f32; 3];
+}
+
+// An entity sent to a client, it sees the entities within range of its position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Observer {
+    range: f32,
+}
+
+impl Observer {
+    pub fn new(range: f32) -> Self {
+        Self { range }
+    }
+
+    pub fn range(&self) -> f32 {
+        self.range
+    }
+
+    pub fn set_range(&mut self, range: f32) {
+        self.range = range;
+    }
+}
+
+impl ComponentAny for Observer {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Component for Observer {}
+
+// Seen by every observer whatever the distance and the rules, e.g. the entity holding the match score.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AlwaysRelevant;
+
+impl ComponentAny for AlwaysRelevant {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Component for AlwaysRelevant {}
+
+// What an observer sees, and what entered and left its view on the last update.
+#[derive(Debug, Default)]
+pub struct ObserverView {
+    // visible entity to the tick it entered the view at
+    visible: HashMap<EntityId, Tick>,
+    entered: Vec<EntityId>,
+    left: Vec<EntityId>,
+    // every entity that left the view, kept until pruned
+    left_log: Vec<(Tick, EntityId)>,
+}
+
+impl ObserverView {
+    pub fn is_visible(&self, entity_id: EntityId) -> bool {
+        self.visible.contains_key(&entity_id)
+    }
+
+    pub fn visible(&self) -> impl Iterator<Item=EntityId> + '_ {
+        self.visible.keys().copied()
+    }
+
+    pub fn entered(&self) -> &[EntityId] {
+        &self.entered
+    }
+
+    pub fn left(&self) -> &[EntityId] {
+        &self.left
+    }
+
+    // The visible entities that were already visible before the last update.
+    pub fn stayed(&self) -> impl Iterator<Item=EntityId> + '_ {
+        let entered: HashSet<EntityId> = self.entered.iter().copied().collect();
+        self.visible().filter(move |entity_id| !entered.contains(entity_id))
+    }
+
+    pub fn entered_tick(&self, entity_id: EntityId) -> Option<Tick> {
+        self.visible.get(&entity_id).copied()
+    }
+
+    // The entities that left the view after tick, they may have come back since.
+    pub fn left_after(&self, tick: Tick) -> impl Iterator<Item=EntityId> + '_ {
+        let start = self.left_log.partition_point(|(left_tick, _)| *left_tick <= tick);
+        self.left_log[start..].iter().map(|(_, entity_id)| *entity_id)
+    }
+
+    fn update(&mut self, visible: HashSet<EntityId>, tick: Tick) {
+        self.entered.clear();
+        self.left.clear();
+        self.visible.retain(|entity_id, _| {
+            let stays = visible.contains(entity_id);
+            if !stays {
+                self.left.push(*entity_id);
+                self.left_log.push((tick, *entity_id));
+            }
+            stays
+        });
+        for entity_id in visible {
+            if let std::collections::hash_map::Entry::Vacant(entry) = self.visible.entry(entity_id) {
+                entry.insert(tick);
+                self.entered.push(entity_id);
+            }
+        }
+    }
+}
+
+// The view of every observer, kept up to date by InterestTask.
+pub struct Interest {
+    views: HashMap<EntityId, ObserverView>,
+    grid: SpatialGrid,
+}
+
+impl Interest {
+    // Cells a bit larger than the usual observer range keep the lookups to a few cells.
+    pub fn new(cell_size: f32) -> Self {
+        Self { views: HashMap::new(), grid: SpatialGrid::new(cell_size) }
+    }
+
+    pub fn view(&self, observer: EntityId) -> Option<&ObserverView> {
+        self.views.get(&observer)
+    }
+
+    pub fn views(&self) -> impl Iterator<Item=(EntityId, &ObserverView)> {
+        self.views.iter().map(|(observer, view)| (*observer, view))
+    }
+
+    // Forgets the entities that left a view at or before tick, e.g. the oldest tick its client acknowledged.
+    pub fn prune(&mut self, tick: Tick) {
+        for view in self.views.values_mut() {
+            view.left_log.retain(|(left_tick, _)| *left_tick > tick);
+        }
+    }
+
+    pub(crate) fn grid_mut(&mut self) -> &mut SpatialGrid {
+        &mut self.grid
+    }
+
+    pub(crate) fn grid(&self) -> &SpatialGrid {
+        &self.grid
+    }
+
+    pub(crate) fn update(&mut self, observer: EntityId, visible: HashSet<EntityId>, tick: Tick) {
+        self.views.entry(observer).or_default().update(visible, tick);
+    }
+
+    // Drops the views of the entities that stopped being observers.
+    pub(crate) fn retain_observers(&mut self, observers: &HashSet<EntityId>) {
+        self.views.retain(|observer, _| observers.contains(observer));
+    }
+}
+
+impl Resource for Interest {}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use crate::entity::entity::EntityId;
+
+pub type Cell = [i32; 3];
+
+// Buckets the entities by cube of cell_size, a range query only looks at the cells it overlaps.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<(EntityId, [f32; 3])>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "the cells of a grid must have a size");
+        Self { cell_size, cells: HashMap::new() }
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    // Empties the grid, the buckets keep their memory for the next fill.
+    pub fn clear(&mut self) {
+        self.cells.values_mut().for_each(Vec::clear);
+    }
+
+    pub fn insert(&mut self, entity_id: EntityId, position: [f32; 3]) {
+        self.cells.entry(self.cell_of(position)).or_default().push((entity_id, position));
+    }
+
+    pub fn cell_of(&self, position: [f32; 3]) -> Cell {
+        position.map(|axis| (axis / self.cell_size).floor() as i32)
+    }
+
+    // The entities of the cells a sphere of range around any point of cell can reach,
+    // the caller checks the exact distance.
+    pub fn around(&self, cell: Cell, range: f32) -> impl Iterator<Item=(EntityId, [f32; 3])> + '_ {
+        let reach = (range / self.cell_size).ceil() as i32;
+        (-reach..=reach).flat_map(move |x| (-reach..=reach).flat_map(move |y| (-reach..=reach).map(move |z| [cell[0] + x, cell[1] + y, cell[2] + z])))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
@@ -0,0 +1,260 @@
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::commands::EntityCommands;
+use crate::component::component::Component;
+use crate::entity::entity::EntityId;
+use crate::interest::interest::{AlwaysRelevant, Interest, InterestPosition, Observer};
+use crate::interest::interest_grid::Cell;
+use crate::resource::res_manager::ResManager;
+use crate::schedule::task_state::TaskState;
+use crate::schedule::task_type::TaskType;
+use crate::Task;
+
+type InterestRule = Box<dyn Fn(&ArchetypeManager, EntityId, EntityId) -> bool + Send + Sync>;
+// the cell and the bits of the range of the observers in the group
+type ObserverGroup = (Cell, u32);
+
+// Updates the Interest resource from the positions P of the entities,
+// an entity is visible to an observer within its range if every rule accepts it.
+pub struct InterestTask<P> {
+    rules: Vec<InterestRule>,
+    // the components the rules read, so the scheduler orders the task after their writers
+    reads: Vec<TypeId>,
+    _marker: PhantomData<fn() -> P>,
+}
+
+impl<P: InterestPosition> Default for InterestTask<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: InterestPosition> InterestTask<P> {
+    pub fn new() -> Self {
+        Self { rules: vec![], reads: vec![], _marker: PhantomData }
+    }
+
+    // Filters the entities on a component of the observer and of the entity, e.g. the same Team.
+    pub fn with_rule<C: Component + 'static>(mut self, rule: impl Fn(Option<&C>, Option<&C>) -> bool + Send + Sync + 'static) -> Self {
+        self.reads.push(TypeId::of::<C>());
+        self.rules.push(Box::new(move |archetypes, observer, entity_id| {
+            rule(archetypes.query::<C>(observer), archetypes.query::<C>(entity_id))
+        }));
+        self
+    }
+
+    fn accepts(&self, archetypes: &ArchetypeManager, observer: EntityId, entity_id: EntityId) -> bool {
+        self.rules.iter().all(|rule| rule(archetypes, observer, entity_id))
+    }
+}
+
+impl<P: InterestPosition> Task for InterestTask<P> {
+    fn name(&self) -> String {
+        format!("InterestTask<{}>", std::any::type_name::<P>())
+    }
+
+    fn arguments(&self) -> Vec<TaskType> {
+        let mut arguments = vec![
+            TaskType::Comp(TypeId::of::<P>()),
+            TaskType::Comp(TypeId::of::<Observer>()),
+            TaskType::Comp(TypeId::of::<AlwaysRelevant>()),
+            TaskType::ResMut(TypeId::of::<Interest>()),
+        ];
+        arguments.extend(self.reads.iter().map(|type_id| TaskType::Comp(*type_id)));
+        arguments
+    }
+
+    fn run(&self, archetypes: &mut ArchetypeManager, _commands: &mut EntityCommands<'_>, res: &ResManager, _state: &mut TaskState) {
+        let Some(interest) = res.query::<Interest>() else {
+            return;
+        };
+        let mut interest = interest.resource().write().unwrap();
+        let archetypes: &ArchetypeManager = archetypes;
+        let tick = archetypes.change_tick();
+
+        let grid = interest.grid_mut();
+        grid.clear();
+        for (entity_id, position) in archetypes.fetch_info::<P>().iter() {
+            grid.insert(entity_id, position.position());
+        }
+        let always_relevant: Vec<EntityId> = archetypes.fetch_info::<AlwaysRelevant>().iter().map(|(entity_id, _)| entity_id).collect();
+
+        // observers in the same cell with the same range look up the grid once for all of them
+        let mut groups: HashMap<ObserverGroup, Vec<(EntityId, [f32; 3])>> = HashMap::new();
+        let mut observers = HashSet::new();
+        let mut views = vec![];
+        for (observer, view) in archetypes.fetch_info::<Observer>().iter() {
+            observers.insert(observer);
+            match archetypes.query::<P>(observer).map(P::position) {
+                Some(position) => {
+                    let key = (interest.grid().cell_of(position), view.range().to_bits());
+                    groups.entry(key).or_default().push((observer, position));
+                }
+                // an observer without a position only sees itself and the relevant entities
+                None => views.push((observer, HashSet::new())),
+            }
+        }
+
+        for ((cell, range), members) in groups {
+            let range = f32::from_bits(range);
+            let candidates: Vec<(EntityId, [f32; 3])> = interest.grid().around(cell, range).collect();
+            for (observer, position) in members {
+                let visible = candidates.iter()
+                    .filter(|(_, other)| distance_squared(position, *other) <= range * range)
+                    .filter(|(entity_id, _)| self.accepts(archetypes, observer, *entity_id))
+                    .map(|(entity_id, _)| *entity_id)
+                    .collect();
+                views.push((observer, visible));
+            }
+        }
+
+        for (observer, mut visible) in views {
+            visible.extend(always_relevant.iter().copied());
+            // a client always knows about its own entity
+            visible.insert(observer);
+            interest.update(observer, visible, tick);
+        }
+        interest.retain_observers(&observers);
+    }
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|axis| (a[axis] - b[axis]) * (a[axis] - b[axis])).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cow_macros::Component;
+    use crate::entity::entity::EntityId;
+    use crate::interest::interest::{AlwaysRelevant, Interest, InterestPosition, Observer};
+    use crate::interest::interest_task::InterestTask;
+    use crate::scheduler::Scheduler;
+    use crate::world::World;
+
+    #[derive(Component, Clone, Copy)]
+    struct Pos([f32; 3]);
+
+    impl InterestPosition for Pos {
+        fn position(&self) -> [f32; 3] {
+            self.0
+        }
+    }
+
+    #[derive(Component, Clone, Copy, PartialEq)]
+    struct Team(u8);
+
+    fn spawn(world: &mut World, x: f32) -> EntityId {
+        let entity_id = world.create();
+        world.add(entity_id, Pos([x, 0.0, 0.0]));
+        entity_id
+    }
+
+    fn observer(world: &mut World, x: f32, range: f32) -> EntityId {
+        let entity_id = spawn(world, x);
+        world.add(entity_id, Observer::new(range));
+        entity_id
+    }
+
+    fn sorted(entities: impl IntoIterator<Item=EntityId>) -> Vec<EntityId> {
+        let mut entities: Vec<EntityId> = entities.into_iter().collect();
+        entities.sort();
+        entities
+    }
+
+    // (visible, entered, left) for the observer
+    fn view(world: &World, observer: EntityId) -> (Vec<EntityId>, Vec<EntityId>, Vec<EntityId>) {
+        let interest = world.resources().query::<Interest>().unwrap().resource().read().unwrap();
+        let view = interest.view(observer).unwrap();
+        (sorted(view.visible()), sorted(view.entered().to_vec()), sorted(view.left().to_vec()))
+    }
+
+    fn has_view(world: &World, observer: EntityId) -> bool {
+        world.resources().query::<Interest>().unwrap().resource().read().unwrap().view(observer).is_some()
+    }
+
+    fn setup(cell_size: f32, task: InterestTask<Pos>) -> (World, Scheduler) {
+        let mut world = World::new();
+        world.set_res(Interest::new(cell_size));
+        let mut scheduler = Scheduler::new();
+        scheduler.add_task(task);
+        (world, scheduler)
+    }
+
+    #[test]
+    fn views_follow_the_entities_moving() {
+        let (mut world, mut scheduler) = setup(4.0, InterestTask::new());
+        let client = observer(&mut world, 0.0, 5.0);
+        let (near, far) = (spawn(&mut world, 3.0), spawn(&mut world, 10.0));
+        scheduler.run(&mut world);
+        assert_eq!(view(&world, client), (sorted([client, near]), sorted([client, near]), vec![]));
+
+        let first = world.advance_tick();
+        world.get_mut::<Pos>(near).unwrap().0[0] = 20.0;
+        world.get_mut::<Pos>(far).unwrap().0[0] = -4.0;
+        scheduler.run(&mut world);
+        assert_eq!(view(&world, client), (sorted([client, far]), vec![far], vec![near]));
+        {
+            let interest = world.resources().query::<Interest>().unwrap().resource().read().unwrap();
+            let view = interest.view(client).unwrap();
+            assert_eq!(view.stayed().collect::<Vec<_>>(), [client]);
+            assert_eq!(view.entered_tick(far), Some(first));
+            assert_eq!(view.left_after(first - 1).collect::<Vec<_>>(), [near]);
+        }
+
+        // nothing moved, nothing entered or left
+        world.advance_tick();
+        scheduler.run(&mut world);
+        assert_eq!(view(&world, client), (sorted([client, far]), vec![], vec![]));
+    }
+
+    #[test]
+    fn ranges_reach_across_cells() {
+        let (mut world, mut scheduler) = setup(4.0, InterestTask::new());
+        let client = observer(&mut world, 3.9, 2.0);
+        let next_cell = spawn(&mut world, 4.1);
+        let at_range = spawn(&mut world, 5.9);
+        let behind = spawn(&mut world, -0.5);
+        let wide = observer(&mut world, 0.0, 9.0);
+        let three_cells_away = spawn(&mut world, 8.9);
+        scheduler.run(&mut world);
+
+        assert_eq!(view(&world, client).0, sorted([client, next_cell, at_range]));
+        assert_eq!(view(&world, wide).0, sorted([wide, client, next_cell, at_range, behind, three_cells_away]));
+    }
+
+    #[test]
+    fn rules_filter_the_entities_in_range() {
+        let task = InterestTask::new().with_rule::<Team>(|observer, entity| entity.is_none() || observer == entity);
+        let (mut world, mut scheduler) = setup(4.0, task);
+        let client = observer(&mut world, 0.0, 5.0);
+        world.add(client, Team(1));
+        let (ally, enemy, neutral, score) = (spawn(&mut world, 1.0), spawn(&mut world, 1.0), spawn(&mut world, 1.0), spawn(&mut world, 100.0));
+        world.add(ally, Team(1));
+        world.add(enemy, Team(2));
+        world.add(score, Team(2));
+        world.add(score, AlwaysRelevant);
+        scheduler.run(&mut world);
+
+        assert_eq!(view(&world, client).0, sorted([client, ally, neutral, score]));
+    }
+
+    #[test]
+    fn views_of_removed_observers_are_dropped() {
+        let (mut world, mut scheduler) = setup(4.0, InterestTask::new());
+        let (a, b) = (observer(&mut world, 0.0, 5.0), observer(&mut world, 1.0, 5.0));
+        let unplaced = world.create();
+        world.add(unplaced, Observer::new(5.0));
+        scheduler.run(&mut world);
+        assert!(has_view(&world, a) && has_view(&world, b));
+        // without a position it only sees itself
+        assert_eq!(view(&world, unplaced).0, [unplaced]);
+
+        world.remove::<Observer>(a);
+        world.release(b);
+        scheduler.run(&mut world);
+        assert!(!has_view(&world, a) && !has_view(&world, b));
+        assert!(has_view(&world, unplaced));
+    }
+}
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+pub mod interest;
+pub mod interest_grid;
+pub mod interest_task;
@@ -1,7 +1,10 @@
+// lets the derive macros, which name cow_ecs, be used inside the crate's own tests
+extern crate self as cow_ecs;
 #[allow(unused)]
 #[allow(dead_code)]
 pub mod scheduler;
 pub mod world;
+pub mod snapshot;
 pub mod schedule;
 pub mod component;
 pub mod entity;
@@ -15,7 +18,16 @@ use crate::resource::res_manager::ResManager;
 
 pub mod commands;
 pub mod archetype;
+pub mod hierarchy;
+pub mod interest;
+pub mod observer;
+pub mod relation;
+pub mod reflect;
+pub mod scene;
+#[cfg(feature = "serialize")]
+pub mod serialize;
 
+use crate::schedule::task_state::TaskState;
 use crate::schedule::task_type::TaskType;
 
 
@@ -24,7 +36,9 @@ pub trait Task {
 
     fn arguments(&self) -> Vec<TaskType>;
 
+    // state is kept by whoever runs the task, see TaskState
     fn run(&self, comps: &mut ArchetypeManager,
-           commands: &mut EntityCommands,
-           res: &ResManager);
+           commands: &mut EntityCommands<'_>,
+           res: &ResManager,
+           state: &mut TaskState);
 }
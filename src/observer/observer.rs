@@ -0,0 +1,196 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::commands::EntityCommands;
+use crate::entity::entity::EntityId;
+use crate::resource::res_manager::ResManager;
+use crate::schedule::task_state::TaskState;
+
+// Something triggered on an entity or on the whole world, see World::trigger
+pub trait Event: Send + Sync + 'static {
+    // once the observers ran for an entity, run them again for its parent, and so on up the hierarchy
+    const PROPAGATE: bool = false;
+}
+
+// The event given to an observer, the first parameter of a #[cow_task] observing E.
+pub struct Trigger<'a, E: Event> {
+    event: &'a E,
+    // the entity the event is at, it moves up the hierarchy while propagating
+    target: Option<EntityId>,
+    // the entity the event was triggered on
+    origin: Option<EntityId>,
+    propagate: &'a mut bool,
+}
+
+impl<'a, E: Event> Trigger<'a, E> {
+    pub(crate) fn new(event: &'a E, target: Option<EntityId>, origin: Option<EntityId>, propagate: &'a mut bool) -> Self {
+        Self { event, target, origin, propagate }
+    }
+
+    pub fn event(&self) -> &E {
+        self.event
+    }
+
+    // None for a global trigger
+    pub fn target(&self) -> Option<EntityId> {
+        self.target
+    }
+
+    pub fn origin(&self) -> Option<EntityId> {
+        self.origin
+    }
+
+    // Starts or stops the propagation to the parent of the target, E::PROPAGATE being the default.
+    pub fn propagate(&mut self, propagate: bool) {
+        *self.propagate = propagate;
+    }
+}
+
+// Implemented by #[cow_task] for the functions taking a Trigger<E>.
+pub trait Observer<E: Event>: Send + Sync {
+    fn name(&self) -> String;
+
+    // state is kept by the world, see TaskState
+    fn run(&self, trigger: Trigger<'_, E>,
+           comps: &mut ArchetypeManager,
+           commands: &mut EntityCommands<'_>,
+           res: &ResManager,
+           state: &mut TaskState);
+}
+
+pub type ObserverList<E> = Vec<Arc<dyn Observer<E>>>;
+
+// The observers of each event type, in registration order.
+#[derive(Clone, Default)]
+pub struct Observers {
+    // an ObserverList<E> for each event
+    observers: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<E: Event>(&mut self, observer: impl Observer<E> + 'static) {
+        let mut observers = self.get::<E>().map(|observers| observers.as_ref().clone()).unwrap_or_default();
+        observers.push(Arc::new(observer));
+        self.observers.insert(TypeId::of::<E>(), Arc::new(observers));
+    }
+
+    pub fn get<E: Event>(&self) -> Option<Arc<ObserverList<E>>> {
+        let observers = self.observers.get(&TypeId::of::<E>())?.clone();
+        observers.downcast::<ObserverList<E>>().ok()
+    }
+
+    pub fn names<E: Event>(&self) -> Vec<String> {
+        self.get::<E>().map(|observers| observers.iter().map(|observer| observer.name()).collect()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::EntityCommands;
+    use crate::comps::Commands;
+    use crate::cow_macros::{cow_task, Component, Event, Resource};
+    use crate::entity::entity::EntityId;
+    use crate::world::World;
+
+    #[derive(Event)]
+    struct Ping;
+
+    // the target of each Ping seen
+    #[derive(Resource)]
+    struct Seen(Vec<Option<EntityId>>);
+
+    #[cow_task]
+    fn on_ping(trigger: Trigger<Ping>, mut seen: ResMut<Seen>) {
+        seen.get_mut().0.push(trigger.target());
+    }
+
+    #[derive(Event)]
+    #[event(propagate)]
+    struct Bubble;
+
+    // the (target, origin) of each Bubble seen
+    #[derive(Resource)]
+    struct Path(Vec<(Option<EntityId>, Option<EntityId>)>);
+
+    // stops the Bubbles reaching the entity
+    #[derive(Component)]
+    struct Stop;
+
+    #[cow_task]
+    fn on_bubble(mut trigger: Trigger<Bubble>, stops: Comps<Stop>, mut path: ResMut<Path>) {
+        path.get_mut().0.push((trigger.target(), trigger.origin()));
+        if trigger.target().is_some_and(|target| stops.query(target).is_some()) {
+            trigger.propagate(false);
+        }
+    }
+
+    // child -> parent -> grandparent
+    fn family(world: &mut World) -> [EntityId; 3] {
+        let [child, parent, grandparent] = [world.create(), world.create(), world.create()];
+        world.set_parent(child, parent).unwrap();
+        world.set_parent(parent, grandparent).unwrap();
+        [child, parent, grandparent]
+    }
+
+    fn path(world: &World) -> Vec<(Option<EntityId>, Option<EntityId>)> {
+        std::mem::take(&mut world.resources().query::<Path>().unwrap().resource().write().unwrap().0)
+    }
+
+    fn seen(world: &World) -> Vec<Option<EntityId>> {
+        world.resources().query::<Seen>().unwrap().resource().read().unwrap().0.clone()
+    }
+
+    #[test]
+    fn commands_trigger_global_and_targeted_events() {
+        let mut world = World::new();
+        world.set_res(Seen(vec![]));
+        world.observe(on_ping);
+        let target = world.create();
+
+        let (_, _, entities) = world.managers();
+        let mut entity_commands = EntityCommands::new(entities);
+        let mut commands = Commands::new(&mut entity_commands);
+        commands.trigger_global(Ping);
+        commands.trigger(Ping, target);
+        let commands = entity_commands.take_commands();
+        world.apply_commands(commands);
+        assert_eq!(seen(&world), [None, Some(target)]);
+    }
+
+    #[test]
+    fn events_propagate_up_the_hierarchy() {
+        let mut world = World::new();
+        world.set_res(Seen(vec![]));
+        world.set_res(Path(vec![]));
+        world.observe(on_ping);
+        world.observe(on_bubble);
+        let [child, parent, grandparent] = family(&mut world);
+
+        world.trigger(Bubble, child);
+        assert_eq!(path(&world), [(Some(child), Some(child)), (Some(parent), Some(child)), (Some(grandparent), Some(child))]);
+
+        // Ping doesn't propagate
+        world.trigger(Ping, child);
+        assert_eq!(seen(&world), [Some(child)]);
+    }
+
+    #[test]
+    fn an_observer_stops_the_propagation() {
+        let mut world = World::new();
+        world.set_res(Path(vec![]));
+        world.observe(on_bubble);
+        let [child, parent, _] = family(&mut world);
+        world.add(parent, Stop);
+
+        world.trigger(Bubble, child);
+        assert_eq!(path(&world), [(Some(child), Some(child)), (Some(parent), Some(child))]);
+
+        world.trigger(Bubble, parent);
+        assert_eq!(path(&world), [(Some(parent), Some(parent))]);
+    }
+}
@@ -0,0 +1,274 @@
+use std::any::Any;
+use std::fmt::{Debug, Display, Formatter};
+
+// The name of a field and its type as written in the struct.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FieldInfo {
+    name: &'static str,
+    type_name: &'static str,
+}
+
+impl FieldInfo {
+    pub const fn new(name: &'static str, type_name: &'static str) -> Self {
+        Self { name, type_name }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+// Access to the fields of a value whose type is only known at runtime, derived with #[derive(Reflect)].
+// Tuple struct fields are named 0, 1, ...
+pub trait Reflect: Any {
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    fn field_infos() -> &'static [FieldInfo] where Self: Sized {
+        &[]
+    }
+
+    fn fields(&self) -> &'static [FieldInfo] {
+        &[]
+    }
+
+    fn field(&self, _name: &str) -> Option<&dyn Reflect> {
+        None
+    }
+
+    fn field_mut(&mut self, _name: &str) -> Option<&mut dyn Reflect> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    // Writes the value the way Debug would.
+    fn reflect_fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReflectError {
+    UnknownField(String),
+    // the field and its type
+    WrongType(String, &'static str),
+}
+
+impl Display for ReflectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReflectError::UnknownField(path) => write!(f, "unknown field {path}"),
+            ReflectError::WrongType(path, type_name) => write!(f, "the field {path} is a {type_name}"),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+impl dyn Reflect {
+    pub fn downcast_ref<T: Reflect>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Reflect>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+
+    // A nested field, e.g. "transform.position.x"
+    pub fn path(&self, path: &str) -> Option<&dyn Reflect> {
+        path.split('.').try_fold(self, |value, name| value.field(name))
+    }
+
+    pub fn path_mut(&mut self, path: &str) -> Option<&mut dyn Reflect> {
+        path.split('.').try_fold(self, |value, name| value.field_mut(name))
+    }
+
+    pub fn get<T: Reflect>(&self, path: &str) -> Result<&T, ReflectError> {
+        let field = self.path(path).ok_or_else(|| ReflectError::UnknownField(path.to_string()))?;
+        field.downcast_ref().ok_or_else(|| ReflectError::WrongType(path.to_string(), field.type_name()))
+    }
+
+    pub fn set<T: Reflect>(&mut self, path: &str, value: T) -> Result<(), ReflectError> {
+        let field = self.path_mut(path).ok_or_else(|| ReflectError::UnknownField(path.to_string()))?;
+        let type_name = field.type_name();
+        *field.downcast_mut().ok_or_else(|| ReflectError::WrongType(path.to_string(), type_name))? = value;
+        Ok(())
+    }
+}
+
+impl Debug for dyn Reflect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.reflect_fmt(f)
+    }
+}
+
+// Values without fields, printed with their Debug.
+macro_rules! impl_reflect_value {
+    ($($value:ty),*) => {
+        $(
+            impl Reflect for $value {
+                fn as_any(&self) -> &dyn Any {
+                    self
+                }
+
+                fn as_any_mut(&mut self) -> &mut dyn Any {
+                    self
+                }
+
+                fn reflect_fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                    Debug::fmt(self, f)
+                }
+            }
+        )*
+    };
+}
+
+impl_reflect_value!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64, bool, char, String, &'static str);
+
+// the elements of a list or an array are its fields 0, 1, ...
+impl<T: Reflect> Reflect for Vec<T> {
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        let value = self.get(name.parse::<usize>().ok()?)?;
+        Some(value)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        let value = self.get_mut(name.parse::<usize>().ok()?)?;
+        Some(value)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn reflect_fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter().map(|value| value as &dyn Reflect)).finish()
+    }
+}
+
+impl<T: Reflect, const N: usize> Reflect for [T; N] {
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        let value = self.get(name.parse::<usize>().ok()?)?;
+        Some(value)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        let value = self.get_mut(name.parse::<usize>().ok()?)?;
+        Some(value)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn reflect_fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter().map(|value| value as &dyn Reflect)).finish()
+    }
+}
+
+impl<T: Reflect> Reflect for Option<T> {
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        match self {
+            Some(value) if name == "0" => Some(value),
+            _ => None,
+        }
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        match self {
+            Some(value) if name == "0" => Some(value),
+            _ => None,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn reflect_fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Some(value) => f.debug_tuple("Some").field(&(value as &dyn Reflect)).finish(),
+            None => f.write_str("None"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cow_macros::Reflect;
+    use crate::reflect::reflect::{FieldInfo, Reflect, ReflectError};
+
+    #[derive(Reflect, Debug, PartialEq)]
+    struct Vec2 {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Reflect, Debug, PartialEq)]
+    struct Transform {
+        position: Vec2,
+        layers: Vec<u8>,
+    }
+
+    #[derive(Reflect, Debug, PartialEq)]
+    struct Name(String, Option<u32>);
+
+    #[test]
+    fn fields_are_listed_in_order() {
+        assert_eq!(Transform::field_infos(), &[FieldInfo::new("position", "Vec2"), FieldInfo::new("layers", "Vec<u8>")]);
+        assert_eq!(Name::field_infos(), &[FieldInfo::new("0", "String"), FieldInfo::new("1", "Option<u32>")]);
+
+        let transform = Transform { position: Vec2 { x: 1.0, y: 2.0 }, layers: vec![3] };
+        let value: &dyn Reflect = &transform;
+        assert_eq!(value.fields().iter().map(FieldInfo::name).collect::<Vec<_>>(), ["position", "layers"]);
+        assert_eq!(format!("{value:?}"), "Transform { position: Vec2 { x: 1.0, y: 2.0 }, layers: [3] }");
+    }
+
+    #[test]
+    fn paths_get_and_set_nested_fields() {
+        let mut transform = Transform { position: Vec2 { x: 1.0, y: 2.0 }, layers: vec![3, 4] };
+        let value: &mut dyn Reflect = &mut transform;
+        assert_eq!(value.get::<f32>("position.y"), Ok(&2.0));
+        assert_eq!(value.get::<u8>("layers.1"), Ok(&4));
+
+        value.set("position.x", 5.0f32).unwrap();
+        value.set("layers.0", 7u8).unwrap();
+        value.set("position", Vec2 { x: 8.0, y: 9.0 }).unwrap();
+        assert_eq!(transform, Transform { position: Vec2 { x: 8.0, y: 9.0 }, layers: vec![7, 4] });
+
+        let mut name = Name("a".to_string(), Some(1));
+        let value: &mut dyn Reflect = &mut name;
+        value.set("1.0", 2u32).unwrap();
+        assert_eq!(value.get::<String>("0").map(String::as_str), Ok("a"));
+        assert_eq!(name.1, Some(2));
+    }
+
+    #[test]
+    fn wrong_paths_and_types_are_errors() {
+        let mut transform = Transform { position: Vec2 { x: 1.0, y: 2.0 }, layers: vec![] };
+        let value: &mut dyn Reflect = &mut transform;
+        assert_eq!(value.get::<f32>("position.z"), Err(ReflectError::UnknownField("position.z".to_string())));
+        assert_eq!(value.get::<u8>("layers.0"), Err(ReflectError::UnknownField("layers.0".to_string())));
+        assert_eq!(value.get::<f64>("position.x"), Err(ReflectError::WrongType("position.x".to_string(), "f32")));
+        assert_eq!(value.set("position.y", 1u32), Err(ReflectError::WrongType("position.y".to_string(), "f32")));
+        assert_eq!(value.get::<f32>("position.y"), Ok(&2.0));
+    }
+}
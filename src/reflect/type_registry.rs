@@ -0,0 +1,153 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::entity::entity::EntityId;
+use crate::reflect::reflect::{FieldInfo, Reflect};
+
+pub struct ReflectType {
+    type_id: TypeId,
+    name: &'static str,
+    fields: &'static [FieldInfo],
+    // turns a pointer to the value into a reflected reference
+    from_ptr: unsafe fn(*mut u8) -> *mut dyn Reflect,
+}
+
+impl ReflectType {
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn fields(&self) -> &'static [FieldInfo] {
+        self.fields
+    }
+
+    /// The value pointed by ptr as a reflected reference.
+    ///
+    /// # Safety
+    /// ptr must point to a valid value of the type that outlives 'a.
+    pub unsafe fn from_ptr<'a>(&self, ptr: *mut u8) -> &'a dyn Reflect {
+        &*(self.from_ptr)(ptr)
+    }
+
+    /// The value pointed by ptr as a mutable reflected reference.
+    ///
+    /// # Safety
+    /// Same as from_ptr, and nothing else may borrow the value while 'a lasts.
+    pub unsafe fn from_ptr_mut<'a>(&self, ptr: *mut u8) -> &'a mut dyn Reflect {
+        &mut *(self.from_ptr)(ptr)
+    }
+}
+
+// The types that can be reflected without knowing them at compile time, keyed by TypeId.
+pub struct TypeRegistry {
+    types: HashMap<TypeId, ReflectType>,
+}
+
+impl Default for TypeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self { types: HashMap::new() }
+    }
+
+    pub fn register<T: Reflect>(&mut self) {
+        unsafe fn from_ptr<T: Reflect>(ptr: *mut u8) -> *mut dyn Reflect {
+            ptr.cast::<T>() as *mut dyn Reflect
+        }
+
+        self.types.insert(TypeId::of::<T>(), ReflectType {
+            type_id: TypeId::of::<T>(),
+            name: std::any::type_name::<T>(),
+            fields: T::field_infos(),
+            from_ptr: from_ptr::<T>,
+        });
+    }
+
+    pub fn get(&self, type_id: TypeId) -> Option<&ReflectType> {
+        self.types.get(&type_id)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&ReflectType> {
+        self.types.values().find(|reflect_type| reflect_type.name == name)
+    }
+
+    pub fn types(&self) -> impl Iterator<Item=&ReflectType> {
+        self.types.values()
+    }
+
+    pub fn reflect<'a>(&self, archetypes: &'a ArchetypeManager, entity_id: EntityId, type_id: TypeId) -> Option<&'a dyn Reflect> {
+        let reflect_type = self.get(type_id)?;
+        let component_id = archetypes.components().id_from_type(type_id)?;
+        let ptr = archetypes.get_ptr(entity_id, component_id)?;
+        Some(unsafe { reflect_type.from_ptr(ptr) })
+    }
+
+    pub fn reflect_mut<'a>(&self, archetypes: &'a mut ArchetypeManager, entity_id: EntityId, type_id: TypeId) -> Option<&'a mut dyn Reflect> {
+        let reflect_type = self.get(type_id)?;
+        let component_id = archetypes.components().id_from_type(type_id)?;
+        let ptr = archetypes.get_ptr_mut(entity_id, component_id)?;
+        Some(unsafe { reflect_type.from_ptr_mut(ptr) })
+    }
+
+    // Every registered component of the entity, the others are skipped.
+    pub fn components<'a>(&'a self, archetypes: &'a ArchetypeManager, entity_id: EntityId) -> impl Iterator<Item=(TypeId, &'a dyn Reflect)> + 'a {
+        archetypes.components_of(entity_id).filter_map(move |component_id| {
+            let type_id = archetypes.components().info(component_id).type_id()?;
+            Some((type_id, self.reflect(archetypes, entity_id, type_id)?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+    use crate::cow_macros::{Component, Reflect};
+    use crate::reflect::reflect::FieldInfo;
+    use crate::reflect::type_registry::TypeRegistry;
+    use crate::world::World;
+
+    #[derive(Component, Reflect, Debug, PartialEq)]
+    struct Health {
+        current: u32,
+        max: u32,
+    }
+
+    #[derive(Component, Reflect, Debug, PartialEq)]
+    struct Speed(f32);
+
+    // not registered, skipped when walking the components
+    #[derive(Component, Debug, PartialEq)]
+    struct Hidden(u32);
+
+    #[test]
+    fn components_of_an_entity_are_walked_through_the_registry() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Health>();
+        registry.register::<Speed>();
+        let mut world = World::new();
+        let entity_id = world.create();
+        world.add(entity_id, Health { current: 5, max: 10 });
+        world.add(entity_id, Speed(2.0));
+        world.add(entity_id, Hidden(1));
+
+        let mut fields: Vec<(TypeId, Vec<&str>)> = registry.components(world.archetypes(), entity_id)
+            .map(|(type_id, value)| (type_id, value.fields().iter().map(FieldInfo::name).collect()))
+            .collect();
+        fields.sort_by_key(|(_, fields)| fields.len());
+        assert_eq!(fields, [(TypeId::of::<Speed>(), vec!["0"]), (TypeId::of::<Health>(), vec!["current", "max"])]);
+
+        let health = registry.reflect_mut(world.managers().0, entity_id, TypeId::of::<Health>()).unwrap();
+        health.set("current", 9u32).unwrap();
+        assert_eq!(world.query::<Health>(entity_id), Some(&Health { current: 9, max: 10 }));
+        assert!(registry.reflect(world.archetypes(), entity_id, TypeId::of::<Hidden>()).is_none());
+        assert_eq!(registry.get_by_name(std::any::type_name::<Speed>()).map(|speed| speed.type_id()), Some(TypeId::of::<Speed>()));
+    }
+}
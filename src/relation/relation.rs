@@ -0,0 +1,335 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use crate::archetype::archetype_filter::QueryFilter;
+use crate::component::clone::{clone_value, CloneFn};
+use crate::component::component::{Component, ComponentAny};
+use crate::comps::Comps;
+use crate::entity::entity::EntityId;
+use crate::world::World;
+
+// What happens to the sources of a relation when its target is released.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RelationCleanup {
+    // the sources lose the relation
+    RemoveRelation,
+    // the sources are released with the target
+    DespawnSource,
+}
+
+// A typed link from a source entity to a target entity, e.g. MemberOf(guild)
+pub trait Relation: Component + 'static {
+    // an exclusive relation has at most one target per source
+    const EXCLUSIVE: bool = false;
+    const CLEANUP: RelationCleanup = RelationCleanup::RemoveRelation;
+
+    fn target(&self) -> EntityId;
+}
+
+// The relations of kind R going from an entity, at most one per target.
+pub struct Related<R: Relation>(Vec<R>);
+
+impl<R: Relation> Related<R> {
+    pub(crate) fn new(relation: R) -> Self {
+        Self(vec![relation])
+    }
+
+    // Replaces the relation with the same target if there is one.
+    pub(crate) fn insert(&mut self, relation: R) {
+        match self.0.iter().position(|current| current.target() == relation.target()) {
+            Some(index) => self.0[index] = relation,
+            None => self.0.push(relation),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, target: EntityId) -> bool {
+        let len = self.0.len();
+        self.0.retain(|relation| relation.target() != target);
+        len != self.0.len()
+    }
+
+    pub fn get(&self, target: EntityId) -> Option<&R> {
+        self.0.iter().find(|relation| relation.target() == target)
+    }
+
+    pub fn targets(&self) -> impl Iterator<Item=EntityId> + '_ {
+        self.0.iter().map(R::target)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, R> {
+        self.0.iter()
+    }
+
+    pub fn as_slice(&self) -> &[R] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<R: Relation> ComponentAny for Related<R> {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl<R: Relation> Component for Related<R> {
+    // cloneable when the relation is
+    fn clone_fn() -> Option<CloneFn> {
+        R::clone_fn().map(|_| clone_related::<R> as CloneFn)
+    }
+}
+
+unsafe fn clone_related<R: Relation>(src: *const u8, dest: *mut u8) {
+    let clone = R::clone_fn().unwrap();
+    let related = &*src.cast::<Related<R>>();
+    let mut relations = Vec::with_capacity(related.len());
+    for relation in related.iter() {
+        let mut copy = MaybeUninit::<R>::uninit();
+        clone(relation as *const R as *const u8, copy.as_mut_ptr().cast::<u8>());
+        relations.push(copy.assume_init());
+    }
+    dest.cast::<Related<R>>().write(Related(relations));
+}
+
+// The entities having a relation of kind R to an entity.
+pub struct RelatedBy<R: Relation> {
+    sources: Vec<EntityId>,
+    _marker: PhantomData<R>,
+}
+
+impl<R: Relation> RelatedBy<R> {
+    pub(crate) fn new(source: EntityId) -> Self {
+        Self { sources: vec![source], _marker: PhantomData }
+    }
+
+    pub(crate) fn push(&mut self, source: EntityId) {
+        if !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, source: EntityId) {
+        self.sources.retain(|entity_id| *entity_id != source);
+    }
+
+    pub fn sources(&self) -> &[EntityId] {
+        &self.sources
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
+impl<R: Relation> ComponentAny for RelatedBy<R> {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl<R: Relation> Clone for RelatedBy<R> {
+    fn clone(&self) -> Self {
+        Self { sources: self.sources.clone(), _marker: PhantomData }
+    }
+}
+
+impl<R: Relation> Component for RelatedBy<R> {
+    fn clone_fn() -> Option<CloneFn> {
+        Some(clone_value::<Self>)
+    }
+}
+
+// Relation lookups from tasks, e.g. members.sources(guild)
+impl<R: Relation, F: QueryFilter> Comps<'_, RelatedBy<R>, F> {
+    pub fn sources(&self, target: EntityId) -> &[EntityId] {
+        self.query(target).map(RelatedBy::sources).unwrap_or_default()
+    }
+}
+
+impl<R: Relation, F: QueryFilter> Comps<'_, Related<R>, F> {
+    pub fn targets(&self, source: EntityId) -> impl Iterator<Item=EntityId> + '_ {
+        self.query(source).into_iter().flat_map(Related::targets)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<R: Relation + serde::Serialize> serde::Serialize for Related<R> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de, R: Relation + serde::Deserialize<'de>> serde::Deserialize<'de> for Related<R> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<R>::deserialize(deserializer).map(Related)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<R: Relation> serde::Serialize for RelatedBy<R> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.sources.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de, R: Relation> serde::Deserialize<'de> for RelatedBy<R> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<EntityId>::deserialize(deserializer).map(|sources| RelatedBy { sources, _marker: PhantomData })
+    }
+}
+
+pub(crate) type ReleaseRelations = fn(&mut World, EntityId);
+
+// Drops the relations of kind R going from or to an entity about to be released.
+pub(crate) fn release_relations<R: Relation>(world: &mut World, entity_id: EntityId) {
+    let targets: Vec<EntityId> = world.relations::<R>(entity_id).iter().map(R::target).collect();
+    for target in targets {
+        world.unrelate::<R>(entity_id, target);
+    }
+
+    for source in world.sources::<R>(entity_id).to_vec() {
+        match R::CLEANUP {
+            RelationCleanup::RemoveRelation => world.unrelate::<R>(source, entity_id),
+            RelationCleanup::DespawnSource => {
+                // unlinked first so that cycles of relations end
+                world.unrelate::<R>(source, entity_id);
+                world.release(source);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cow_macros::Component;
+    use crate::entity::entity::EntityId;
+    use crate::relation::relation::{Related, RelatedBy, Relation, RelationCleanup};
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct MemberOf(EntityId);
+
+    impl Relation for MemberOf {
+        const EXCLUSIVE: bool = true;
+
+        fn target(&self) -> EntityId {
+            self.0
+        }
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Likes(EntityId, u32);
+
+    impl Relation for Likes {
+        fn target(&self) -> EntityId {
+            self.0
+        }
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    struct OwnedBy(EntityId);
+
+    impl Relation for OwnedBy {
+        const CLEANUP: RelationCleanup = RelationCleanup::DespawnSource;
+
+        fn target(&self) -> EntityId {
+            self.0
+        }
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Guild(&'static str);
+
+    #[test]
+    fn exclusive_relation_replaces_its_target() {
+        let mut world = World::new();
+        let (player, red, blue) = (world.create(), world.create(), world.create());
+        world.relate(player, MemberOf(red));
+        world.relate(player, MemberOf(blue));
+
+        assert_eq!(world.relations::<MemberOf>(player), &[MemberOf(blue)]);
+        assert_eq!(world.sources::<MemberOf>(blue), &[player]);
+        assert!(world.query::<RelatedBy<MemberOf>>(red).is_none());
+
+        // a relation that isn't exclusive keeps one per target, the last value wins
+        world.relate(player, Likes(red, 1));
+        world.relate(player, Likes(blue, 2));
+        world.relate(player, Likes(red, 3));
+        assert_eq!(world.relations::<Likes>(player), &[Likes(red, 3), Likes(blue, 2)]);
+    }
+
+    #[test]
+    fn released_target_removes_the_relation() {
+        let mut world = World::new();
+        let (a, b, guild) = (world.create(), world.create(), world.create());
+        world.relate(a, MemberOf(guild));
+        world.relate(b, MemberOf(guild));
+        world.relate(a, Likes(b, 1));
+
+        world.release(guild);
+        assert!(world.is_alive(a) && world.is_alive(b));
+        assert!(world.query::<Related<MemberOf>>(a).is_none());
+        assert!(world.relations::<MemberOf>(b).is_empty());
+
+        // releasing the source cleans the target side too
+        world.release(a);
+        assert!(world.query::<RelatedBy<Likes>>(b).is_none());
+    }
+
+    #[test]
+    fn released_target_despawns_the_sources_recursively() {
+        let mut world = World::new();
+        let (player, bag, item, other) = (world.create(), world.create(), world.create(), world.create());
+        world.relate(bag, OwnedBy(player));
+        world.relate(item, OwnedBy(bag));
+        world.relate(other, Likes(item, 1));
+        // a cycle of owners still ends
+        let (first, second) = (world.create(), world.create());
+        world.relate(first, OwnedBy(second));
+        world.relate(second, OwnedBy(first));
+
+        world.release(player);
+        assert!(!world.is_alive(bag) && !world.is_alive(item));
+        assert!(world.is_alive(other));
+        assert!(world.relations::<Likes>(other).is_empty());
+
+        world.release(first);
+        assert!(!world.is_alive(second));
+    }
+
+    #[test]
+    fn queries_follow_the_relations() {
+        let mut world = World::new();
+        let (a, b, red, blue) = (world.create(), world.create(), world.create(), world.create());
+        world.add(red, Guild("red"));
+        world.add(blue, Guild("blue"));
+        world.relate(a, MemberOf(red));
+        world.relate(b, MemberOf(red));
+        world.relate(a, Likes(red, 1));
+        world.relate(a, Likes(blue, 2));
+
+        let members = world.comps::<RelatedBy<MemberOf>>();
+        assert_eq!(members.sources(red), &[a, b]);
+        assert!(members.sources(blue).is_empty());
+        let likes = world.comps::<Related<Likes>>();
+        assert_eq!(likes.targets(a).collect::<Vec<_>>(), [red, blue]);
+        assert_eq!(likes.targets(b).count(), 0);
+
+        let guilds: Vec<_> = world.query_targets::<Likes, Guild>(a).map(|(target, guild)| (target, guild.0)).collect();
+        assert_eq!(guilds, [(red, "red"), (blue, "blue")]);
+        assert_eq!(world.query_targets::<MemberOf, Guild>(b).map(|(_, guild)| guild.0).collect::<Vec<_>>(), ["red"]);
+    }
+}
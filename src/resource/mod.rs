@@ -1,3 +1,4 @@
 pub mod res_manager;
 pub mod res_lock;
-pub mod resource;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod resource;
@@ -1,23 +1,32 @@
 use std::any::Any;
-use std::sync::{Arc, RwLock};
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, LockResult, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use crate::resource::resource::Resource;
 
-pub trait ResLockAny {
+pub trait ResLockAny: Send + Sync {
     fn as_any(&self) -> &dyn Any;
 
     fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    // The resource shared with a snapshot, None if it can't be cloned.
+    fn snapshot(&self) -> Option<Box<dyn Any + Send + Sync>>;
+
+    fn fork(&self) -> Option<Box<dyn ResLockAny>>;
+
+    fn name(&self) -> &'static str;
 }
 
 pub struct ResLock<T: Resource> {
-    res: Arc<RwLock<T>>,
+    res: ResCell<T>,
 }
 
 impl<T: Resource + 'static> ResLock<T> {
     pub fn new(res: T) -> Self {
-        Self { res: Arc::new(RwLock::new(res)) }
+        Self { res: ResCell { value: RwLock::new(Arc::new(res)) } }
     }
 
-    pub fn resource(&self) -> &Arc<RwLock<T>> {
+    pub fn resource(&self) -> &ResCell<T> {
         &self.res
     }
 }
@@ -28,4 +37,93 @@ impl<T: Resource + 'static> ResLockAny for ResLock<T> {
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
-}
\ No newline at end of file
+
+    fn snapshot(&self) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new(self.res.share()?))
+    }
+
+    fn fork(&self) -> Option<Box<dyn ResLockAny>> {
+        Some(Box::new(ResLock { res: ResCell { value: RwLock::new(self.res.share()?) } }))
+    }
+
+    fn name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+}
+
+// The lock around a resource. The value is shared with the snapshots and forks like the columns,
+// a write copies it first if one of them still holds it.
+pub struct ResCell<T: Resource> {
+    value: RwLock<Arc<T>>,
+}
+
+impl<T: Resource + 'static> ResCell<T> {
+    pub fn read(&self) -> LockResult<ResReadGuard<'_, T>> {
+        map_lock(self.value.read(), |guard| ResReadGuard { guard })
+    }
+
+    pub fn write(&self) -> LockResult<ResWriteGuard<'_, T>> {
+        map_lock(self.value.write(), |mut guard| {
+            unshare(&mut guard);
+            ResWriteGuard { guard }
+        })
+    }
+
+    // only the resources that can be cloned are ever shared, see unshare
+    fn share(&self) -> Option<Arc<T>> {
+        T::clone_fn()?;
+        Some(self.value.read().unwrap().clone())
+    }
+}
+
+pub struct ResReadGuard<'a, T: Resource> {
+    guard: RwLockReadGuard<'a, Arc<T>>,
+}
+
+impl<T: Resource> Deref for ResReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+pub struct ResWriteGuard<'a, T: Resource> {
+    guard: RwLockWriteGuard<'a, Arc<T>>,
+}
+
+impl<T: Resource> Deref for ResWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: Resource> DerefMut for ResWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // unshared when the guard was taken, nothing can share it while the lock is held
+        Arc::get_mut(&mut self.guard).expect("a written resource is never shared")
+    }
+}
+
+fn map_lock<G, U>(result: LockResult<G>, map: impl FnOnce(G) -> U) -> LockResult<U> {
+    match result {
+        Ok(guard) => Ok(map(guard)),
+        Err(poisoned) => Err(PoisonError::new(map(poisoned.into_inner()))),
+    }
+}
+
+// Copies the resource if a snapshot or a fork still shares it.
+fn unshare<T: Resource + 'static>(res: &mut Arc<T>) {
+    if Arc::get_mut(res).is_some() {
+        return;
+    }
+
+    let clone = T::clone_fn().expect("only cloneable resources are shared");
+    let mut copy = MaybeUninit::<T>::uninit();
+    unsafe {
+        clone(&**res as *const T as *const u8, copy.as_mut_ptr().cast::<u8>());
+        *res = Arc::new(copy.assume_init());
+    }
+}
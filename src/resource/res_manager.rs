@@ -1,12 +1,19 @@
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use crate::resource::res_lock::{ResLock, ResLockAny};
 use crate::resource::resource::Resource;
+use crate::world::ForkError;
 
 pub struct ResManager {
     components: HashMap<TypeId, Box<dyn ResLockAny>>,
 }
 
+impl Default for ResManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ResManager {
     pub fn new() -> Self {
         Self { components: HashMap::new() }
@@ -19,13 +26,31 @@ impl ResManager {
 
     pub fn query<T: Resource + 'static>(&self) -> Option<&ResLock<T>> {
         let type_id = TypeId::of::<T>();
-        let storage = self.components.get(&type_id).unwrap();
+        let storage = self.components.get(&type_id)?;
         storage.as_any().downcast_ref::<ResLock<T>>()
     }
 
+    // Shares every resource that can be cloned, the world copies one the next time it writes it.
+    pub fn snapshot(&self) -> HashMap<TypeId, Box<dyn Any + Send + Sync>> {
+        self.components.iter()
+            .filter_map(|(type_id, res)| Some((*type_id, res.snapshot()?)))
+            .collect()
+    }
+
+    // Shares every resource with the copy, they must all be cloneable.
+    pub fn fork(&self) -> Result<Self, ForkError> {
+        let components = self.components.iter()
+            .map(|(type_id, res)| {
+                let copy = res.fork().ok_or_else(|| ForkError::Resource(res.name().to_string()))?;
+                Ok((*type_id, copy))
+            })
+            .collect::<Result<_, ForkError>>()?;
+        Ok(Self { components })
+    }
+
     pub fn query_mut<T: Resource + 'static>(&mut self) -> Option<&mut ResLock<T>> {
         let type_id = TypeId::of::<T>();
-        let storage = self.components.get_mut(&type_id).unwrap();
+        let storage = self.components.get_mut(&type_id)?;
         storage.as_any_mut().downcast_mut::<ResLock<T>>()
     }
 }
\ No newline at end of file
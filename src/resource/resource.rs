@@ -1 +1,8 @@
-pub trait Resource: Send + Sync {}
\ No newline at end of file
+use crate::component::clone::CloneFn;
+
+pub trait Resource: Send + Sync {
+    // how snapshots copy the resource, None if it can't be cloned
+    fn clone_fn() -> Option<CloneFn> where Self: Sized {
+        None
+    }
+}
\ No newline at end of file
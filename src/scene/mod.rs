@@ -0,0 +1,5 @@
+#[allow(clippy::module_inception)]
+pub mod scene;
+pub mod scene_parser;
+pub mod scene_registry;
+pub mod scene_value;
@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+use crate::component::component_box::ComponentBox;
+use crate::comps::Commands;
+use crate::entity::entity::EntityId;
+use crate::scene::scene_parser::parse_scene;
+use crate::scene::scene_registry::SceneRegistry;
+use crate::scene::scene_value::{SceneContext, SceneError, SceneValue};
+use crate::world::World;
+
+pub struct SceneEntity {
+    pub(crate) name: Option<String>,
+    pub(crate) parent: Option<String>,
+    pub(crate) line: usize,
+    pub(crate) components: Vec<SceneComponent>,
+}
+
+impl SceneEntity {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn components(&self) -> impl Iterator<Item=&SceneValue> {
+        self.components.iter().map(|component| &component.value)
+    }
+}
+
+pub struct SceneComponent {
+    pub(crate) line: usize,
+    // always a SceneValue::Struct named after the component
+    pub(crate) value: SceneValue,
+}
+
+// A prefab or a level read from its text, it can be spawned any number of times.
+// The entities refer to each other by their local name, see parse_scene for the format.
+pub struct Scene {
+    entities: Vec<SceneEntity>,
+}
+
+impl Scene {
+    pub fn parse(text: &str) -> Result<Self, SceneError> {
+        let entities = parse_scene(text)?;
+
+        let mut names = HashSet::new();
+        for entity in &entities {
+            if let Some(name) = &entity.name {
+                if !names.insert(name.as_str()) {
+                    return Err(SceneError::DuplicateEntity(name.clone()));
+                }
+            }
+        }
+        for entity in &entities {
+            if let Some(parent) = &entity.parent {
+                if !names.contains(parent.as_str()) {
+                    return Err(SceneError::UnknownEntity(parent.clone()));
+                }
+            }
+        }
+        check_parents(&entities)?;
+
+        Ok(Self { entities })
+    }
+
+    pub fn entities(&self) -> &[SceneEntity] {
+        &self.entities
+    }
+
+    // Creates the entities of the scene in the world, nothing is left in it on error.
+    // Returns the entity created for each name.
+    pub fn spawn(&self, world: &mut World, registry: &SceneRegistry) -> Result<HashMap<String, EntityId>, SceneError> {
+        let entity_ids: Vec<EntityId> = self.entities.iter().map(|_| world.create()).collect();
+        let context = self.context(&entity_ids);
+        let components = match self.build(registry, &context) {
+            Ok(components) => components,
+            Err(error) => {
+                for entity_id in entity_ids {
+                    world.release(entity_id);
+                }
+                return Err(error);
+            }
+        };
+
+        for (entity_id, components) in entity_ids.iter().zip(components) {
+            for comp in components {
+                world.add_box(*entity_id, comp);
+            }
+        }
+        let parents = self.entities.iter().zip(&entity_ids).try_for_each(|(entity, entity_id)| match &entity.parent {
+            Some(parent) => Ok(world.set_parent(*entity_id, context.entity(parent)?)?),
+            None => Ok(()),
+        });
+        if let Err(error) = parents {
+            for entity_id in entity_ids {
+                world.release(entity_id);
+            }
+            return Err(error);
+        }
+
+        Ok(context.into_entities())
+    }
+
+    // Same as spawn, through the commands of a task, each entity is created with all its components.
+    // The parents were checked by parse, setting them can't make a cycle.
+    pub fn spawn_commands(&self, commands: &mut Commands, registry: &SceneRegistry) -> Result<HashMap<String, EntityId>, SceneError> {
+        // the ids are known before the components, which can refer to the entities
+        let entity_ids: Vec<EntityId> = self.entities.iter().map(|_| commands.reserve()).collect();
+        let context = self.context(&entity_ids);
+        let components = match self.build(registry, &context) {
+            Ok(components) => components,
+            Err(error) => {
+                for entity_id in entity_ids {
+                    commands.unreserve(entity_id);
+                }
+                return Err(error);
+            }
+        };
+
+        for (entity_id, components) in entity_ids.iter().zip(components) {
+            let entity = commands.spawn_reserved(*entity_id);
+            for comp in components {
+                entity.add_box(comp);
+            }
+        }
+        for (entity, entity_id) in self.entities.iter().zip(&entity_ids) {
+            if let Some(parent) = &entity.parent {
+                commands.set_parent(*entity_id, context.entity(parent)?);
+            }
+        }
+
+        Ok(context.into_entities())
+    }
+
+    fn context(&self, entity_ids: &[EntityId]) -> SceneContext {
+        let names = self.entities.iter()
+            .zip(entity_ids)
+            .filter_map(|(entity, entity_id)| Some((entity.name.clone()?, *entity_id)))
+            .collect();
+        SceneContext::new(names)
+    }
+
+    // Every component of every entity, in the order of the file.
+    fn build(&self, registry: &SceneRegistry, context: &SceneContext) -> Result<Vec<Vec<ComponentBox>>, SceneError> {
+        self.entities.iter().map(|entity| {
+            entity.components.iter().map(|component| {
+                let SceneValue::Struct(name, _) = &component.value else {
+                    unreachable!("the parser only reads structs as components")
+                };
+                let build = registry.component(name)
+                    .ok_or_else(|| SceneError::UnknownComponent { line: component.line, name: name.clone() })?;
+                build(&component.value, context).map_err(|error| match error {
+                    SceneError::Value(message) => SceneError::Component { line: component.line, name: name.clone(), message },
+                    error => error,
+                })
+            }).collect()
+        }).collect()
+    }
+}
+
+// Only named entities can be parents, a cycle goes through named entities only.
+fn check_parents(entities: &[SceneEntity]) -> Result<(), SceneError> {
+    let parents: HashMap<&str, &str> = entities.iter()
+        .filter_map(|entity| Some((entity.name.as_deref()?, entity.parent.as_deref()?)))
+        .collect();
+
+    // in the order of the file, the error names the first entity of the cycle
+    for name in entities.iter().filter_map(|entity| entity.name.as_deref()) {
+        let mut ancestor = parents.get(name);
+        // a longer chain would have to visit an entity twice
+        for _ in 0..parents.len() {
+            match ancestor {
+                Some(parent) if *parent == name => return Err(SceneError::ParentCycle(name.to_string())),
+                Some(parent) => ancestor = parents.get(parent),
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::EntityCommands;
+    use crate::comps::Commands;
+    use crate::cow_macros::{Component, FromScene};
+    use crate::entity::entity::EntityId;
+    use crate::scene::scene::Scene;
+    use crate::scene::scene_registry::SceneRegistry;
+    use crate::scene::scene_value::SceneError;
+    use crate::world::World;
+
+    #[derive(Component, FromScene, Debug, Clone, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Component, FromScene, Debug, Clone, PartialEq)]
+    struct Target(#[scene(entity)] EntityId);
+
+    #[derive(Component, FromScene, Debug, Clone, PartialEq)]
+    struct Squad {
+        #[scene(entity)]
+        members: Vec<EntityId>,
+        #[scene(entity, default)]
+        leader: Option<EntityId>,
+    }
+
+    fn registry() -> SceneRegistry {
+        let mut registry = SceneRegistry::new();
+        registry.register::<Health>();
+        registry.register::<Target>();
+        registry.register::<Squad>();
+        registry
+    }
+
+    fn parse_error(text: &str) -> SceneError {
+        Scene::parse(text).err().expect("the scene should not parse")
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(parse_error("entity a {\n  Health(1\n}"), SceneError::Parse { line: 3, .. }));
+        assert_eq!(parse_error("entity a {}\nentity a {}"), SceneError::DuplicateEntity("a".into()));
+        assert_eq!(parse_error("entity a : b {}"), SceneError::UnknownEntity("b".into()));
+    }
+
+    #[test]
+    fn parse_rejects_parent_cycles() {
+        assert_eq!(parse_error("entity a : a {}"), SceneError::ParentCycle("a".into()));
+        assert_eq!(parse_error("entity a : b {}\nentity b : a {}"), SceneError::ParentCycle("a".into()));
+        assert_eq!(parse_error("entity root {}\nentity a : c {}\nentity b : a {}\nentity c : b {}"), SceneError::ParentCycle("a".into()));
+        assert!(Scene::parse("entity root {}\nentity a : root {}\nentity b : a {}").is_ok());
+    }
+
+    #[test]
+    fn spawn_links_the_entities() {
+        let scene = Scene::parse("entity player { Health(10) Target(@enemy) }\nentity enemy { Health(5) }\nentity sword : player {}").unwrap();
+        let mut world = World::new();
+        let names = scene.spawn(&mut world, &registry()).unwrap();
+
+        assert_eq!(world.query::<Health>(names["player"]), Some(&Health(10)));
+        assert_eq!(world.query::<Target>(names["player"]), Some(&Target(names["enemy"])));
+        assert_eq!(world.parent(names["sword"]), Some(names["player"]));
+        assert_eq!(world.entities_count(), 3);
+    }
+
+    #[test]
+    fn spawn_leaves_nothing_on_error() {
+        let mut world = World::new();
+        let existing = world.create();
+        let scene = Scene::parse("entity a { Health(1) }\nentity b : a { Mana(2) }").unwrap();
+        let error = scene.spawn(&mut world, &registry()).err().unwrap();
+
+        assert_eq!(error, SceneError::UnknownComponent { line: 2, name: "Mana".into() });
+        assert_eq!(world.entities_count(), 1);
+        assert!(world.is_alive(existing));
+    }
+
+    #[test]
+    fn only_entity_fields_take_entities() {
+        let scene = Scene::parse("entity a { Squad { members: [@a, @b] } }\nentity b { Squad { members: [], leader: @a } }").unwrap();
+        let mut world = World::new();
+        let names = scene.spawn(&mut world, &registry()).unwrap();
+        let (a, b) = (names["a"], names["b"]);
+        assert_eq!(world.query::<Squad>(a), Some(&Squad { members: vec![a, b], leader: None }));
+        assert_eq!(world.query::<Squad>(b), Some(&Squad { members: vec![], leader: Some(a) }));
+
+        // an u32 is a number, even though EntityId is one too
+        let scene = Scene::parse("entity a { Health(@a) }").unwrap();
+        assert!(matches!(scene.spawn(&mut world, &registry()), Err(SceneError::Component { line: 1, .. })));
+        let scene = Scene::parse("entity a { Target(3) }").unwrap();
+        assert!(matches!(scene.spawn(&mut world, &registry()), Err(SceneError::Component { line: 1, .. })));
+        assert_eq!(world.entities_count(), 2);
+    }
+
+    #[test]
+    fn spawn_commands_creates_each_entity_with_its_components() {
+        let scene = Scene::parse("entity player { Health(10) Target(@enemy) }\nentity enemy { Health(5) }\nentity sword : player {}").unwrap();
+        let mut world = World::new();
+        let (_, _, entities) = world.managers();
+        let mut ec = EntityCommands::new(entities);
+        let names = scene.spawn_commands(&mut Commands::new(&mut ec), &registry()).unwrap();
+        let commands = ec.take_commands();
+
+        // one command per entity, then the parent of the sword
+        assert_eq!(commands.len(), 4);
+        world.apply_commands(commands);
+        assert_eq!(world.query::<Health>(names["player"]), Some(&Health(10)));
+        assert_eq!(world.query::<Target>(names["player"]), Some(&Target(names["enemy"])));
+        assert_eq!(world.parent(names["sword"]), Some(names["player"]));
+        assert_eq!(world.entities_count(), 3);
+    }
+
+    #[test]
+    fn spawn_commands_gives_the_ids_back_on_error() {
+        let scene = Scene::parse("entity a { Health(1) }\nentity b : a { Mana(2) }").unwrap();
+        let mut world = World::new();
+        let existing = world.create();
+        let (_, _, entities) = world.managers();
+        let mut ec = EntityCommands::new(entities);
+        assert!(scene.spawn_commands(&mut Commands::new(&mut ec), &registry()).is_err());
+        assert!(ec.take_commands().is_empty());
+        assert_eq!(world.entities_count(), 1);
+        assert!(world.is_alive(existing));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::scene::scene::{SceneComponent, SceneEntity};
+use crate::scene::scene_value::{SceneError, SceneFields, SceneValue};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    // @name
+    Entity(String),
+    Punct(char),
+}
+
+// Reads the entities of a scene file:
+//
+// entity player {
+//     Position { x: 0.0, y: 1.5 }
+//     Health(100)
+//     Target(@enemy)
+// }
+// entity sword : player { Damage(12) }
+//
+// @enemy is read by the fields marked #[scene(entity)], see FromSceneEntity.
+//
+// # and // start a comment until the end of the line.
+pub fn parse_scene(text: &str) -> Result<Vec<SceneEntity>, SceneError> {
+    let mut parser = Parser { tokens: tokenize(text)?, position: 0 };
+    let mut entities = vec![];
+    while parser.peek().is_some() {
+        entities.push(parser.entity()?);
+    }
+    Ok(entities)
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, SceneError> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => skip_line(&mut chars),
+            '/' => {
+                chars.next();
+                if chars.next() != Some('/') {
+                    return Err(SceneError::Parse { line, message: "expected // to start a comment".to_string() });
+                }
+                skip_line(&mut chars);
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some(c @ ('"' | '\\')) => string.push(c),
+                            _ => return Err(SceneError::Parse { line, message: "unknown escape in string".to_string() }),
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            string.push(c)
+                        }
+                        None => return Err(SceneError::Parse { line, message: "unterminated string".to_string() }),
+                    }
+                }
+                tokens.push((Token::Str(string), line));
+            }
+            '@' => {
+                chars.next();
+                let name = take_ident(&mut chars);
+                if name.is_empty() {
+                    return Err(SceneError::Parse { line, message: "expected an entity name after @".to_string() });
+                }
+                tokens.push((Token::Entity(name), line));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                number.push(c);
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    // 1e-3 keeps its sign
+                    let exponent_sign = (c == '-' || c == '+') && number.ends_with(['e', 'E']);
+                    if c.is_ascii_alphanumeric() || c == '.' || c == '_' || exponent_sign {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((parse_number(&number, line)?, line));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                tokens.push((Token::Ident(take_ident(&mut chars)), line));
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | ',' => {
+                chars.next();
+                tokens.push((Token::Punct(c), line));
+            }
+            c => return Err(SceneError::Parse { line, message: format!("unexpected character {c:?}") }),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn skip_line(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.next_if(|&c| c != '\n').is_some() {}
+}
+
+// Names can have a path, like game::Position.
+fn take_ident(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut ident = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_alphanumeric() || c == '_' {
+            ident.push(c);
+            chars.next();
+        } else if c == ':' && chars.clone().nth(1) == Some(':') {
+            ident.push_str("::");
+            chars.nth(1);
+        } else {
+            break;
+        }
+    }
+    ident
+}
+
+fn parse_number(number: &str, line: usize) -> Result<Token, SceneError> {
+    let digits = number.replace('_', "");
+    if let Ok(int) = digits.parse::<i64>() {
+        return Ok(Token::Int(int));
+    }
+    digits.parse::<f64>()
+        .map(Token::Float)
+        .map_err(|_| SceneError::Parse { line, message: format!("invalid number {number}") })
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.position)
+            .or(self.tokens.last())
+            .map(|(_, line)| *line)
+            .unwrap_or(1)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, SceneError> {
+        Err(SceneError::Parse { line: self.line(), message: message.into() })
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), SceneError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected {punct:?}"))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, SceneError> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => {
+                let ident = ident.clone();
+                self.position += 1;
+                Ok(ident)
+            }
+            _ => self.error("expected a name"),
+        }
+    }
+
+    // entity <name>? (: <parent>)? { <component>* }
+    fn entity(&mut self) -> Result<SceneEntity, SceneError> {
+        let line = self.line();
+        if self.ident()? != "entity" {
+            return Err(SceneError::Parse { line, message: "expected entity".to_string() });
+        }
+
+        let name = match self.peek() {
+            Some(Token::Ident(_)) => Some(self.ident()?),
+            _ => None,
+        };
+        let parent = if self.eat(':') { Some(self.ident()?) } else { None };
+
+        self.expect('{')?;
+        let mut components = vec![];
+        while !self.eat('}') {
+            if self.peek().is_none() {
+                return self.error("expected '}' to close the entity");
+            }
+            let line = self.line();
+            let name = self.ident()?;
+            let value = self.struct_value(name)?;
+            components.push(SceneComponent { line, value });
+            self.eat(',');
+        }
+
+        Ok(SceneEntity { name, parent, line, components })
+    }
+
+    // The fields after the name of a struct, if any.
+    fn struct_value(&mut self, name: String) -> Result<SceneValue, SceneError> {
+        let fields = if self.eat('(') {
+            SceneFields::Tuple(self.values_until(')')?)
+        } else if self.eat('{') {
+            let mut fields = vec![];
+            while !self.eat('}') {
+                let field = self.ident()?;
+                self.expect(':')?;
+                fields.push((field, self.value()?));
+                if !self.eat(',') {
+                    self.expect('}')?;
+                    break;
+                }
+            }
+            SceneFields::Named(fields)
+        } else {
+            SceneFields::Unit
+        };
+        Ok(SceneValue::Struct(name, fields))
+    }
+
+    fn values_until(&mut self, close: char) -> Result<Vec<SceneValue>, SceneError> {
+        let mut values = vec![];
+        while !self.eat(close) {
+            values.push(self.value()?);
+            if !self.eat(',') {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    fn value(&mut self) -> Result<SceneValue, SceneError> {
+        match self.next() {
+            Some(Token::Int(int)) => Ok(SceneValue::Int(int)),
+            Some(Token::Float(float)) => Ok(SceneValue::Float(float)),
+            Some(Token::Str(string)) => Ok(SceneValue::Str(string)),
+            Some(Token::Entity(name)) => Ok(SceneValue::Entity(name)),
+            Some(Token::Punct('[')) => Ok(SceneValue::List(self.values_until(']')?)),
+            Some(Token::Ident(ident)) if ident == "true" => Ok(SceneValue::Bool(true)),
+            Some(Token::Ident(ident)) if ident == "false" => Ok(SceneValue::Bool(false)),
+            Some(Token::Ident(ident)) => self.struct_value(ident),
+            _ => {
+                self.position -= 1;
+                self.error("expected a value")
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use crate::component::component::Component;
+use crate::component::component_box::ComponentBox;
+use crate::scene::scene_value::{FromScene, SceneContext, SceneError, SceneValue};
+
+pub type SceneComponentFn = fn(&SceneValue, &SceneContext) -> Result<ComponentBox, SceneError>;
+
+// The components a scene file can use, by the name written in the file.
+pub struct SceneRegistry {
+    components: HashMap<String, SceneComponentFn>,
+}
+
+impl Default for SceneRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneRegistry {
+    pub fn new() -> Self {
+        Self { components: HashMap::new() }
+    }
+
+    // Registers the component under its type name without the module path, e.g. Position.
+    pub fn register<T: Component + FromScene + 'static>(&mut self) {
+        let name = std::any::type_name::<T>();
+        let name = name.split('<').next().unwrap_or(name);
+        let name = name.rsplit("::").next().unwrap_or(name);
+        self.register_as::<T>(name);
+    }
+
+    pub fn register_as<T: Component + FromScene + 'static>(&mut self, name: &str) {
+        self.components.insert(name.to_string(), build_component::<T>);
+    }
+
+    pub fn component(&self, name: &str) -> Option<SceneComponentFn> {
+        self.components.get(name).copied()
+    }
+}
+
+fn build_component<T: Component + FromScene + 'static>(value: &SceneValue, context: &SceneContext) -> Result<ComponentBox, SceneError> {
+    T::from_scene(value, context).map(ComponentBox::new)
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::entity::entity::EntityId;
+use crate::hierarchy::hierarchy::HierarchyError;
+
+// A value written in a scene file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    // @name, an entity of the scene
+    Entity(String),
+    List(Vec<SceneValue>),
+    // Name, Name(a, b) or Name { x: a, y: b }
+    Struct(String, SceneFields),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneFields {
+    Unit,
+    Tuple(Vec<SceneValue>),
+    Named(Vec<(String, SceneValue)>),
+}
+
+impl SceneValue {
+    // The fields of a struct value, its name is left to the registry.
+    pub fn fields(&self) -> Result<&SceneFields, SceneError> {
+        match self {
+            SceneValue::Struct(_, fields) => Ok(fields),
+            other => Err(SceneError::Value(format!("expected a struct, found {other}"))),
+        }
+    }
+}
+
+impl SceneFields {
+    pub fn unit(&self) -> Result<(), SceneError> {
+        match self {
+            SceneFields::Unit => Ok(()),
+            _ => Err(SceneError::Value("expected no field".to_string())),
+        }
+    }
+
+    pub fn tuple(&self, len: usize) -> Result<&[SceneValue], SceneError> {
+        match self {
+            SceneFields::Tuple(values) if values.len() == len => Ok(values),
+            SceneFields::Unit if len == 0 => Ok(&[]),
+            _ => Err(SceneError::Value(format!("expected {len} values in parentheses"))),
+        }
+    }
+
+    // The named fields, after checking they are all known.
+    pub fn named(&self, known: &[&str]) -> Result<&[(String, SceneValue)], SceneError> {
+        let fields: &[(String, SceneValue)] = match self {
+            SceneFields::Named(fields) => fields,
+            SceneFields::Unit => &[],
+            SceneFields::Tuple(_) => return Err(SceneError::Value("expected named fields in braces".to_string())),
+        };
+
+        if let Some((name, _)) = fields.iter().find(|(name, _)| !known.contains(&name.as_str())) {
+            return Err(SceneError::Value(format!("unknown field {name}")));
+        }
+        Ok(fields)
+    }
+}
+
+// Looks up a named field, None when it's missing.
+pub fn scene_field<'a>(fields: &'a [(String, SceneValue)], name: &str) -> Option<&'a SceneValue> {
+    fields.iter().find(|(field, _)| field == name).map(|(_, value)| value)
+}
+
+impl Display for SceneValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneValue::Int(value) => write!(f, "{value}"),
+            SceneValue::Float(value) => write!(f, "{value:?}"),
+            SceneValue::Bool(value) => write!(f, "{value}"),
+            SceneValue::Str(value) => write!(f, "{value:?}"),
+            SceneValue::Entity(name) => write!(f, "@{name}"),
+            SceneValue::List(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            SceneValue::Struct(name, SceneFields::Unit) => write!(f, "{name}"),
+            SceneValue::Struct(name, SceneFields::Tuple(values)) => {
+                write!(f, "{name}(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, ")")
+            }
+            SceneValue::Struct(name, SceneFields::Named(fields)) => {
+                write!(f, "{name} {{ ")?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{field}: {value}")?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneError {
+    Parse { line: usize, message: String },
+    UnknownComponent { line: usize, name: String },
+    UnknownEntity(String),
+    DuplicateEntity(String),
+    // the entity is its own parent or one of its ancestors
+    ParentCycle(String),
+    // a value doesn't fit the field it's given to
+    Value(String),
+    Component { line: usize, name: String, message: String },
+    Hierarchy(HierarchyError),
+}
+
+impl SceneError {
+    // Prefixes the message of a value error with the field it happened in.
+    pub fn in_field(self, field: &str) -> Self {
+        match self {
+            SceneError::Value(message) => SceneError::Value(format!("{field}: {message}")),
+            other => other,
+        }
+    }
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            SceneError::UnknownComponent { line, name } => write!(f, "line {line}: unknown component {name}"),
+            SceneError::UnknownEntity(name) => write!(f, "unknown entity {name}"),
+            SceneError::DuplicateEntity(name) => write!(f, "the entity {name} is declared twice"),
+            SceneError::ParentCycle(name) => write!(f, "the entity {name} is its own ancestor"),
+            SceneError::Value(message) => write!(f, "{message}"),
+            SceneError::Component { line, name, message } => write!(f, "line {line}: {name}: {message}"),
+            SceneError::Hierarchy(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<HierarchyError> for SceneError {
+    fn from(error: HierarchyError) -> Self {
+        SceneError::Hierarchy(error)
+    }
+}
+
+// The entities of the scene being loaded, by local name.
+pub struct SceneContext {
+    entities: HashMap<String, EntityId>,
+}
+
+impl SceneContext {
+    pub fn new(entities: HashMap<String, EntityId>) -> Self {
+        Self { entities }
+    }
+
+    pub fn entity(&self, name: &str) -> Result<EntityId, SceneError> {
+        self.entities.get(name).copied().ok_or_else(|| SceneError::UnknownEntity(name.to_string()))
+    }
+
+    pub fn into_entities(self) -> HashMap<String, EntityId> {
+        self.entities
+    }
+}
+
+// Builds a value from its scene text, derived with #[derive(FromScene)] for structs.
+pub trait FromScene: Sized {
+    fn from_scene(value: &SceneValue, context: &SceneContext) -> Result<Self, SceneError>;
+}
+
+macro_rules! impl_from_scene_int {
+    ($($int:ty),*) => {
+        $(
+            impl FromScene for $int {
+                fn from_scene(value: &SceneValue, _context: &SceneContext) -> Result<Self, SceneError> {
+                    match value {
+                        SceneValue::Int(int) => <$int>::try_from(*int)
+                            .map_err(|_| SceneError::Value(format!("{int} doesn't fit in {}", stringify!($int)))),
+                        other => Err(SceneError::Value(format!("expected an integer, found {other}"))),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_scene_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_from_scene_float {
+    ($($float:ty),*) => {
+        $(
+            impl FromScene for $float {
+                fn from_scene(value: &SceneValue, _context: &SceneContext) -> Result<Self, SceneError> {
+                    match value {
+                        SceneValue::Float(float) => Ok(*float as $float),
+                        SceneValue::Int(int) => Ok(*int as $float),
+                        other => Err(SceneError::Value(format!("expected a number, found {other}"))),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_scene_float!(f32, f64);
+
+impl FromScene for bool {
+    fn from_scene(value: &SceneValue, _context: &SceneContext) -> Result<Self, SceneError> {
+        match value {
+            SceneValue::Bool(value) => Ok(*value),
+            other => Err(SceneError::Value(format!("expected true or false, found {other}"))),
+        }
+    }
+}
+
+impl FromScene for String {
+    fn from_scene(value: &SceneValue, _context: &SceneContext) -> Result<Self, SceneError> {
+        match value {
+            SceneValue::Str(value) => Ok(value.clone()),
+            other => Err(SceneError::Value(format!("expected a string, found {other}"))),
+        }
+    }
+}
+
+impl<T: FromScene> FromScene for Vec<T> {
+    fn from_scene(value: &SceneValue, context: &SceneContext) -> Result<Self, SceneError> {
+        match value {
+            SceneValue::List(values) => values.iter()
+                .enumerate()
+                .map(|(i, value)| T::from_scene(value, context).map_err(|error| error.in_field(&format!("[{i}]"))))
+                .collect(),
+            other => Err(SceneError::Value(format!("expected a list, found {other}"))),
+        }
+    }
+}
+
+// a missing optional field is None, see #[scene(default)]
+impl<T: FromScene> FromScene for Option<T> {
+    fn from_scene(value: &SceneValue, context: &SceneContext) -> Result<Self, SceneError> {
+        T::from_scene(value, context).map(Some)
+    }
+}
+
+// Reads the fields marked #[scene(entity)], given as @name in the scene.
+// EntityId is an u32, a plain u32 field doesn't take entities.
+pub trait FromSceneEntity: Sized {
+    fn from_scene_entity(value: &SceneValue, context: &SceneContext) -> Result<Self, SceneError>;
+}
+
+impl FromSceneEntity for EntityId {
+    fn from_scene_entity(value: &SceneValue, context: &SceneContext) -> Result<Self, SceneError> {
+        match value {
+            SceneValue::Entity(name) => context.entity(name),
+            other => Err(SceneError::Value(format!("expected an entity, found {other}"))),
+        }
+    }
+}
+
+impl<T: FromSceneEntity> FromSceneEntity for Vec<T> {
+    fn from_scene_entity(value: &SceneValue, context: &SceneContext) -> Result<Self, SceneError> {
+        match value {
+            SceneValue::List(values) => values.iter()
+                .enumerate()
+                .map(|(i, value)| T::from_scene_entity(value, context).map_err(|error| error.in_field(&format!("[{i}]"))))
+                .collect(),
+            other => Err(SceneError::Value(format!("expected a list, found {other}"))),
+        }
+    }
+}
+
+impl<T: FromSceneEntity> FromSceneEntity for Option<T> {
+    fn from_scene_entity(value: &SceneValue, context: &SceneContext) -> Result<Self, SceneError> {
+        T::from_scene_entity(value, context).map(Some)
+    }
+}
@@ -1,2 +1,4 @@
 pub mod task_type;
-pub mod sorted_task;
\ No newline at end of file
+pub mod sorted_task;
+pub mod task_pool;
+pub mod task_state;
@@ -1,13 +1,18 @@
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::commands::EntityCommands;
+use crate::resource::res_manager::ResManager;
+use crate::schedule::task_state::TaskState;
 use crate::Task;
 
 pub struct SortedTask {
     task: Box<dyn Task>,
     depends: Option<usize>,
+    state: TaskState,
 }
 
 impl SortedTask {
     pub fn new(task: Box<dyn Task>) -> Self {
-        Self { task, depends: None }
+        Self { task, depends: None, state: TaskState::new() }
     }
 
     pub fn set_depends(&mut self, value: Option<usize>) {
@@ -15,13 +20,7 @@ impl SortedTask {
     }
 
     pub fn find_dependencies(&self, tasks: &[SortedTask]) -> Option<usize> {
-        for i in (0..tasks.len()).rev() {
-            if self.check_if_depends(&tasks[i]) {
-                return Some(i);
-            }
-        }
-
-        None
+        (0..tasks.len()).rev().find(|&i| self.check_if_depends(&tasks[i]))
     }
 
     pub fn check_if_depends(&self, other: &Self) -> bool {
@@ -35,14 +34,19 @@ impl SortedTask {
             }
         }
 
-        return false;
+        false
     }
 
     pub fn depends_on(&self) -> Option<usize> {
         self.depends
     }
 
-    pub fn task(&self) -> &Box<dyn Task> {
-        &self.task
+    pub fn task(&self) -> &dyn Task {
+        self.task.as_ref()
+    }
+
+    // Runs the task with the state it kept from its last run.
+    pub fn run(&mut self, archs: &mut ArchetypeManager, commands: &mut EntityCommands<'_>, res: &ResManager) {
+        self.task.run(archs, commands, res, &mut self.state);
     }
 }
\ No newline at end of file
@@ -0,0 +1,190 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1)
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Worker threads kept by a world for its parallel queries, spawned the first time they are needed
+// and stopped when the last world sharing them is dropped.
+pub struct TaskPool {
+    // the workers, the thread running the batches works too
+    workers: usize,
+    sender: OnceLock<mpsc::Sender<Job>>,
+}
+
+impl Default for TaskPool {
+    fn default() -> Self {
+        Self::new(default_threads())
+    }
+}
+
+impl TaskPool {
+    // threads counts the calling thread.
+    pub fn new(threads: usize) -> Self {
+        Self { workers: threads.max(1) - 1, sender: OnceLock::new() }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers + 1
+    }
+
+    // Runs job once for each batch on at most threads threads, each one picks the next batch when it's done.
+    // Returns once every batch ran, a panic in a batch is raised again on the calling thread.
+    pub fn run_batches(&self, batch_count: usize, threads: usize, job: impl Fn(usize) + Sync) {
+        let helpers = threads.min(self.threads()).min(batch_count).saturating_sub(1);
+        if helpers == 0 {
+            (0..batch_count).for_each(job);
+            return;
+        }
+
+        let job: &(dyn Fn(usize) + Sync) = &job;
+        // the helpers only call the job before the wait below, which the job outlives
+        let job = unsafe { std::mem::transmute::<&(dyn Fn(usize) + Sync), &'static (dyn Fn(usize) + Sync)>(job) };
+        let batches = Arc::new(Batches {
+            job,
+            count: batch_count,
+            next: AtomicUsize::new(0),
+            running: Mutex::new(Running { helpers: 0, closed: false }),
+            done: Condvar::new(),
+            panicked: AtomicBool::new(false),
+        });
+
+        let sender = self.sender();
+        for _ in 0..helpers {
+            let batches = batches.clone();
+            sender.send(Box::new(move || batches.help())).unwrap();
+        }
+
+        {
+            // waits for the helpers even if a batch panics on this thread
+            let _wait = WaitHelpers(&batches);
+            batches.work();
+        }
+        if batches.panicked.load(Ordering::Relaxed) {
+            panic!("a batch panicked on a worker thread");
+        }
+    }
+
+    fn sender(&self) -> &mpsc::Sender<Job> {
+        self.sender.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let receiver = Arc::new(Mutex::new(receiver));
+            for _ in 0..self.workers {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    // the lock is released before running the job
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        // the pool was dropped
+                        Err(_) => break,
+                    }
+                });
+            }
+            sender
+        })
+    }
+}
+
+struct Running {
+    helpers: usize,
+    // set once the calling thread is done, the helpers that didn't start yet don't touch the job
+    closed: bool,
+}
+
+struct Batches {
+    job: &'static (dyn Fn(usize) + Sync),
+    count: usize,
+    next: AtomicUsize,
+    running: Mutex<Running>,
+    done: Condvar,
+    panicked: AtomicBool,
+}
+
+impl Batches {
+    fn work(&self) {
+        loop {
+            let batch = self.next.fetch_add(1, Ordering::Relaxed);
+            if batch >= self.count {
+                break;
+            }
+            (self.job)(batch);
+        }
+    }
+
+    fn help(&self) {
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.closed {
+                return;
+            }
+            running.helpers += 1;
+        }
+
+        if catch_unwind(AssertUnwindSafe(|| self.work())).is_err() {
+            self.panicked.store(true, Ordering::Relaxed);
+        }
+
+        self.running.lock().unwrap().helpers -= 1;
+        self.done.notify_all();
+    }
+}
+
+struct WaitHelpers<'b>(&'b Batches);
+
+impl Drop for WaitHelpers<'_> {
+    fn drop(&mut self) {
+        let mut running = self.0.running.lock().unwrap();
+        running.closed = true;
+        while running.helpers > 0 {
+            running = self.0.done.wait(running).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use crate::schedule::task_pool::TaskPool;
+
+    #[test]
+    fn every_batch_runs_once_on_the_same_threads() {
+        let pool = TaskPool::new(4);
+        let threads = Mutex::new(HashSet::new());
+        for batch_count in [0, 1, 3, 50] {
+            let runs: Vec<AtomicUsize> = (0..batch_count).map(|_| AtomicUsize::new(0)).collect();
+            pool.run_batches(batch_count, 4, |batch| {
+                runs[batch].fetch_add(1, Ordering::Relaxed);
+                threads.lock().unwrap().insert(thread::current().id());
+            });
+            assert!(runs.iter().all(|runs| runs.load(Ordering::Relaxed) == 1));
+        }
+
+        // the workers are kept between the calls
+        assert!(threads.into_inner().unwrap().len() <= 4);
+    }
+
+    #[test]
+    fn a_panicking_batch_is_raised_on_the_caller() {
+        let pool = TaskPool::new(3);
+        let result = std::panic::catch_unwind(|| pool.run_batches(20, 3, |batch| {
+            if batch == 5 {
+                panic!("batch 5");
+            }
+        }));
+        assert!(result.is_err());
+
+        // the pool still works after
+        let count = AtomicUsize::new(0);
+        pool.run_batches(10, 3, |_| { count.fetch_add(1, Ordering::Relaxed); });
+        assert_eq!(count.load(Ordering::Relaxed), 10);
+    }
+}
@@ -0,0 +1,23 @@
+use std::any::Any;
+
+// What a task keeps between its runs, e.g. the archetypes its queries matched.
+// The scheduler keeps one for each of its tasks and the world one for each observer,
+// so tasks run on different worlds don't share anything.
+#[derive(Default)]
+pub struct TaskState {
+    state: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl TaskState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The state of type S, created with its default on the first run.
+    pub fn get_or_default<S: Default + Send + Sync + 'static>(&mut self) -> &mut S {
+        if !self.state.as_ref().is_some_and(|state| state.is::<S>()) {
+            self.state = Some(Box::new(S::default()));
+        }
+        self.state.as_mut().and_then(|state| state.downcast_mut::<S>()).unwrap()
+    }
+}
@@ -13,11 +13,8 @@ impl TaskType {
     pub fn is_dependant(&self, other: &TaskType) -> bool {
         match self {
             TaskType::Comp(type_id) => {
-                match other {
-                    // a component can only be read if the component is done being written
-                    TaskType::CompMut(other_id) => { return type_id == other_id; }
-                    _ => {}
-                }
+                // a component can only be read if the component is done being written
+                if let TaskType::CompMut(other_id) = other { return type_id == other_id; }
             }
             TaskType::CompMut(type_id) => {
                 match other {
@@ -28,11 +25,8 @@ impl TaskType {
                 }
             }
             TaskType::Res(type_id) => {
-                match other {
-                    // a res can only be read if the res is done being written
-                    TaskType::ResMut(other_id) => { return type_id == other_id; }
-                    _ => {}
-                }
+                // a res can only be read if the res is done being written
+                if let TaskType::ResMut(other_id) = other { return type_id == other_id; }
             }
             TaskType::ResMut(type_id) => {
                 match other {
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use crate::{Task};
-use crate::commands::{EntityCommand, EntityCommands};
+use crate::commands::EntityCommands;
 use crate::schedule::sorted_task::SortedTask;
 use crate::world::World;
 
@@ -13,6 +13,12 @@ pub struct Scheduler {
     is_sorted: bool,
 }
 
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Scheduler {
     pub fn new() -> Self {
         Self { blocks: HashMap::new(), is_sorted: false }
@@ -41,32 +47,20 @@ impl Scheduler {
     }
 
     pub fn run(&mut self, world: &mut World) {
-        if self.is_sorted == false {
+        if !self.is_sorted {
             self.sort_tasks();
         }
 
-        for (_, block) in self.blocks.iter() {
-            for task in &block.tasks {
-                let mut commands = EntityCommands::new();
-                {
-                    let (mut archs, res) = world.managers();
-                    task.task().run(&mut archs, &mut commands, res);
-                }
-
-                /*for cmd in commands.take_commands().into_iter() {
-                    match cmd {
-                        EntityCommand::NewEntity(components) => {
-                            for (comp_type_id, comp) in components {
-                                let new_entity = world.create();
-                                let (comps_manager, _, _) = world.managers();
-                                comps_manager.insert_by_comp_id(comp_type_id, new_entity, comp);
-                            }
-                        }
-                        EntityCommand::ReleaseEntity(entity_id) => {
-                            world.release(entity_id);
-                        }
-                    }
-                }*/
+        for (_, block) in self.blocks.iter_mut() {
+            for task in &mut block.tasks {
+                let commands = {
+                    let (archs, res, entities) = world.managers();
+                    let mut commands = EntityCommands::new(entities);
+                    task.run(archs, &mut commands, res);
+                    commands.take_commands()
+                };
+
+                world.apply_commands(commands);
             }
         }
     }
@@ -80,4 +74,84 @@ impl Scheduler {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::archetype::archetype_filter::Without;
+    use crate::cow_macros::{cow_task, Component, Resource};
+    use crate::entity::entity::EntityId;
+    use crate::scheduler::Scheduler;
+    use crate::world::World;
+
+    #[derive(Component, Clone)]
+    struct Value(u32);
+
+    #[derive(Component, Clone)]
+    struct Marker;
+
+    #[derive(Resource, Clone)]
+    struct Total(u32);
+
+    #[cow_task]
+    fn sum(values: Comps<Value>, mut total: ResMut<Total>) {
+        total.get_mut().0 = values.iter().map(|(_, value)| value.0).sum();
+    }
+
+    #[cow_task]
+    fn double(mut values: Query<(&mut Value,), Without<Marker>>) {
+        for (_, (value,)) in values.iter_mut() {
+            value.0 *= 2;
+        }
+    }
+
+    fn total(world: &World) -> u32 {
+        world.resources().query::<Total>().unwrap().resource().read().unwrap().0
+    }
+
+    fn spawn(world: &mut World, value: u32, marked: bool) -> EntityId {
+        let entity_id = world.create();
+        world.add(entity_id, Value(value));
+        if marked {
+            world.add(entity_id, Marker);
+        }
+        entity_id
+    }
+
+    #[test]
+    fn cached_queries_follow_compaction() {
+        let mut world = World::new();
+        world.set_res(Total(0));
+        let marked = spawn(&mut world, 1, true);
+        spawn(&mut world, 2, false);
+        let mut scheduler = Scheduler::new();
+        scheduler.add_task(double);
+        scheduler.add_task(sum);
+        scheduler.run(&mut world);
+        assert_eq!(total(&world), 5);
+
+        // the archetypes move once the empty ones are freed
+        world.release(marked);
+        assert!(world.compact() > 0);
+        spawn(&mut world, 10, true);
+        scheduler.run(&mut world);
+        assert_eq!(total(&world), 18);
+    }
+
+    #[test]
+    fn each_world_keeps_its_own_query_states() {
+        let mut world = World::new();
+        world.set_res(Total(0));
+        spawn(&mut world, 1, false);
+        let mut fork = world.fork().unwrap();
+        spawn(&mut fork, 2, true);
+        let mut scheduler = Scheduler::new();
+        scheduler.add_task(sum);
+
+        for _ in 0..2 {
+            scheduler.run(&mut world);
+            scheduler.run(&mut fork);
+            assert_eq!(total(&world), 1);
+            assert_eq!(total(&fork), 3);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use crate::entity::entity::EntityId;
+use crate::entity::entity_manager::EntityManager;
+use crate::serialize::registry::SerializeRegistry;
+use crate::world::World;
+
+const MAGIC: [u8; 4] = *b"COWW";
+// bumped whenever the layout below changes
+pub const FORMAT_VERSION: u32 = 1;
+
+// the saved components of one type, (entity, bytes) for each entity having it
+type ComponentValues = Vec<(EntityId, Vec<u8>)>;
+
+#[derive(Debug)]
+pub enum LoadError {
+    // the bytes don't start with the world header
+    NotAWorld,
+    UnsupportedVersion(u32),
+    UnknownComponent(String),
+    UnknownResource(String),
+    // the saved entities contradict each other, e.g. an entity both allocated and free
+    CorruptEntities,
+    // a component belongs to an entity the saved world doesn't have
+    UnknownEntity(EntityId),
+    Decode(bincode::Error),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotAWorld => write!(f, "the data is not a saved world"),
+            LoadError::UnsupportedVersion(version) => write!(f, "unsupported world format version {version}, expected {FORMAT_VERSION}"),
+            LoadError::UnknownComponent(name) => write!(f, "the component {name} is not registered for serialization"),
+            LoadError::UnknownResource(name) => write!(f, "the resource {name} is not registered for serialization"),
+            LoadError::CorruptEntities => write!(f, "the saved entities are corrupt"),
+            LoadError::UnknownEntity(entity_id) => write!(f, "a component belongs to the entity {entity_id} which is not allocated"),
+            LoadError::Decode(error) => write!(f, "can't decode the world: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<bincode::Error> for LoadError {
+    fn from(error: bincode::Error) -> Self {
+        LoadError::Decode(error)
+    }
+}
+
+// Layout: magic, version, (current entity, free list, allocated entities),
+// [(component name, [(entity, bytes)])], [(resource name, bytes)]
+pub fn save_world(world: &World, registry: &SerializeRegistry) -> bincode::Result<Vec<u8>> {
+    let mut out = MAGIC.to_vec();
+    bincode::serialize_into(&mut out, &FORMAT_VERSION)?;
+
+    let entities = world.entity_manager();
+    let mut allocated: Vec<EntityId> = entities.allocated().collect();
+    allocated.sort_unstable();
+    bincode::serialize_into(&mut out, &(entities.current(), entities.frees(), &allocated))?;
+
+    let mut components = vec![];
+    for serializer in registry.components() {
+        let values = serializer.save(world)?;
+        if !values.is_empty() {
+            components.push((serializer.name(), values));
+        }
+    }
+    bincode::serialize_into(&mut out, &components)?;
+
+    let mut resources = vec![];
+    for serializer in registry.resources() {
+        if let Some(value) = serializer.save(world)? {
+            resources.push((serializer.name(), value));
+        }
+    }
+    bincode::serialize_into(&mut out, &resources)?;
+
+    Ok(out)
+}
+
+pub fn load_world(bytes: &[u8], registry: &SerializeRegistry) -> Result<World, LoadError> {
+    if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+        return Err(LoadError::NotAWorld);
+    }
+
+    let mut input = &bytes[MAGIC.len()..];
+    let version: u32 = bincode::deserialize_from(&mut input)?;
+    if version != FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+
+    let mut world = World::new();
+    let (current, frees, allocated): (EntityId, Vec<EntityId>, Vec<EntityId>) = bincode::deserialize_from(&mut input)?;
+    check_entities(current, &frees, &allocated)?;
+    world.restore_entities(EntityManager::from_parts(current, frees, allocated));
+
+    let components: Vec<(String, ComponentValues)> = bincode::deserialize_from(&mut input)?;
+    for (name, values) in components {
+        let serializer = registry.component(&name).ok_or(LoadError::UnknownComponent(name))?;
+        serializer.prepare(&mut world);
+        for (entity_id, value) in values {
+            if !world.is_alive(entity_id) {
+                return Err(LoadError::UnknownEntity(entity_id));
+            }
+            serializer.load(&mut world, entity_id, &value)?;
+        }
+    }
+
+    let resources: Vec<(String, Vec<u8>)> = bincode::deserialize_from(&mut input)?;
+    for (name, value) in resources {
+        let serializer = registry.resource(&name).ok_or(LoadError::UnknownResource(name))?;
+        serializer.load(&mut world, &value)?;
+    }
+
+    Ok(world)
+}
+
+// Every id up to current is either allocated or free, never both, and 0 is never used.
+fn check_entities(current: EntityId, frees: &[EntityId], allocated: &[EntityId]) -> Result<(), LoadError> {
+    let mut seen = HashSet::new();
+    for entity_id in frees.iter().chain(allocated) {
+        if *entity_id == 0 || *entity_id > current || !seen.insert(*entity_id) {
+            return Err(LoadError::CorruptEntities);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use crate::component::component_box::ComponentBox;
+    use crate::component::hooks::HookContext;
+    use crate::cow_macros::{Component, Resource};
+    use crate::entity::entity::EntityId;
+    use crate::serialize::binary::{LoadError, FORMAT_VERSION, MAGIC};
+    use crate::serialize::registry::SerializeRegistry;
+    use crate::world::World;
+
+    #[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+    struct Armor(u32);
+
+    #[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[component(on_add = mark_hooked, requires(Armor))]
+    struct Health(u32);
+
+    #[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[component(storage = "sparse")]
+    struct Stunned(u32);
+
+    #[derive(Component)]
+    struct Hooked;
+
+    #[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Round(u32);
+
+    fn mark_hooked(context: &mut HookContext<'_>) {
+        let entity_id = context.entity();
+        context.commands().add_box(entity_id, ComponentBox::new(Hooked));
+    }
+
+    fn registry() -> SerializeRegistry {
+        let mut registry = SerializeRegistry::new();
+        registry.register::<Armor>().register::<Health>().register::<Stunned>().register_resource::<Round>();
+        registry
+    }
+
+    // a world file holding a single Health on entity_id, see save_world for the layout
+    fn health_file(current: EntityId, frees: Vec<EntityId>, allocated: Vec<EntityId>, entity_id: EntityId) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, &FORMAT_VERSION).unwrap();
+        bincode::serialize_into(&mut bytes, &(current, frees, allocated)).unwrap();
+        let values = vec![(entity_id, bincode::serialize(&Health(1)).unwrap())];
+        bincode::serialize_into(&mut bytes, &vec![(std::any::type_name::<Health>(), values)]).unwrap();
+        bincode::serialize_into(&mut bytes, &Vec::<(String, Vec<u8>)>::new()).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn load_restores_the_saved_world() {
+        let registry = registry();
+        let mut world = World::new();
+        world.set_res(Round(3));
+        let (a, b, c) = (world.create(), world.create(), world.create());
+        world.add(a, Health(10));
+        world.remove::<Armor>(a);
+        world.remove::<Hooked>(a);
+        world.add(b, Stunned(2));
+        world.set_parent(b, a).unwrap();
+        world.release(c);
+
+        let loaded = World::load(&world.save(&registry).unwrap(), &registry).unwrap();
+        assert_eq!(loaded.query::<Health>(a), Some(&Health(10)));
+        assert_eq!(loaded.query::<Stunned>(b), Some(&Stunned(2)));
+        assert_eq!(loaded.parent(b), Some(a));
+        assert_eq!(loaded.children(a), [b]);
+        assert!(!loaded.is_alive(c));
+        assert_eq!(*loaded.resources().query::<Round>().unwrap().resource().read().unwrap(), Round(3));
+
+        // the values come back as they were, without running the hooks or adding the required components
+        assert_eq!(loaded.query::<Armor>(a), None);
+        assert!(loaded.query::<Hooked>(a).is_none());
+    }
+
+    #[test]
+    fn load_rejects_corrupt_entities() {
+        let registry = registry();
+        assert!(matches!(World::load(b"nope", &registry), Err(LoadError::NotAWorld)));
+        assert!(World::load(&health_file(2, vec![], vec![1, 2], 1), &registry).is_ok());
+        assert!(matches!(World::load(&health_file(2, vec![], vec![1, 2], 7), &registry), Err(LoadError::UnknownEntity(7))));
+        assert!(matches!(World::load(&health_file(2, vec![2], vec![1, 2], 1), &registry), Err(LoadError::CorruptEntities)));
+        assert!(matches!(World::load(&health_file(2, vec![], vec![0, 1], 1), &registry), Err(LoadError::CorruptEntities)));
+        assert!(matches!(World::load(&health_file(1, vec![], vec![1, 2], 1), &registry), Err(LoadError::CorruptEntities)));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod binary;
+pub mod registry;
+pub mod replication;
@@ -0,0 +1,159 @@
+use std::any::TypeId;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::component::component::Component;
+use crate::entity::entity::EntityId;
+use crate::hierarchy::hierarchy::{Children, Parent};
+use crate::relation::relation::{Related, RelatedBy, Relation};
+use crate::resource::resource::Resource;
+use crate::world::World;
+
+pub type SaveComponentFn = fn(&World) -> bincode::Result<Vec<(EntityId, Vec<u8>)>>;
+pub type LoadComponentFn = fn(&mut World, EntityId, &[u8]) -> bincode::Result<()>;
+pub type SaveResourceFn = fn(&World) -> bincode::Result<Option<Vec<u8>>>;
+pub type LoadResourceFn = fn(&mut World, &[u8]) -> bincode::Result<()>;
+
+pub struct ComponentSerializer {
+    type_id: TypeId,
+    name: String,
+    save: SaveComponentFn,
+    load: LoadComponentFn,
+    // called once before the components are loaded
+    prepare: Option<fn(&mut World)>,
+}
+
+impl ComponentSerializer {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn save(&self, world: &World) -> bincode::Result<Vec<(EntityId, Vec<u8>)>> {
+        (self.save)(world)
+    }
+
+    pub fn load(&self, world: &mut World, entity_id: EntityId, bytes: &[u8]) -> bincode::Result<()> {
+        (self.load)(world, entity_id, bytes)
+    }
+
+    pub fn prepare(&self, world: &mut World) {
+        if let Some(prepare) = self.prepare {
+            prepare(world);
+        }
+    }
+}
+
+pub struct ResourceSerializer {
+    name: String,
+    save: SaveResourceFn,
+    load: LoadResourceFn,
+}
+
+impl ResourceSerializer {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn save(&self, world: &World) -> bincode::Result<Option<Vec<u8>>> {
+        (self.save)(world)
+    }
+
+    pub fn load(&self, world: &mut World, bytes: &[u8]) -> bincode::Result<()> {
+        (self.load)(world, bytes)
+    }
+}
+
+// The components and resources written by World::save, keyed by name in the saved data.
+// Anything not registered is skipped.
+pub struct SerializeRegistry {
+    components: Vec<ComponentSerializer>,
+    resources: Vec<ResourceSerializer>,
+}
+
+impl Default for SerializeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerializeRegistry {
+    // The hierarchy components are registered from the start.
+    pub fn new() -> Self {
+        let mut registry = Self { components: vec![], resources: vec![] };
+        registry.register_as::<Parent>("cow_ecs::Parent");
+        registry.register_as::<Children>("cow_ecs::Children");
+        registry
+    }
+
+    pub fn register<T: Component + Serialize + DeserializeOwned + 'static>(&mut self) -> &mut Self {
+        self.register_as::<T>(std::any::type_name::<T>())
+    }
+
+    // The name must stay the same between the save and the load, unlike type names it can't change with the code.
+    pub fn register_as<T: Component + Serialize + DeserializeOwned + 'static>(&mut self, name: impl Into<String>) -> &mut Self {
+        self.add_component::<T>(name.into(), None)
+    }
+
+    // Registers both sides of the relation, the cleanup policy of R is restored with them.
+    pub fn register_relation<R: Relation + Serialize + DeserializeOwned>(&mut self, name: impl Into<String>) -> &mut Self {
+        let name = name.into();
+        self.add_component::<Related<R>>(format!("{name}::Related"), Some(World::track_relation::<R>));
+        self.add_component::<RelatedBy<R>>(format!("{name}::RelatedBy"), Some(World::track_relation::<R>))
+    }
+
+    pub fn register_resource<T: Resource + Serialize + DeserializeOwned + 'static>(&mut self) -> &mut Self {
+        self.register_resource_as::<T>(std::any::type_name::<T>())
+    }
+
+    pub fn register_resource_as<T: Resource + Serialize + DeserializeOwned + 'static>(&mut self, name: impl Into<String>) -> &mut Self {
+        fn save<T: Resource + Serialize + 'static>(world: &World) -> bincode::Result<Option<Vec<u8>>> {
+            match world.resources().query::<T>() {
+                Some(res) => bincode::serialize(&*res.resource().read().unwrap()).map(Some),
+                None => Ok(None),
+            }
+        }
+
+        fn load<T: Resource + DeserializeOwned + 'static>(world: &mut World, bytes: &[u8]) -> bincode::Result<()> {
+            world.set_res(bincode::deserialize::<T>(bytes)?);
+            Ok(())
+        }
+
+        self.resources.push(ResourceSerializer { name: name.into(), save: save::<T>, load: load::<T> });
+        self
+    }
+
+    pub fn components(&self) -> &Vec<ComponentSerializer> {
+        &self.components
+    }
+
+    pub fn resources(&self) -> &Vec<ResourceSerializer> {
+        &self.resources
+    }
+
+    pub fn component(&self, name: &str) -> Option<&ComponentSerializer> {
+        self.components.iter().find(|serializer| serializer.name == name)
+    }
+
+    pub fn resource(&self, name: &str) -> Option<&ResourceSerializer> {
+        self.resources.iter().find(|serializer| serializer.name == name)
+    }
+
+    fn add_component<T: Component + Serialize + DeserializeOwned + 'static>(&mut self, name: String, prepare: Option<fn(&mut World)>) -> &mut Self {
+        fn save<T: Component + Serialize + 'static>(world: &World) -> bincode::Result<Vec<(EntityId, Vec<u8>)>> {
+            world.archetypes().fetch_info::<T>().iter()
+                .map(|(entity_id, comp)| Ok((entity_id, bincode::serialize(comp)?)))
+                .collect()
+        }
+
+        // the value is put back as it was saved, its hooks and required components already ran before the save
+        fn load<T: Component + DeserializeOwned + 'static>(world: &mut World, entity_id: EntityId, bytes: &[u8]) -> bincode::Result<()> {
+            let (archetypes, _, _) = world.managers();
+            archetypes.add(entity_id, bincode::deserialize::<T>(bytes)?);
+            Ok(())
+        }
+
+        // registering a type twice replaces the previous name
+        self.components.retain(|serializer| serializer.type_id != TypeId::of::<T>());
+        self.components.push(ComponentSerializer { type_id: TypeId::of::<T>(), name, save: save::<T>, load: load::<T>, prepare });
+        self
+    }
+}
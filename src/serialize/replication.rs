@@ -0,0 +1,554 @@
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::component::component::{Component, StorageType};
+use crate::component::tick::{ComponentTicks, Tick};
+use crate::entity::entity::EntityId;
+use crate::hierarchy::hierarchy::{HierarchyError, Parent};
+use crate::interest::interest::ObserverView;
+use crate::world::World;
+
+pub type EncodeComponentFn = fn(&World, EntityId) -> Option<bincode::Result<Vec<u8>>>;
+pub type ApplyComponentFn = fn(&mut World, EntityId, &[u8], &EntityMap) -> Result<(), ReplicationError>;
+pub type RemoveComponentFn = fn(&mut World, EntityId);
+
+// Rewrites the entity ids a component holds from the server ids to the ids of the receiving world.
+pub trait MapEntities {
+    fn map_entities(&mut self, entities: &EntityMap) -> Result<(), ReplicationError>;
+}
+
+pub struct ReplicatedComponent {
+    type_id: TypeId,
+    name: &'static str,
+    encode: EncodeComponentFn,
+    apply: ApplyComponentFn,
+    remove: RemoveComponentFn,
+}
+
+impl ReplicatedComponent {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+}
+
+// The components sent to the clients, anything not registered stays on the server.
+// Diffs refer to the components by their registration order, both worlds must register them in the same order.
+pub struct ReplicationRegistry {
+    components: Vec<ReplicatedComponent>,
+}
+
+impl Default for ReplicationRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplicationRegistry {
+    // The parent of an entity is replicated from the start, the children are rebuilt from it.
+    pub fn new() -> Self {
+        fn apply_parent(world: &mut World, entity_id: EntityId, bytes: &[u8], entities: &EntityMap) -> Result<(), ReplicationError> {
+            let parent: EntityId = bincode::deserialize(bytes)?;
+            Ok(world.set_parent(entity_id, entities.local(parent)?)?)
+        }
+
+        fn remove_parent(world: &mut World, entity_id: EntityId) {
+            world.remove_parent(entity_id);
+        }
+
+        let mut registry = Self { components: vec![] };
+        registry.push::<Parent>(apply_parent, remove_parent);
+        registry
+    }
+
+    pub fn register<T: Component + Serialize + DeserializeOwned + 'static>(&mut self) -> &mut Self {
+        fn apply<T: Component + DeserializeOwned + 'static>(world: &mut World, entity_id: EntityId, bytes: &[u8], _entities: &EntityMap) -> Result<(), ReplicationError> {
+            world.add(entity_id, bincode::deserialize::<T>(bytes)?);
+            Ok(())
+        }
+
+        self.push::<T>(apply::<T>, remove::<T>)
+    }
+
+    // For the components holding entity ids, they are mapped before the component is inserted.
+    pub fn register_mapped<T: Component + MapEntities + Serialize + DeserializeOwned + 'static>(&mut self) -> &mut Self {
+        fn apply<T: Component + MapEntities + DeserializeOwned + 'static>(world: &mut World, entity_id: EntityId, bytes: &[u8], entities: &EntityMap) -> Result<(), ReplicationError> {
+            let mut comp = bincode::deserialize::<T>(bytes)?;
+            comp.map_entities(entities)?;
+            world.add(entity_id, comp);
+            Ok(())
+        }
+
+        self.push::<T>(apply::<T>, remove::<T>)
+    }
+
+    pub fn components(&self) -> &Vec<ReplicatedComponent> {
+        &self.components
+    }
+
+    fn push<T: Component + Serialize + 'static>(&mut self, apply: ApplyComponentFn, remove: RemoveComponentFn) -> &mut Self {
+        fn encode<T: Component + Serialize + 'static>(world: &World, entity_id: EntityId) -> Option<bincode::Result<Vec<u8>>> {
+            world.query::<T>(entity_id).map(bincode::serialize)
+        }
+
+        assert!(self.components.iter().all(|replicated| replicated.type_id != TypeId::of::<T>()),
+                "{} is already replicated", std::any::type_name::<T>());
+        assert!(self.components.len() < u16::MAX as usize, "too many replicated components");
+        self.components.push(ReplicatedComponent {
+            type_id: TypeId::of::<T>(),
+            name: std::any::type_name::<T>(),
+            encode: encode::<T>,
+            apply,
+            remove,
+        });
+        self
+    }
+}
+
+fn remove<T: Component + 'static>(world: &mut World, entity_id: EntityId) {
+    world.remove::<T>(entity_id);
+}
+
+// A component value sent to a client, the component is its index in the registry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ComponentChange {
+    pub entity: EntityId,
+    pub component: u16,
+    pub bytes: Vec<u8>,
+}
+
+// Everything that changed in the replicated part of a world after a tick, see World::diff.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct WorldDiff {
+    // the changes go up to this tick, the client acknowledges it once the diff is applied
+    tick: Tick,
+    spawned: Vec<EntityId>,
+    despawned: Vec<EntityId>,
+    added: Vec<ComponentChange>,
+    changed: Vec<ComponentChange>,
+    // entity, component
+    removed: Vec<(EntityId, u16)>,
+}
+
+impl WorldDiff {
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    pub fn spawned(&self) -> &[EntityId] {
+        &self.spawned
+    }
+
+    pub fn despawned(&self) -> &[EntityId] {
+        &self.despawned
+    }
+
+    pub fn added(&self) -> &[ComponentChange] {
+        &self.added
+    }
+
+    pub fn changed(&self) -> &[ComponentChange] {
+        &self.changed
+    }
+
+    pub fn removed(&self) -> &[(EntityId, u16)] {
+        &self.removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty() && self.despawned.is_empty() && self.added.is_empty()
+            && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+// The entity of the receiving world for each entity of the server.
+#[derive(Debug, Clone, Default)]
+pub struct EntityMap {
+    entities: HashMap<EntityId, EntityId>,
+}
+
+impl EntityMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, remote: EntityId) -> Option<EntityId> {
+        self.entities.get(&remote).copied()
+    }
+
+    pub fn local(&self, remote: EntityId) -> Result<EntityId, ReplicationError> {
+        self.get(remote).ok_or(ReplicationError::UnknownEntity(remote))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(EntityId, EntityId)> + '_ {
+        self.entities.iter().map(|(remote, local)| (*remote, *local))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplicationError {
+    UnknownComponent(u16),
+    // the diff refers to an entity the client never received
+    UnknownEntity(EntityId),
+    Decode(bincode::Error),
+    // the parents in the diff make a cycle
+    Hierarchy(HierarchyError),
+}
+
+impl Display for ReplicationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplicationError::UnknownComponent(index) => write!(f, "no replicated component at index {index}"),
+            ReplicationError::UnknownEntity(entity_id) => write!(f, "the entity {entity_id} was never replicated"),
+            ReplicationError::Decode(error) => write!(f, "can't decode a component: {error}"),
+            ReplicationError::Hierarchy(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplicationError {}
+
+impl From<bincode::Error> for ReplicationError {
+    fn from(error: bincode::Error) -> Self {
+        ReplicationError::Decode(error)
+    }
+}
+
+impl From<HierarchyError> for ReplicationError {
+    fn from(error: HierarchyError) -> Self {
+        ReplicationError::Hierarchy(error)
+    }
+}
+
+// Changes are the writes stamped after since, a client that acknowledged nothing passes 0 and gets the whole world.
+// A row counts as written when a query hands it out mutably, fetching the query alone doesn't stamp anything.
+// The removals and releases are read from logs kept while the world is replicated, see World::enable_replication,
+// and shortened by World::prune_changes.
+pub fn diff_world(world: &World, registry: &ReplicationRegistry, since: Tick) -> bincode::Result<WorldDiff> {
+    let archetypes = world.archetypes();
+    let mut diff = WorldDiff { tick: archetypes.change_tick(), ..WorldDiff::default() };
+
+    diff.spawned = archetypes.spawn_ticks()
+        .filter(|(_, tick)| *tick > since)
+        .map(|(entity_id, _)| entity_id)
+        .collect();
+    diff.spawned.sort_unstable();
+    diff.despawned = archetypes.despawned().iter()
+        .filter(|(tick, _)| *tick > since)
+        .map(|(_, entity_id)| *entity_id)
+        .collect();
+
+    for (index, replicated) in registry.components.iter().enumerate() {
+        let Some(component_id) = archetypes.components().id_from_type(replicated.type_id) else {
+            continue;
+        };
+
+        let mut rows: Vec<(EntityId, ComponentTicks)> = vec![];
+        if archetypes.components().info(component_id).storage() == StorageType::Sparse {
+            if let Some(sparse_set) = archetypes.sparse_set(component_id) {
+                rows.extend(sparse_set.entities().iter().filter_map(|entity_id| Some((*entity_id, sparse_set.ticks(*entity_id)?))));
+            }
+        } else {
+            for archetype in archetypes.archetypes_with(component_id) {
+                rows.extend(archetype.indices().iter().filter_map(|entity_id| Some((*entity_id, archetype.ticks(*entity_id, component_id)?))));
+            }
+        }
+
+        for (entity_id, ticks) in rows {
+            let changes = if ticks.is_added_after(since) {
+                &mut diff.added
+            } else if ticks.is_changed_after(since) {
+                &mut diff.changed
+            } else {
+                continue;
+            };
+
+            if let Some(bytes) = (replicated.encode)(world, entity_id) {
+                changes.push(ComponentChange { entity: entity_id, component: index as u16, bytes: bytes? });
+            }
+        }
+    }
+
+    // only the entities the client already has need their removals,
+    // the ones spawned since arrive without the component and the despawns cover the others
+    let mut removed = HashSet::new();
+    let log = archetypes.removed();
+    for (_, entity_id, component_id) in &log[log.partition_point(|(tick, _, _)| *tick <= since)..] {
+        let Some(type_id) = archetypes.components().info(*component_id).type_id() else {
+            continue;
+        };
+        let Some(index) = registry.components.iter().position(|replicated| replicated.type_id == type_id) else {
+            continue;
+        };
+
+        let known = archetypes.spawn_tick(*entity_id).is_some_and(|spawned| spawned <= since);
+        if known && removed.insert((*entity_id, index)) {
+            diff.removed.push((*entity_id, index as u16));
+        }
+    }
+
+    Ok(diff)
+}
+
+// The diff of what an observer sees. The entities that entered its view after since are sent whole,
+// the ones that left it are despawned on the client.
+pub fn diff_visible(world: &World, registry: &ReplicationRegistry, since: Tick, view: &ObserverView) -> bincode::Result<WorldDiff> {
+    let full = diff_world(world, registry, since)?;
+    let archetypes = world.archetypes();
+    let mut entered: Vec<EntityId> = view.visible()
+        .filter(|entity_id| view.entered_tick(*entity_id).is_some_and(|tick| tick > since))
+        .filter(|entity_id| archetypes.spawn_tick(*entity_id).is_some())
+        .collect();
+    entered.sort_unstable();
+    let entered_set: HashSet<EntityId> = entered.iter().copied().collect();
+    // the entities the client already has, the full diff is filtered on them
+    let known = |entity_id: &EntityId| view.is_visible(*entity_id) && !entered_set.contains(entity_id);
+
+    let mut diff = WorldDiff { tick: full.tick, ..WorldDiff::default() };
+    let mut despawned = HashSet::new();
+    diff.despawned = view.left_after(since)
+        .chain(full.despawned.into_iter().filter(|entity_id| view.is_visible(*entity_id)))
+        .filter(|entity_id| despawned.insert(*entity_id))
+        .collect();
+    diff.spawned = full.spawned.into_iter().filter(known).chain(entered.iter().copied()).collect();
+    diff.added = full.added.into_iter().filter(|change| known(&change.entity)).collect();
+    diff.changed = full.changed.into_iter().filter(|change| known(&change.entity)).collect();
+    diff.removed = full.removed.into_iter().filter(|(entity_id, _)| known(entity_id)).collect();
+
+    for entity_id in entered {
+        for (index, replicated) in registry.components.iter().enumerate() {
+            if let Some(bytes) = (replicated.encode)(world, entity_id) {
+                diff.added.push(ComponentChange { entity: entity_id, component: index as u16, bytes: bytes? });
+            }
+        }
+    }
+
+    Ok(diff)
+}
+
+// Applies the despawns, then the spawns, the removals and last the new component values,
+// so a diff sent twice leaves the world as it was after the first one.
+pub fn apply_diff(world: &mut World, diff: &WorldDiff, registry: &ReplicationRegistry, entities: &mut EntityMap) -> Result<(), ReplicationError> {
+    for remote in &diff.despawned {
+        if let Some(local) = entities.entities.remove(remote) {
+            world.release(local);
+        }
+    }
+
+    for remote in &diff.spawned {
+        if !entities.entities.contains_key(remote) {
+            let local = world.create();
+            entities.entities.insert(*remote, local);
+        }
+    }
+
+    for (remote, component) in &diff.removed {
+        let replicated = registry.components.get(*component as usize).ok_or(ReplicationError::UnknownComponent(*component))?;
+        if let Some(local) = entities.get(*remote) {
+            (replicated.remove)(world, local);
+        }
+    }
+
+    // a reparenting can make a cycle until the other parents of the diff are applied, those are retried at the end
+    let mut deferred = vec![];
+    for change in diff.added.iter().chain(&diff.changed) {
+        let replicated = registry.components.get(change.component as usize).ok_or(ReplicationError::UnknownComponent(change.component))?;
+        let local = entities.local(change.entity)?;
+        match (replicated.apply)(world, local, &change.bytes, entities) {
+            Err(ReplicationError::Hierarchy(HierarchyError::Cycle { .. })) => deferred.push((replicated, local, change)),
+            result => result?,
+        }
+    }
+
+    while !deferred.is_empty() {
+        let count = deferred.len();
+        let mut error = None;
+        deferred.retain(|(replicated, local, change)| match (replicated.apply)(world, *local, &change.bytes, entities) {
+            Ok(()) => false,
+            Err(retry_error) => {
+                error = Some(retry_error);
+                true
+            }
+        });
+        if let Some(error) = error.filter(|_| deferred.len() == count) {
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use crate::archetype::archetype_filter::Without;
+    use crate::cow_macros::Component;
+    use crate::entity::entity::EntityId;
+    use crate::hierarchy::hierarchy::HierarchyError;
+    use crate::serialize::replication::{ComponentChange, EntityMap, ReplicationError, ReplicationRegistry, WorldDiff};
+    use crate::world::World;
+
+    #[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Component)]
+    struct Frozen;
+
+    fn registry() -> ReplicationRegistry {
+        let mut registry = ReplicationRegistry::new();
+        registry.register::<Health>();
+        registry
+    }
+
+    #[test]
+    fn apply_diff_follows_the_server() {
+        let registry = registry();
+        let mut server = World::new();
+        server.enable_replication();
+        let (a, b, c) = (server.create(), server.create(), server.create());
+        server.add(a, Health(10));
+        server.add(b, Health(5));
+        server.set_parent(c, a).unwrap();
+
+        let mut client = World::new();
+        let mut entities = EntityMap::new();
+        let diff = server.diff(&registry, 0).unwrap();
+        client.apply_diff(&diff, &registry, &mut entities).unwrap();
+        let local = |remote| entities.get(remote).unwrap();
+        assert_eq!(client.query::<Health>(local(a)), Some(&Health(10)));
+        assert_eq!(client.parent(local(c)), Some(local(a)));
+
+        let since = diff.tick();
+        server.advance_tick();
+        server.get_mut::<Health>(a).unwrap().0 = 7;
+        server.remove::<Health>(b);
+        server.release(c);
+        let diff = server.diff(&registry, since).unwrap();
+        assert_eq!(diff.changed().len(), 1);
+        assert_eq!(diff.removed(), [(b, 1)]);
+        assert_eq!(diff.despawned(), [c]);
+
+        let despawned = local(c);
+        client.apply_diff(&diff, &registry, &mut entities).unwrap();
+        let local = |remote| entities.get(remote).unwrap();
+        assert_eq!(client.query::<Health>(local(a)), Some(&Health(7)));
+        assert_eq!(client.query::<Health>(local(b)), None);
+        assert!(!client.is_alive(despawned));
+        assert_eq!(entities.get(c), None);
+        assert_eq!(client.entities_count(), 2);
+    }
+
+    #[test]
+    fn only_the_rows_handed_out_mutably_are_changed() {
+        let registry = registry();
+        let mut world = World::new();
+        let entities: Vec<EntityId> = (0..1000).map(|_| world.create()).collect();
+        for (i, entity_id) in entities.iter().enumerate() {
+            world.add(*entity_id, Health(i as u32));
+            if i % 2 == 0 {
+                world.add(*entity_id, Frozen);
+            }
+        }
+        let since = world.diff(&registry, 0).unwrap().tick();
+        world.advance_tick();
+
+        // fetched but never written
+        let _ = world.comps_mut::<Health>();
+        let _ = world.query_tuple_mut::<(&mut Health,), ()>();
+        assert!(world.diff(&registry, since).unwrap().is_empty());
+
+        world.comps_filtered_mut::<Health, Without<Frozen>>().iter().for_each(|(_, health)| health.0 += 1);
+        assert_eq!(world.diff(&registry, since).unwrap().changed().len(), 500);
+
+        world.advance_tick();
+        let since = world.diff(&registry, 0).unwrap().tick();
+        world.advance_tick();
+        let mut query = world.query_tuple_mut::<(&mut Health, &Frozen), ()>();
+        query.query_mut(entities[0]).unwrap().0 .0 += 1;
+        let diff = world.diff(&registry, since).unwrap();
+        assert_eq!(diff.changed().len(), 1);
+        assert_eq!(diff.changed()[0].entity, entities[0]);
+    }
+
+    // the parent of entity is parent, both remote ids
+    fn parent_change(entity: EntityId, parent: EntityId) -> ComponentChange {
+        ComponentChange { entity, component: 0, bytes: bincode::serialize(&parent).unwrap() }
+    }
+
+    #[test]
+    fn apply_diff_retries_temporary_parent_cycles() {
+        let registry = ReplicationRegistry::new();
+        let mut server = World::new();
+        let (a, b, c) = (server.create(), server.create(), server.create());
+        server.set_parent(b, a).unwrap();
+        let mut client = World::new();
+        let mut entities = EntityMap::new();
+        client.apply_diff(&server.diff(&registry, 0).unwrap(), &registry, &mut entities).unwrap();
+
+        // a under b is only valid once b moved under c
+        let diff = WorldDiff { changed: vec![parent_change(a, b), parent_change(b, c)], ..WorldDiff::default() };
+        client.apply_diff(&diff, &registry, &mut entities).unwrap();
+        let local = |remote| entities.get(remote).unwrap();
+        assert_eq!(client.parent(local(a)), Some(local(b)));
+        assert_eq!(client.parent(local(b)), Some(local(c)));
+    }
+
+    #[test]
+    fn apply_diff_rejects_parent_cycles() {
+        let registry = ReplicationRegistry::new();
+        let mut server = World::new();
+        let (a, b) = (server.create(), server.create());
+        let mut client = World::new();
+        let mut entities = EntityMap::new();
+        client.apply_diff(&server.diff(&registry, 0).unwrap(), &registry, &mut entities).unwrap();
+
+        let diff = WorldDiff { changed: vec![parent_change(a, b), parent_change(b, a)], ..WorldDiff::default() };
+        let result = client.apply_diff(&diff, &registry, &mut entities);
+        assert!(matches!(result, Err(ReplicationError::Hierarchy(HierarchyError::Cycle { .. }))));
+
+        let diff = WorldDiff { changed: vec![parent_change(a, a)], ..WorldDiff::default() };
+        let result = client.apply_diff(&diff, &registry, &mut entities);
+        assert!(matches!(result, Err(ReplicationError::Hierarchy(HierarchyError::SelfParent(_)))));
+    }
+
+    #[test]
+    fn removals_are_only_logged_while_replicated() {
+        let mut world = World::new();
+        let entities: Vec<EntityId> = (0..4).map(|_| world.create()).collect();
+        for entity_id in &entities {
+            world.add(*entity_id, Health(1));
+        }
+        world.remove::<Health>(entities[0]);
+        world.release(entities[1]);
+        assert!(world.archetypes().removed().is_empty() && world.archetypes().despawned().is_empty());
+
+        world.enable_replication();
+        world.remove::<Health>(entities[2]);
+        let tick = world.advance_tick();
+        world.release(entities[3]);
+        assert_eq!(world.archetypes().removed().len(), 1);
+        assert_eq!(world.archetypes().despawned(), [(tick, entities[3])]);
+
+        // pruning forgets up to the tick, compact gives the capacity back
+        world.prune_changes(tick - 1);
+        world.compact();
+        assert!(world.archetypes().removed().is_empty());
+        assert_eq!(world.archetypes().despawned().len(), 1);
+
+        world.disable_replication();
+        assert!(world.archetypes().despawned().is_empty());
+    }
+}
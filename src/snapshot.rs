@@ -0,0 +1,129 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::archetype::archetype_filter::QueryFilter;
+use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::archetype::query_data::ReadOnlyQueryData;
+use crate::component::component::Component;
+use crate::comps::{Comps, Query};
+use crate::entity::entity::EntityId;
+use crate::resource::resource::Resource;
+
+// An immutable copy of the world, taken with World::snapshot.
+// The component storages and the resources are shared with the world until it writes to them,
+// so taking one is cheap and it can be sent to another thread while the world keeps running.
+// Components and resources that don't implement Clone are left out.
+pub struct WorldSnapshot {
+    archetypes: ArchetypeManager,
+    resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl WorldSnapshot {
+    pub fn new(archetypes: ArchetypeManager, resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>) -> Self {
+        Self { archetypes, resources }
+    }
+
+    pub fn query<T: Component + 'static>(&self, entity_id: EntityId) -> Option<&T> {
+        self.archetypes.query::<T>(entity_id)
+    }
+
+    pub fn comps<T: Component + 'static>(&self) -> Comps<'_, T> {
+        Comps::new(self.archetypes.fetch_info::<T>())
+    }
+
+    pub fn comps_filtered<T: Component + 'static, F: QueryFilter>(&self) -> Comps<'_, T, F> {
+        Comps::new(self.archetypes.fetch_info_filtered::<T, F>())
+    }
+
+    pub fn query_tuple<D: ReadOnlyQueryData, F: QueryFilter>(&self) -> Query<'_, D, F> {
+        Query::new(self.archetypes.fetch_query_read::<D, F>())
+    }
+
+    pub fn res<T: Resource + 'static>(&self) -> Option<&T> {
+        // the resources are kept as the Arc shared with the world, see ResLock
+        self.resources.get(&TypeId::of::<T>())?.downcast_ref::<Arc<T>>().map(|res| &**res)
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item=EntityId> + '_ {
+        self.archetypes.entities()
+    }
+
+    pub fn entities_count(&self) -> usize {
+        self.archetypes.entities_count()
+    }
+
+    pub fn archetypes(&self) -> &ArchetypeManager {
+        &self.archetypes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cow_macros::{Component, Resource};
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Pos(f32);
+
+    // not Clone, left out of the snapshots
+    #[derive(Component, Debug, PartialEq)]
+    struct Big(Vec<u64>);
+
+    #[derive(Resource, Clone, Debug, PartialEq)]
+    struct Score(u32);
+
+    #[derive(Resource)]
+    struct Socket;
+
+    #[test]
+    fn left_out_components_are_not_queried() {
+        let mut world = World::new();
+        let entity = world.create();
+        world.add(entity, Big(vec![1, 2, 3]));
+        world.add(entity, Pos(1.0));
+
+        let snapshot = world.snapshot();
+        assert_eq!(snapshot.query_tuple::<(&Big, &Pos), ()>().iter().count(), 0);
+        assert_eq!(snapshot.comps::<Big>().iter().count(), 0);
+        assert!(snapshot.query::<Big>(entity).is_none());
+        let positions: Vec<_> = snapshot.query_tuple::<(&Pos,), ()>().iter().map(|(entity_id, (pos,))| (entity_id, pos.0)).collect();
+        assert_eq!(positions, vec![(entity, 1.0)]);
+    }
+
+    #[test]
+    fn snapshot_is_isolated_from_the_world() {
+        let mut world = World::new();
+        let first = world.create();
+        world.add(first, Pos(1.0));
+
+        let snapshot = world.snapshot();
+        *world.get_mut::<Pos>(first).unwrap() = Pos(2.0);
+        let second = world.create();
+        world.add(second, Pos(3.0));
+        world.remove::<Pos>(first);
+
+        assert_eq!(snapshot.query::<Pos>(first), Some(&Pos(1.0)));
+        assert!(snapshot.query::<Pos>(second).is_none());
+        assert_eq!(snapshot.entities_count(), 1);
+        assert!(world.query::<Pos>(first).is_none());
+        assert_eq!(world.query::<Pos>(second), Some(&Pos(3.0)));
+    }
+
+    #[test]
+    fn resources_are_shared_until_written() {
+        let mut world = World::new();
+        world.set_res(Score(1));
+        world.set_res(Socket);
+
+        let snapshot = world.snapshot();
+        {
+            let score = world.resources().query::<Score>().unwrap().resource().read().unwrap();
+            assert!(std::ptr::eq(&*score, snapshot.res::<Score>().unwrap()));
+        }
+        assert!(snapshot.res::<Socket>().is_none());
+
+        world.resources().query::<Score>().unwrap().resource().write().unwrap().0 = 2;
+        assert_eq!(snapshot.res::<Score>(), Some(&Score(1)));
+        assert_eq!(*world.resources().query::<Score>().unwrap().resource().read().unwrap(), Score(2));
+    }
+}
@@ -1,19 +1,85 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::mem::ManuallyDrop;
+use crate::archetype::archetype_filter::QueryFilter;
 use crate::archetype::archetype_manager::ArchetypeManager;
+use crate::archetype::query_data::{QueryData, ReadOnlyQueryData};
+use crate::commands::{EntityCommand, EntityCommands};
 use crate::component::component::Component;
+use crate::component::component_box::ComponentBox;
+use crate::component::hooks::{ComponentHook, HookContext};
+use crate::component::required::RequiredComponent;
+use crate::component::tick::Tick;
+use crate::comps::{Comps, CompsMut, Query};
+use crate::archetype::archetype_dynamic::DynamicQuery;
+use crate::component::registry::{ComponentDescriptor, ComponentId};
 use crate::entity::entity::EntityId;
+use crate::entity::entity_ref::{EntityError, EntityMut, EntityRef};
 use crate::entity::entity_manager::EntityManager;
+use crate::hierarchy::hierarchy::{Children, HierarchyError, Parent};
+use crate::observer::observer::{Event, Observer, Observers, Trigger};
+use crate::hierarchy::hierarchy_iter::{Ancestors, DescendantsBreadthFirst, DescendantsDepthFirst};
+use crate::relation::relation::{release_relations, Related, RelatedBy, Relation, ReleaseRelations};
 use crate::resource::res_manager::ResManager;
+use crate::schedule::task_state::TaskState;
+use crate::snapshot::WorldSnapshot;
+#[cfg(feature = "serialize")]
+use crate::serialize::binary::{load_world, save_world, LoadError};
+#[cfg(feature = "serialize")]
+use crate::serialize::registry::SerializeRegistry;
+#[cfg(feature = "serialize")]
+use crate::serialize::replication::{apply_diff, diff_visible, diff_world, EntityMap, ReplicationError, ReplicationRegistry, WorldDiff};
+#[cfg(feature = "serialize")]
+use crate::interest::interest::ObserverView;
 use crate::resource::resource::Resource;
 
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ForkError {
+    // the type name of a component stored in the world that doesn't implement Clone
+    Component(String),
+    // same for a resource
+    Resource(String),
+}
+
+impl Display for ForkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForkError::Component(name) => write!(f, "the component {name} must implement Clone to fork the world"),
+            ForkError::Resource(name) => write!(f, "the resource {name} must implement Clone to fork the world"),
+        }
+    }
+}
+
+impl std::error::Error for ForkError {}
+
 pub struct World {
     resources: ResManager,
     archetypes: ArchetypeManager,
     entities: EntityManager,
+    // cleans the relations of each kind when an entity is released
+    relations: Vec<(TypeId, ReleaseRelations)>,
+    observers: Observers,
+    // for each event, the state of each of its observers, see TaskState
+    observer_states: HashMap<TypeId, Vec<TaskState>>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self {
-        Self { archetypes: ArchetypeManager::new(), entities: EntityManager::new(), resources: ResManager::new() }
+        Self {
+            archetypes: ArchetypeManager::new(),
+            entities: EntityManager::new(),
+            resources: ResManager::new(),
+            relations: vec![],
+            observers: Observers::new(),
+            observer_states: HashMap::new(),
+        }
     }
 
     pub fn create(&mut self) -> EntityId {
@@ -23,30 +89,796 @@ impl World {
     }
 
     pub fn release(&mut self, entity_id: EntityId) {
+        if !self.entities.is_alive(entity_id) {
+            return;
+        }
+
+        let mut commands = vec![];
+        self.run_despawn_hooks(entity_id, &mut commands);
+        self.release_relations(entity_id);
+
+        // the children become roots
+        if let Some(children) = self.archetypes.query::<Children>(entity_id) {
+            for child in children.as_slice().to_vec() {
+                self.archetypes.remove::<Parent>(child);
+            }
+        }
+        self.remove_parent(entity_id);
+
+        self.archetypes.remove_entity(entity_id);
         self.entities.release(entity_id);
+        self.apply_commands(commands);
+    }
+
+    // Releases the entity and all its descendants.
+    pub fn despawn_recursive(&mut self, entity_id: EntityId) {
+        if !self.entities.is_alive(entity_id) {
+            return;
+        }
+
+        self.remove_parent(entity_id);
+        let descendants: Vec<EntityId> = self.descendants_depth_first(entity_id).collect();
+        let mut commands = vec![];
+        // the whole subtree goes away, no hierarchy link needs to be fixed
+        for entity_id in std::iter::once(entity_id).chain(descendants) {
+            if !self.entities.is_alive(entity_id) {
+                continue;
+            }
+            self.run_despawn_hooks(entity_id, &mut commands);
+            self.release_relations(entity_id);
+            self.archetypes.remove_entity(entity_id);
+            self.entities.release(entity_id);
+        }
+        self.apply_commands(commands);
+    }
+
+    pub fn is_alive(&self, entity_id: EntityId) -> bool {
+        self.entities.is_alive(entity_id)
+    }
+
+    pub fn entity(&self, entity_id: EntityId) -> Result<EntityRef<'_>, EntityError> {
+        EntityRef::new(self, entity_id)
+    }
+
+    pub fn entity_mut(&mut self, entity_id: EntityId) -> Result<EntityMut<'_>, EntityError> {
+        EntityMut::new(self, entity_id)
+    }
+
+    // Attaches the child to the parent, detaching it from its previous parent.
+    // Nothing changes if either entity is dead or if it would make a cycle.
+    pub fn set_parent(&mut self, child: EntityId, parent: EntityId) -> Result<(), HierarchyError> {
+        for entity_id in [child, parent] {
+            if !self.entities.is_alive(entity_id) {
+                return Err(HierarchyError::Dead(entity_id));
+            }
+        }
+        if child == parent {
+            return Err(HierarchyError::SelfParent(child));
+        }
+        if self.ancestors(parent).any(|ancestor| ancestor == child) {
+            return Err(HierarchyError::Cycle { child, parent });
+        }
+
+        self.remove_parent(child);
+        self.archetypes.add(child, Parent::new(parent));
+        match self.archetypes.query_mut::<Children>(parent) {
+            Some(children) => children.push(child),
+            None => self.archetypes.add(parent, Children::new(child)),
+        }
+        Ok(())
+    }
+
+    pub fn remove_parent(&mut self, child: EntityId) {
+        let Some(parent) = self.archetypes.query::<Parent>(child).map(Parent::get) else {
+            return;
+        };
+
+        self.archetypes.remove::<Parent>(child);
+        if let Some(children) = self.archetypes.query_mut::<Children>(parent) {
+            children.remove(child);
+            if children.is_empty() {
+                self.archetypes.remove::<Children>(parent);
+            }
+        }
+    }
+
+    pub fn parent(&self, entity_id: EntityId) -> Option<EntityId> {
+        self.archetypes.query::<Parent>(entity_id).map(Parent::get)
+    }
+
+    pub fn children(&self, entity_id: EntityId) -> &[EntityId] {
+        self.archetypes.query::<Children>(entity_id).map(Children::as_slice).unwrap_or_default()
+    }
+
+    pub fn ancestors(&self, entity_id: EntityId) -> impl Iterator<Item=EntityId> + '_ {
+        Ancestors::new(entity_id, |entity_id| self.parent(entity_id))
+    }
+
+    pub fn descendants_depth_first(&self, entity_id: EntityId) -> impl Iterator<Item=EntityId> + '_ {
+        DescendantsDepthFirst::new(entity_id, |entity_id| self.archetypes.query::<Children>(entity_id).map(Children::as_slice))
+    }
+
+    pub fn descendants_breadth_first(&self, entity_id: EntityId) -> impl Iterator<Item=EntityId> + '_ {
+        DescendantsBreadthFirst::new(entity_id, |entity_id| self.archetypes.query::<Children>(entity_id).map(Children::as_slice))
+    }
+
+    // Links the source to the target of the relation, replacing the previous target of an exclusive relation.
+    pub fn relate<R: Relation>(&mut self, source: EntityId, relation: R) {
+        let target = relation.target();
+        if !self.entities.is_alive(source) || !self.entities.is_alive(target) {
+            return;
+        }
+
+        self.track_relation::<R>();
+
+        if R::EXCLUSIVE {
+            let previous: Vec<EntityId> = self.relations::<R>(source).iter().map(R::target).filter(|previous| *previous != target).collect();
+            for previous in previous {
+                self.unrelate::<R>(source, previous);
+            }
+        }
+
+        match self.archetypes.query_mut::<Related<R>>(source) {
+            Some(related) => related.insert(relation),
+            None => self.archetypes.add(source, Related::new(relation)),
+        }
+        match self.archetypes.query_mut::<RelatedBy<R>>(target) {
+            Some(related_by) => related_by.push(source),
+            None => self.archetypes.add(target, RelatedBy::<R>::new(source)),
+        }
+    }
+
+    pub fn unrelate<R: Relation>(&mut self, source: EntityId, target: EntityId) {
+        let Some(related) = self.archetypes.query_mut::<Related<R>>(source) else {
+            return;
+        };
+        if !related.remove(target) {
+            return;
+        }
+        if related.is_empty() {
+            self.archetypes.remove::<Related<R>>(source);
+        }
+
+        if let Some(related_by) = self.archetypes.query_mut::<RelatedBy<R>>(target) {
+            related_by.remove(source);
+            if related_by.is_empty() {
+                self.archetypes.remove::<RelatedBy<R>>(target);
+            }
+        }
+    }
+
+    // The relations of kind R going from the source.
+    pub fn relations<R: Relation>(&self, source: EntityId) -> &[R] {
+        self.archetypes.query::<Related<R>>(source).map(Related::as_slice).unwrap_or_default()
+    }
+
+    // The entities related by R to the target.
+    pub fn sources<R: Relation>(&self, target: EntityId) -> &[EntityId] {
+        self.archetypes.query::<RelatedBy<R>>(target).map(RelatedBy::sources).unwrap_or_default()
+    }
+
+    // The component T of each target of the source, e.g. the Guild of every MemberOf.
+    pub fn query_targets<R: Relation, T: Component + 'static>(&self, source: EntityId) -> impl Iterator<Item=(EntityId, &T)> + '_ {
+        self.relations::<R>(source).iter()
+            .filter_map(|relation| Some((relation.target(), self.query::<T>(relation.target())?)))
+    }
+
+    // Makes sure the relations of kind R are cleaned when an entity is released.
+    pub(crate) fn track_relation<R: Relation>(&mut self) {
+        if !self.relations.iter().any(|(type_id, _)| *type_id == TypeId::of::<R>()) {
+            self.relations.push((TypeId::of::<R>(), release_relations::<R>));
+        }
+    }
+
+    fn release_relations(&mut self, entity_id: EntityId) {
+        for index in 0..self.relations.len() {
+            let release = self.relations[index].1;
+            release(self, entity_id);
+        }
+    }
+
+    // e.g. world.observe(on_damage) with #[cow_task] fn on_damage(trigger: Trigger<Damage>, ...)
+    pub fn observe<E: Event>(&mut self, observer: impl Observer<E> + 'static) {
+        self.observers.add(observer);
+    }
+
+    // Runs the observers of E right away, then applies their commands.
+    pub fn trigger<E: Event>(&mut self, event: E, target: EntityId) {
+        if self.entities.is_alive(target) {
+            self.run_observers(&event, Some(target));
+        }
+    }
+
+    pub fn trigger_global<E: Event>(&mut self, event: E) {
+        self.run_observers(&event, None);
+    }
+
+    fn run_observers<E: Event>(&mut self, event: &E, origin: Option<EntityId>) {
+        let Some(observers) = self.observers.get::<E>() else {
+            return;
+        };
+
+        let states = self.observer_states.entry(TypeId::of::<E>()).or_default();
+        states.resize_with(observers.len(), TaskState::new);
+        let mut commands = EntityCommands::new(&mut self.entities);
+        let mut propagate = E::PROPAGATE;
+        let mut target = origin;
+        loop {
+            for (observer, state) in observers.iter().zip(states.iter_mut()) {
+                observer.run(Trigger::new(event, target, origin, &mut propagate), &mut self.archetypes, &mut commands, &self.resources, state);
+            }
+
+            target = match target {
+                Some(entity_id) if propagate => self.archetypes.query::<Parent>(entity_id).map(Parent::get),
+                _ => None,
+            };
+            if target.is_none() {
+                break;
+            }
+        }
+
+        let commands = commands.take_commands();
+        self.apply_commands(commands);
+    }
+
+    pub fn register<T: Component + 'static>(&mut self) -> ComponentId {
+        self.archetypes.register::<T>()
+    }
+
+    // Registers a component defined at runtime, see ComponentDescriptor::new
+    pub fn register_dynamic(&mut self, descriptor: ComponentDescriptor) -> ComponentId {
+        assert!(descriptor.is_dynamic(), "rust components are registered with World::register");
+        self.archetypes.register_descriptor(descriptor)
+    }
+
+    // Inserts a component defined at runtime from its bytes.
+    pub fn add_bytes(&mut self, entity_id: EntityId, component_id: ComponentId, bytes: &[u8]) {
+        let info = self.archetypes.components().info(component_id);
+        // the bytes of a rust type could break its invariants
+        assert!(info.is_dynamic(), "{} is not a dynamic component", info.name());
+        assert_eq!(bytes.len(), info.layout().size(), "wrong size for the component {}", info.name());
+        unsafe { self.insert_raw(entity_id, &[(component_id, bytes.as_ptr())]) };
+    }
+
+    /// Moves the component pointed by comp to the entity.
+    ///
+    /// # Safety
+    /// comp must point to a valid value of the component registered as component_id,
+    /// the caller must not use or drop it after.
+    pub unsafe fn add_raw(&mut self, entity_id: EntityId, component_id: ComponentId, comp: *const u8) {
+        self.insert_raw(entity_id, &[(component_id, comp)]);
+    }
+
+    pub fn remove_by_id(&mut self, entity_id: EntityId, component_id: ComponentId) {
+        self.remove_with_hooks(entity_id, component_id);
+    }
+
+    pub fn get_ptr(&self, entity_id: EntityId, component_id: ComponentId) -> Option<*mut u8> {
+        self.archetypes.get_ptr(entity_id, component_id)
+    }
+
+    // The bytes of a component defined at runtime.
+    pub fn get_bytes(&self, entity_id: EntityId, component_id: ComponentId) -> Option<&[u8]> {
+        let info = self.archetypes.components().get_info(component_id)?;
+        if !info.is_dynamic() {
+            return None;
+        }
+
+        let ptr = self.archetypes.get_ptr(entity_id, component_id)?;
+        Some(unsafe { std::slice::from_raw_parts(ptr, info.layout().size()) })
+    }
+
+    pub fn get_bytes_mut(&mut self, entity_id: EntityId, component_id: ComponentId) -> Option<&mut [u8]> {
+        let info = self.archetypes.components().get_info(component_id)?;
+        if !info.is_dynamic() {
+            return None;
+        }
+
+        let size = info.layout().size();
+        let ptr = self.archetypes.get_ptr_mut(entity_id, component_id)?;
+        Some(unsafe { std::slice::from_raw_parts_mut(ptr, size) })
+    }
+
+    pub fn query_dynamic(&mut self, components: &[ComponentId]) -> DynamicQuery<'_> {
+        self.archetypes.fetch_dynamic(components)
     }
 
     pub fn add<T: Component + 'static>(&mut self, entity_id: EntityId, comp: T) {
-        self.archetypes.add(entity_id, comp);
+        let component_id = self.archetypes.register::<T>();
+        let comp = ManuallyDrop::new(comp);
+        unsafe { self.insert_raw(entity_id, &[(component_id, &*comp as *const T as *const u8)]) };
+    }
+
+    pub fn add_box(&mut self, entity_id: EntityId, comp: ComponentBox) {
+        let component_id = self.archetypes.register_descriptor(comp.descriptor().clone());
+        comp.insert_with(|comp| unsafe { self.insert_raw(entity_id, &[(component_id, comp)]) });
+    }
+
+    // Inserts the components together, the entity changes of archetype only once.
+    pub fn add_boxes(&mut self, entity_id: EntityId, comps: Vec<ComponentBox>) {
+        let component_ids: Vec<ComponentId> = comps.iter()
+            .map(|comp| self.archetypes.register_descriptor(comp.descriptor().clone()))
+            .collect();
+        ComponentBox::insert_all_with(comps, |comps| {
+            let comps: Vec<(ComponentId, *const u8)> = component_ids.into_iter().zip(comps.iter().copied()).collect();
+            unsafe { self.insert_raw(entity_id, &comps) };
+        });
     }
 
     pub fn remove<T: Component + 'static>(&mut self, entity_id: EntityId) {
-        self.archetypes.remove::<T>(entity_id);
+        if let Some(component_id) = self.archetypes.component_id::<T>() {
+            self.remove_with_hooks(entity_id, component_id);
+        }
+    }
+
+    // Every insert ends here: the required components the entity misses are added with their
+    // defaults in the same archetype move.
+    unsafe fn insert_raw(&mut self, entity_id: EntityId, comps: &[(ComponentId, *const u8)]) {
+        let required = self.missing_required(entity_id, comps);
+        if required.is_empty() {
+            self.insert_with_hooks(entity_id, comps);
+            return;
+        }
+
+        let (required_ids, required): (Vec<ComponentId>, Vec<ComponentBox>) = required.into_iter().unzip();
+        ComponentBox::insert_all_with(required, |required| {
+            let comps: Vec<(ComponentId, *const u8)> = comps.iter().copied()
+                .chain(required_ids.into_iter().zip(required.iter().copied()))
+                .collect();
+            self.insert_with_hooks(entity_id, &comps);
+        });
+    }
+
+    // The defaults of the components required by comps, and by the required ones in turn,
+    // that are neither on the entity nor in comps.
+    fn missing_required(&mut self, entity_id: EntityId, comps: &[(ComponentId, *const u8)]) -> Vec<(ComponentId, ComponentBox)> {
+        let mut missing = vec![];
+        let components = self.archetypes.components();
+        if comps.iter().all(|(component_id, _)| components.info(*component_id).required().is_empty()) {
+            return missing;
+        }
+
+        let mut pending: Vec<RequiredComponent> = comps.iter()
+            .flat_map(|(component_id, _)| components.info(*component_id).required().iter().copied())
+            .collect();
+        while let Some(required) = pending.pop() {
+            let component_id = match self.archetypes.components().id_from_type(required.type_id()) {
+                Some(component_id) => component_id,
+                None => self.archetypes.register_descriptor(required.descriptor()),
+            };
+            let inserted = comps.iter().any(|(comp_id, _)| *comp_id == component_id)
+                || missing.iter().any(|(comp_id, _)| *comp_id == component_id);
+            if inserted || self.archetypes.get_ptr(entity_id, component_id).is_some() {
+                continue;
+            }
+
+            pending.extend(self.archetypes.components().info(component_id).required().iter().copied());
+            missing.push((component_id, required.default_box()));
+        }
+        missing
+    }
+
+    // Runs the hooks of the components around the insert, see ComponentHooks
+    unsafe fn insert_with_hooks(&mut self, entity_id: EntityId, comps: &[(ComponentId, *const u8)]) {
+        let components = self.archetypes.components();
+        if comps.iter().all(|(component_id, _)| components.info(*component_id).hooks().is_empty()) {
+            self.archetypes.add_raw_many(entity_id, comps);
+            return;
+        }
+
+        let mut commands = vec![];
+        let replaced: Vec<bool> = comps.iter()
+            .map(|(component_id, _)| self.archetypes.get_ptr(entity_id, *component_id).is_some())
+            .collect();
+        for ((component_id, _), replaced) in comps.iter().zip(replaced.iter()) {
+            if *replaced {
+                let hooks = *self.archetypes.components().info(*component_id).hooks();
+                self.run_hook(hooks.on_replace, entity_id, *component_id, &mut commands);
+            }
+        }
+
+        self.archetypes.add_raw_many(entity_id, comps);
+
+        for ((component_id, _), replaced) in comps.iter().zip(replaced.iter()) {
+            let hooks = *self.archetypes.components().info(*component_id).hooks();
+            if !*replaced {
+                self.run_hook(hooks.on_add, entity_id, *component_id, &mut commands);
+            }
+            self.run_hook(hooks.on_insert, entity_id, *component_id, &mut commands);
+        }
+        self.apply_commands(commands);
+    }
+
+    fn remove_with_hooks(&mut self, entity_id: EntityId, component_id: ComponentId) {
+        let hooks = *self.archetypes.components().info(component_id).hooks();
+        if hooks.is_empty() || self.archetypes.get_ptr(entity_id, component_id).is_none() {
+            self.archetypes.remove_by_id(entity_id, component_id);
+            return;
+        }
+
+        let mut commands = vec![];
+        self.run_hook(hooks.on_replace, entity_id, component_id, &mut commands);
+        self.run_hook(hooks.on_remove, entity_id, component_id, &mut commands);
+        self.archetypes.remove_by_id(entity_id, component_id);
+        self.apply_commands(commands);
+    }
+
+    // The entity is about to be released, its components are still readable.
+    fn run_despawn_hooks(&mut self, entity_id: EntityId, commands: &mut Vec<EntityCommand>) {
+        let hooked: Vec<ComponentId> = self.archetypes.components_of(entity_id)
+            .filter(|component_id| !self.archetypes.components().info(*component_id).hooks().is_empty())
+            .collect();
+        for component_id in hooked {
+            let hooks = *self.archetypes.components().info(component_id).hooks();
+            self.run_hook(hooks.on_replace, entity_id, component_id, commands);
+            self.run_hook(hooks.on_remove, entity_id, component_id, commands);
+        }
+    }
+
+    // The commands queued by the hook are applied by the caller once the change is done.
+    fn run_hook(&mut self, hook: Option<ComponentHook>, entity_id: EntityId, component_id: ComponentId, commands: &mut Vec<EntityCommand>) {
+        if let Some(hook) = hook {
+            let mut context = HookContext::new(entity_id, component_id, &self.archetypes, &self.resources, EntityCommands::new(&mut self.entities));
+            hook(&mut context);
+            commands.extend(context.into_commands().take_commands());
+        }
+    }
+
+    pub fn apply_commands(&mut self, commands: Vec<EntityCommand>) {
+        for command in commands {
+            match command {
+                EntityCommand::NewEntity(entity_id, components) => {
+                    self.archetypes.add_entity(entity_id);
+                    self.add_boxes(entity_id, components);
+                }
+                EntityCommand::ReleaseEntity(entity_id) => {
+                    self.release(entity_id);
+                }
+                EntityCommand::SetParent(child, parent) => {
+                    // like the other commands, nothing happens if the entities died or it would make a cycle
+                    let _ = self.set_parent(child, parent);
+                }
+                EntityCommand::RemoveParent(child) => {
+                    self.remove_parent(child);
+                }
+                EntityCommand::ReleaseRecursive(entity_id) => {
+                    self.despawn_recursive(entity_id);
+                }
+                EntityCommand::Custom(_, command) | EntityCommand::Global(command) => {
+                    command(self);
+                }
+            }
+        }
     }
 
     pub fn query<T: Component + 'static>(&self, entity_id: EntityId) -> Option<&T> {
         self.archetypes.query::<T>(entity_id)
     }
 
+    // The component counts as changed at the current tick.
+    pub fn get_mut<T: Component + 'static>(&mut self, entity_id: EntityId) -> Option<&mut T> {
+        self.archetypes.query_mut::<T>(entity_id)
+    }
+
+    // Same queries as the tasks get, for tools and tests running without a scheduler.
+    pub fn comps<T: Component + 'static>(&self) -> Comps<'_, T> {
+        Comps::new(self.archetypes.fetch_info::<T>())
+    }
+
+    pub fn comps_filtered<T: Component + 'static, F: QueryFilter>(&self) -> Comps<'_, T, F> {
+        Comps::new(self.archetypes.fetch_info_filtered::<T, F>())
+    }
+
+    // The rows count as changed once they are handed out mutably, like in a task taking CompsMut<T>.
+    pub fn comps_mut<T: Component + 'static>(&mut self) -> CompsMut<'_, T> {
+        CompsMut::new(self.archetypes.fetch_info_mut::<T>())
+    }
+
+    pub fn comps_filtered_mut<T: Component + 'static, F: QueryFilter>(&mut self) -> CompsMut<'_, T, F> {
+        CompsMut::new(self.archetypes.fetch_info_filtered_mut::<T, F>())
+    }
+
+    // e.g. world.query_tuple::<(&Position, &Velocity), With<Player>>().iter()
+    pub fn query_tuple<D: ReadOnlyQueryData, F: QueryFilter>(&self) -> Query<'_, D, F> {
+        Query::new(self.archetypes.fetch_query_read::<D, F>())
+    }
+
+    pub fn query_tuple_mut<D: QueryData, F: QueryFilter>(&mut self) -> Query<'_, D, F> {
+        Query::new(self.archetypes.fetch_query::<D, F>())
+    }
+
     pub fn set_res<T: Resource + 'static>(&mut self, res: T) {
         self.resources.set(res)
     }
 
+    // A copy of the world to run on its own, e.g. for prediction.
+    // The component storages are shared until either world writes to them,
+    // every component and resource stored must implement Clone, the error names the first one that doesn't.
+    pub fn fork(&self) -> Result<World, ForkError> {
+        Ok(World {
+            resources: self.resources.fork()?,
+            archetypes: self.archetypes.fork()?,
+            entities: self.entities.clone(),
+            relations: self.relations.clone(),
+            observers: self.observers.clone(),
+            // the states belong to this world's manager, the fork's observers start over
+            observer_states: HashMap::new(),
+        })
+    }
+
+    // Puts the world back in the state of a fork saved earlier, the fork can be reused for the next rollback.
+    // The world is left as it is if the saved one can't be forked.
+    pub fn rollback(&mut self, saved: &World) -> Result<(), ForkError> {
+        *self = saved.fork()?;
+        Ok(())
+    }
+
+    // A cheap immutable copy of the components and resources that implement Clone.
+    pub fn snapshot(&self) -> WorldSnapshot {
+        WorldSnapshot::new(self.archetypes.snapshot(), self.resources.snapshot())
+    }
+
+    // Components added or written from now on are stamped with the new tick.
+    pub fn advance_tick(&mut self) -> Tick {
+        self.archetypes.advance_tick()
+    }
+
+    pub fn change_tick(&self) -> Tick {
+        self.archetypes.change_tick()
+    }
+
+    // Logs the removed components and released entities for the diffs, a world that isn't replicated
+    // doesn't keep them. The diffs only know the removals made while it's enabled.
+    pub fn enable_replication(&mut self) {
+        self.archetypes.set_track_removals(true);
+    }
+
+    // Stops the logs and forgets what they hold.
+    pub fn disable_replication(&mut self) {
+        self.archetypes.set_track_removals(false);
+    }
+
+    pub fn is_replicated(&self) -> bool {
+        self.archetypes.tracks_removals()
+    }
+
+    // Forgets the removals and releases made at or before tick, e.g. the oldest tick a client acknowledged.
+    // Nothing else forgets them, a replicated world that despawns or removes components must call it
+    // regularly or the logs keep growing.
+    pub fn prune_changes(&mut self, tick: Tick) {
+        self.archetypes.prune_removed(tick);
+    }
+
+    // Frees the empty archetypes and the unused capacity of the storages and logs, e.g. between two rounds
+    // of a long-running server. Returns the number of archetypes freed, see ArchetypeManager::compact
+    pub fn compact(&mut self) -> usize {
+        self.archetypes.compact()
+    }
+
     pub fn entities_count(&self) -> usize {
         self.entities.count()
     }
 
-    pub fn managers(&mut self) -> (&mut ArchetypeManager, &mut ResManager) {
-        (&mut self.archetypes, &mut self.resources)
+    // Writes the entities, the components and the resources registered in the registry.
+    #[cfg(feature = "serialize")]
+    pub fn save(&self, registry: &SerializeRegistry) -> bincode::Result<Vec<u8>> {
+        save_world(self, registry)
+    }
+
+    // Reads a world written by World::save, with the same entity ids.
+    #[cfg(feature = "serialize")]
+    pub fn load(bytes: &[u8], registry: &SerializeRegistry) -> Result<World, LoadError> {
+        load_world(bytes, registry)
+    }
+
+    // The changes the world went through after since, for the components of the registry.
+    // Call advance_tick once the diffs of the current tick are taken so later writes land in the next ones.
+    #[cfg(feature = "serialize")]
+    pub fn diff(&self, registry: &ReplicationRegistry, since: Tick) -> bincode::Result<WorldDiff> {
+        diff_world(self, registry, since)
+    }
+
+    // Same as diff, limited to what the observer sees, see InterestTask.
+    #[cfg(feature = "serialize")]
+    pub fn diff_visible(&self, registry: &ReplicationRegistry, since: Tick, view: &ObserverView) -> bincode::Result<WorldDiff> {
+        diff_visible(self, registry, since, view)
+    }
+
+    // Applies a diff of another world, entities maps its entities to the ones of this world.
+    #[cfg(feature = "serialize")]
+    pub fn apply_diff(&mut self, diff: &WorldDiff, registry: &ReplicationRegistry, entities: &mut EntityMap) -> Result<(), ReplicationError> {
+        apply_diff(self, diff, registry, entities)
+    }
+
+    // Replaces the entity allocation state, every allocated entity is added without components.
+    #[cfg(feature = "serialize")]
+    pub(crate) fn restore_entities(&mut self, entities: EntityManager) {
+        for entity_id in entities.allocated() {
+            self.archetypes.add_entity(entity_id);
+        }
+        self.entities = entities;
+    }
+
+    pub fn archetypes(&self) -> &ArchetypeManager {
+        &self.archetypes
+    }
+
+    pub fn resources(&self) -> &ResManager {
+        &self.resources
+    }
+
+    pub fn entity_manager(&self) -> &EntityManager {
+        &self.entities
     }
-}
\ No newline at end of file
+
+    pub fn managers(&mut self) -> (&mut ArchetypeManager, &mut ResManager, &mut EntityManager) {
+        (&mut self.archetypes, &mut self.resources, &mut self.entities)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::cow_macros::{Component, Resource};
+    use crate::world::{ForkError, World};
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Pos(i32);
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    #[component(storage = "sparse")]
+    struct Marker(i32);
+
+    #[derive(Component)]
+    struct Handle;
+
+    #[derive(Resource, Clone, Debug, PartialEq)]
+    struct Turn(u32);
+
+    #[derive(Resource)]
+    struct Connection;
+
+    fn turn(world: &World) -> u32 {
+        world.resources().query::<Turn>().unwrap().resource().read().unwrap().0
+    }
+
+    #[test]
+    fn fork_is_isolated_from_the_world() {
+        let mut world = World::new();
+        world.set_res(Turn(1));
+        let entity = world.create();
+        world.add(entity, Pos(1));
+        world.add(entity, Marker(1));
+
+        let mut fork = world.fork().unwrap();
+        fork.get_mut::<Pos>(entity).unwrap().0 = 2;
+        fork.remove::<Marker>(entity);
+        fork.resources().query::<Turn>().unwrap().resource().write().unwrap().0 = 2;
+        let spawned = fork.create();
+        fork.add(spawned, Pos(3));
+
+        assert_eq!(world.query::<Pos>(entity), Some(&Pos(1)));
+        assert_eq!(world.query::<Marker>(entity), Some(&Marker(1)));
+        assert_eq!(turn(&world), 1);
+        assert!(!world.is_alive(spawned));
+
+        world.get_mut::<Pos>(entity).unwrap().0 = 4;
+        assert_eq!(fork.query::<Pos>(entity), Some(&Pos(2)));
+        assert_eq!(fork.query::<Pos>(spawned), Some(&Pos(3)));
+        assert_eq!(turn(&fork), 2);
+    }
+
+    #[test]
+    fn rollback_restores_the_saved_fork() {
+        let mut world = World::new();
+        world.set_res(Turn(1));
+        let entity = world.create();
+        world.add(entity, Pos(1));
+        let saved = world.fork().unwrap();
+
+        for _ in 0..2 {
+            world.get_mut::<Pos>(entity).unwrap().0 += 10;
+            world.resources().query::<Turn>().unwrap().resource().write().unwrap().0 += 1;
+            world.release(entity);
+            world.rollback(&saved).unwrap();
+            assert_eq!(world.query::<Pos>(entity), Some(&Pos(1)));
+            assert_eq!(turn(&world), 1);
+        }
+    }
+
+    #[test]
+    fn fork_names_what_cant_be_cloned() {
+        let mut world = World::new();
+        let entity = world.create();
+        world.add(entity, Handle);
+        assert!(world.fork().is_ok(), "a zero-sized component without a drop is a tag");
+
+        world.set_res(Connection);
+        assert_eq!(world.fork().err(), Some(ForkError::Resource(std::any::type_name::<Connection>().to_string())));
+    }
+
+    #[test]
+    fn fork_fails_on_stored_components_that_cant_be_cloned() {
+        #[derive(Component)]
+        struct Socket(#[allow(dead_code)] Box<u32>);
+
+        let mut world = World::new();
+        let entity = world.create();
+        world.add(entity, Socket(Box::new(1)));
+        assert_eq!(world.fork().err(), Some(ForkError::Component(std::any::type_name::<Socket>().to_string())));
+
+        world.remove::<Socket>(entity);
+        assert!(world.fork().is_ok());
+    }
+
+    #[test]
+    fn get_mut_on_dead_or_missing_entities() {
+        let mut world = World::new();
+        let entity = world.create();
+        world.add(entity, Pos(1));
+        let bare = world.create();
+
+        assert!(world.get_mut::<Marker>(entity).is_none());
+        assert!(world.get_mut::<Pos>(bare).is_none());
+        assert!(world.get_mut::<Pos>(bare + 100).is_none());
+        world.release(entity);
+        assert!(world.get_mut::<Pos>(entity).is_none());
+        assert!(world.query::<Pos>(entity).is_none());
+    }
+
+    #[test]
+    fn compact_frees_the_empty_archetypes_and_keeps_the_entities() {
+        #[derive(Component, Debug, PartialEq)]
+        struct Vel(i32);
+
+        #[derive(Component)]
+        struct Temp;
+
+        let mut world = World::new();
+        let entities: Vec<u32> = (0..100).map(|_| world.create()).collect();
+        for entity_id in entities.iter().copied() {
+            world.add(entity_id, Pos(entity_id as i32));
+        }
+        // half the entities move on, the ones left keep the capacity of the hundred
+        for entity_id in entities.iter().copied().filter(|entity_id| entity_id % 2 == 0) {
+            world.add(entity_id, Vel(-(entity_id as i32)));
+            world.add(entity_id, Marker(1));
+            world.add(entity_id, Temp);
+            world.remove::<Temp>(entity_id);
+        }
+        world.add(entities[0], Temp);
+        world.remove::<Temp>(entities[0]);
+
+        let pos_id = world.archetypes().component_id::<Pos>().unwrap();
+        let pos_column = |world: &World| {
+            let archetype = world.archetypes().archetype_of(entities[0]).unwrap();
+            let column = archetype.column(pos_id).unwrap();
+            (column.len(), column.capacity())
+        };
+        let (len, capacity) = pos_column(&world);
+        assert!(len == 50 && capacity >= 100);
+        let archetypes = world.archetypes().archetypes().len();
+        let empty = world.archetypes().archetype_infos().skip(1).filter(|info| info.is_empty()).count();
+        assert_eq!(empty, 2);
+
+        assert_eq!(world.compact(), empty);
+        assert_eq!(world.archetypes().archetypes().len(), archetypes - empty);
+        assert!(world.archetypes().archetype_infos().skip(1).all(|info| !info.is_empty()));
+        assert_eq!(pos_column(&world), (50, 50));
+        // the archetypes are renumbered, each one is still found from its components and its entities
+        for info in world.archetypes().archetype_infos() {
+            assert_eq!(world.archetypes().archetype_id(info.index()), Some(info.id()));
+            for entity_id in info.entities() {
+                assert_eq!(world.archetypes().archetype_info_of(*entity_id).unwrap().id(), info.id());
+            }
+        }
+
+        for entity_id in entities.iter().copied() {
+            assert_eq!(world.query::<Pos>(entity_id), Some(&Pos(entity_id as i32)));
+            let moved = entity_id % 2 == 0;
+            assert_eq!(world.query::<Vel>(entity_id), moved.then_some(&Vel(-(entity_id as i32))));
+            assert_eq!(world.query::<Marker>(entity_id).is_some(), moved);
+            assert!(world.query::<Temp>(entity_id).is_none());
+        }
+        assert_eq!(world.comps::<Pos>().iter().count(), 100);
+        assert_eq!(world.compact(), 0);
+    }
+}